@@ -0,0 +1,531 @@
+use crate::{Expr, Number, Binary, Operator, With, Binding, Id, Meta};
+use crate::ast::{Error, Bool, If, Call, Fun, App, Type, Str, Empty, Record, Field, Get};
+use crate::calc::{unbound_identifier, unsupported};
+use crate::limits::{Budget, EvalLimits};
+use crate::reader::{Comment, Span, Trivia};
+use crate::unparse::to_source;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::rc::Rc;
+
+// calc_arena evaluates the given abstract syntax tree within the given limits,
+// like calc_with_limits, after moving it into a hash-consed Arena.
+//
+// Substitution in an arena shares the node a With binds between every use of
+// its identifier rather than copying it, so a replaced tree is a DAG and
+// replacing an identifier costs the same however large its bound expression
+// is. Evaluation computes each shared node once, so a program like
+//
+//   (with ([a 1]) (with ([b (+ a a)]) (with ([c (+ b b)]) ... )))
+//
+// takes time linear in its length, where calc takes exponential time. Only the
+// arithmetic language is lowered into an arena: any other expression becomes an
+// Error node, and binding annotations are dropped.
+pub fn calc_arena(ast: &Expr, limits: &EvalLimits) -> Result<i32, String> {
+    let mut budget: Budget = Budget::new(limits, ast);
+    let mut arena: Arena = Arena::hash_consed();
+    let root: Tree = arena.lower(ast);
+    let replaced: Tree = arena.replace(root, &mut budget)?;
+    arena.calc(replaced, &mut budget)
+}
+
+// heap_size returns the number of bytes allocated for the given boxed tree,
+// including its identifiers and comments.
+pub fn heap_size(ast: &Expr) -> usize {
+    let mut bytes: usize = 0;
+    let mut stack: Vec<&Expr> = vec![ast];
+    while let Some(expr) = stack.pop() {
+        match expr {
+            Expr::Number(expr) => bytes += size_of::<Number>() + meta_size(&expr.meta),
+            Expr::Binary(expr) => {
+                bytes += size_of::<Binary>() + meta_size(&expr.meta);
+                stack.push(&expr.left);
+                stack.push(&expr.right)
+            },
+            Expr::With(expr) => {
+                bytes += size_of::<With>() + meta_size(&expr.meta) + meta_size(&expr.binding.meta);
+                bytes += size_of::<Id>() + id_size(&expr.binding.identifier);
+                stack.push(&expr.binding.replace);
+                stack.push(&expr.input)
+            },
+            Expr::Id(expr) => bytes += size_of::<Id>() + id_size(expr),
+            Expr::Error(expr) => bytes += size_of::<Error>() + error_size(expr),
+            Expr::Bool(expr) => bytes += size_of::<Bool>() + meta_size(&expr.meta),
+            Expr::If(expr) => {
+                bytes += size_of::<If>() + meta_size(&expr.meta);
+                stack.extend(vec![&expr.condition, &expr.then, &expr.otherwise])
+            },
+            Expr::Call(expr) => {
+                bytes += size_of::<Call>() + meta_size(&expr.meta) + expr.args.capacity() * size_of::<Expr>();
+                stack.extend(expr.args.iter())
+            },
+            Expr::Fun(expr) => {
+                bytes += size_of::<Fun>() + meta_size(&expr.meta) + size_of::<Id>() + id_size(&expr.param);
+                bytes += expr.annotation.as_ref().map_or(0, |annotation| type_size(&annotation.ty));
+                stack.push(&expr.body)
+            },
+            Expr::App(expr) => {
+                bytes += size_of::<App>() + meta_size(&expr.meta);
+                stack.push(&expr.fun);
+                stack.push(&expr.arg)
+            },
+            Expr::Str(expr) => bytes += size_of::<Str>() + expr.val.capacity() + meta_size(&expr.meta),
+            Expr::Empty(expr) => bytes += size_of::<Empty>() + meta_size(&expr.meta),
+            Expr::Record(expr) => {
+                bytes += size_of::<Record>() + meta_size(&expr.meta) + expr.fields.capacity() * size_of::<Field>();
+                for field in &expr.fields {
+                    bytes += meta_size(&field.meta) + size_of::<Id>() + id_size(&field.name);
+                    stack.push(&field.value)
+                }
+            },
+            Expr::Get(expr) => {
+                bytes += size_of::<Get>() + meta_size(&expr.meta) + size_of::<Id>() + id_size(&expr.field);
+                stack.push(&expr.record)
+            },
+        }
+    }
+    bytes
+}
+
+// Symbol is an interned identifier. Two symbols from the same Interner are
+// equal exactly when the names they stand for are.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Symbol(u32);
+
+// Interner stores each distinct identifier once and hands out a Symbol for it.
+#[derive(Default)]
+pub struct Interner {
+    names:   Vec<String>,
+    symbols: HashMap<String, Symbol>,
+}
+
+impl Interner {
+    // intern returns the symbol for the given name, adding it if it is new.
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(name) {
+            return *symbol
+        }
+        let symbol: Symbol = Symbol(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.symbols.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    // name returns the identifier the given symbol stands for.
+    pub fn name(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0 as usize]
+    }
+
+    fn heap_size(&self) -> usize {
+        let names: usize = self.names.iter().map(String::capacity).sum::<usize>();
+        self.names.capacity() * size_of::<String>() + 2 * names
+            + self.symbols.capacity() * (size_of::<String>() + size_of::<Symbol>())
+    }
+}
+
+// NodeId is the index of a node in an Arena.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct NodeId(u32);
+
+// Node is a node of an Arena, which refers to its inputs by index. Nodes are
+// never changed once allocated, so any number of nodes can share an input.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Node {
+    Number(i32),
+    Binary(Operator, NodeId, NodeId),
+    // With holds the bound identifier, the bound expression and the input.
+    With(Symbol, NodeId, NodeId),
+    Id(Symbol),
+    // Error holds the index of an Error node in the arena's list of them.
+    Error(u32),
+}
+
+// SiteId is the index of a Site in an Arena.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SiteId(u32);
+
+// Site is an occurrence of a node in a tree: the span it was read from and the
+// sites of its inputs, if it has any. A node of a hash-consed arena is shared
+// by all of its occurrences, so spans are kept with the sites instead.
+#[derive(Copy, Clone)]
+struct Site {
+    span:   Span,
+    inputs: Option<(SiteId, SiteId)>,
+}
+
+// Tree is a tree held in an Arena: the index of its root node and the site of
+// the root.
+#[derive(Copy, Clone, Debug)]
+pub struct Tree {
+    pub node: NodeId,
+    pub site: SiteId,
+}
+
+// Arena holds the nodes of any number of trees in a single vector, and the
+// sites they occur at in another. Comments are not kept.
+#[derive(Default)]
+pub struct Arena {
+    nodes:    Vec<Node>,
+    sites:    Vec<Site>,
+    interner: Interner,
+    errors:   Vec<Error>,
+    // shared maps every node of a hash-consed arena to its index.
+    shared:   Option<HashMap<Node, NodeId>>,
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena::default()
+    }
+
+    // hash_consed returns an arena in which structurally identical subtrees
+    // are a single node. Each occurrence of a shared node keeps its own site.
+    pub fn hash_consed() -> Arena {
+        Arena{ shared: Some(HashMap::new()), ..Arena::default() }
+    }
+
+    // alloc adds the given node and returns its index. In a hash-consed arena
+    // an identical node is reused instead.
+    pub fn alloc(&mut self, node: Node) -> NodeId {
+        if let Some(id) = self.shared.as_ref().and_then(|shared| shared.get(&node)) {
+            return *id
+        }
+        let id: NodeId = NodeId(self.nodes.len() as u32);
+        self.nodes.push(node);
+        if let Some(shared) = &mut self.shared {
+            shared.insert(node, id);
+        }
+        id
+    }
+
+    // occur adds a site read from the given span, with the given sites of its
+    // inputs, and returns its index.
+    fn occur(&mut self, span: Span, inputs: Option<(SiteId, SiteId)>) -> SiteId {
+        self.sites.push(Site{ span, inputs });
+        SiteId(self.sites.len() as u32 - 1)
+    }
+
+    pub fn node(&self, id: NodeId) -> Node {
+        self.nodes[id.0 as usize]
+    }
+
+    pub fn span(&self, site: SiteId) -> Span {
+        self.sites[site.0 as usize].span
+    }
+
+    // inputs returns the sites of the inputs of the node at the given site.
+    fn inputs(&self, site: SiteId) -> (SiteId, SiteId) {
+        self.sites[site.0 as usize].inputs.expect("only nodes with inputs are asked for them")
+    }
+
+    pub fn name(&self, symbol: Symbol) -> &str {
+        self.interner.name(symbol)
+    }
+
+    // len returns the number of nodes in this arena.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // heap_size returns the number of bytes allocated for this arena.
+    pub fn heap_size(&self) -> usize {
+        let shared: usize = self.shared.as_ref().map_or(0, HashMap::capacity);
+        self.nodes.capacity() * size_of::<Node>() + self.sites.capacity() * size_of::<Site>()
+            + shared * (size_of::<Node>() + size_of::<NodeId>()) + self.interner.heap_size()
+            + self.errors.capacity() * size_of::<Error>() + self.errors.iter().map(error_size).sum::<usize>()
+    }
+
+    // lower copies the given boxed tree into this arena and returns it. Inputs
+    // are allocated before the nodes that use them.
+    pub fn lower(&mut self, ast: &Expr) -> Tree {
+        enum Task<'a> {
+            Visit(&'a Expr),
+            // Binary and With pop their inputs, which were pushed in order, and
+            // push the node.
+            Binary(&'a Binary),
+            With(&'a With),
+        }
+        let mut tasks: Vec<Task> = vec![Task::Visit(ast)];
+        let mut trees: Vec<Tree> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(Expr::Number(expr)) => trees.push(self.leaf(Node::Number(expr.val), expr.meta.span)),
+                Task::Visit(Expr::Id(expr)) => {
+                    let symbol: Symbol = self.interner.intern(&expr.val);
+                    trees.push(self.leaf(Node::Id(symbol), expr.meta.span))
+                },
+                Task::Visit(Expr::Error(expr)) => {
+                    self.errors.push(Error::clone(expr));
+                    let index: u32 = self.errors.len() as u32 - 1;
+                    trees.push(self.leaf(Node::Error(index), expr.meta.span))
+                },
+                Task::Visit(expr @ Expr::Bool(_)) | Task::Visit(expr @ Expr::If(_)) | Task::Visit(expr @ Expr::Call(_))
+                | Task::Visit(expr @ Expr::Fun(_)) | Task::Visit(expr @ Expr::App(_)) | Task::Visit(expr @ Expr::Str(_))
+                | Task::Visit(expr @ Expr::Empty(_)) | Task::Visit(expr @ Expr::Record(_)) | Task::Visit(expr @ Expr::Get(_)) => {
+                    let message: String = unsupported(expr, "the arena");
+                    self.errors.push(Error{ message, source: to_source(expr), meta: expr.meta().clone() });
+                    let index: u32 = self.errors.len() as u32 - 1;
+                    trees.push(self.leaf(Node::Error(index), expr.meta().span))
+                },
+                Task::Visit(Expr::Binary(expr)) => {
+                    tasks.push(Task::Binary(expr));
+                    tasks.push(Task::Visit(&expr.right));
+                    tasks.push(Task::Visit(&expr.left))
+                },
+                Task::Visit(Expr::With(expr)) => {
+                    tasks.push(Task::With(expr));
+                    tasks.push(Task::Visit(&expr.input));
+                    tasks.push(Task::Visit(&expr.binding.replace))
+                },
+                Task::Binary(expr) => {
+                    let right: Tree = trees.pop().unwrap();
+                    let left: Tree = trees.pop().unwrap();
+                    trees.push(self.branch(Node::Binary(expr.op, left.node, right.node), expr.meta.span, left, right))
+                },
+                Task::With(expr) => {
+                    let input: Tree = trees.pop().unwrap();
+                    let replace: Tree = trees.pop().unwrap();
+                    let symbol: Symbol = self.interner.intern(&expr.binding.identifier.val);
+                    trees.push(self.branch(Node::With(symbol, replace.node, input.node), expr.meta.span, replace, input))
+                },
+            }
+        }
+        trees.pop().unwrap()
+    }
+
+    // leaf adds a node without inputs, read from the given span.
+    fn leaf(&mut self, node: Node, span: Span) -> Tree {
+        Tree{ node: self.alloc(node), site: self.occur(span, None) }
+    }
+
+    // branch adds a node, read from the given span, whose inputs are the given
+    // trees.
+    fn branch(&mut self, node: Node, span: Span, first: Tree, second: Tree) -> Tree {
+        Tree{ node: self.alloc(node), site: self.occur(span, Some((first.site, second.site))) }
+    }
+
+    // to_expr copies the given tree out of this arena. A node shared by
+    // several others is copied once for each.
+    pub fn to_expr(&self, root: Tree) -> Expr {
+        enum Task {
+            Visit(Tree),
+            Build(Tree),
+        }
+        let mut tasks: Vec<Task> = vec![Task::Visit(root)];
+        let mut exprs: Vec<Expr> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(tree) => match self.node(tree.node) {
+                    Node::Number(val) => exprs.push(Number{ val, meta: self.meta(tree.site) }.into()),
+                    Node::Id(symbol) => exprs.push(self.id(symbol, tree.site).into()),
+                    Node::Error(index) => exprs.push(self.errors[index as usize].clone().into()),
+                    Node::Binary(_, first, second) | Node::With(_, first, second) => {
+                        let (first_site, second_site): (SiteId, SiteId) = self.inputs(tree.site);
+                        tasks.push(Task::Build(tree));
+                        tasks.push(Task::Visit(Tree{ node: second, site: second_site }));
+                        tasks.push(Task::Visit(Tree{ node: first, site: first_site }))
+                    },
+                },
+                Task::Build(tree) => {
+                    let second: Expr = exprs.pop().unwrap();
+                    let first: Expr = exprs.pop().unwrap();
+                    exprs.push(match self.node(tree.node) {
+                        Node::Binary(op, _, _) => Binary{ op, left: first, right: second, meta: self.meta(tree.site) }.into(),
+                        Node::With(symbol, _, _) => {
+                            let identifier: Box<Id> = Box::new(self.id(symbol, tree.site));
+                            let binding: Binding = Binding{ identifier, annotation: None, replace: first, meta: Meta::default() };
+                            With{ binding, input: second, meta: self.meta(tree.site) }.into()
+                        },
+                        _ => unreachable!("only nodes with inputs are built"),
+                    })
+                },
+            }
+        }
+        exprs.pop().unwrap()
+    }
+
+    // replace carries out every With in the given tree and returns the
+    // result, charging the work to the given budget. Each use of a bound
+    // identifier becomes its replaced bound expression, at the sites that
+    // expression was read from, and nodes and sites that substitution does not
+    // change are reused.
+    pub(crate) fn replace(&mut self, root: Tree, budget: &mut Budget) -> Result<Tree, String> {
+        enum Task {
+            Replace(Tree, Scope, usize),
+            // Binary pops the replaced inputs of the node and pushes its
+            // replacement.
+            Binary(Tree),
+            // Bind pops the replaced bound expression of a With and replaces
+            // its input with the identifier bound to it.
+            Bind(Symbol, Tree, Scope, usize),
+        }
+        let mut tasks: Vec<Task> = vec![Task::Replace(root, None, 0)];
+        let mut trees: Vec<Tree> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Replace(tree, scope, depth) => {
+                    budget.visit(depth)?;
+                    match self.node(tree.node) {
+                        Node::Number(_) | Node::Error(_) => trees.push(tree),
+                        Node::Id(symbol) => match lookup(&scope, symbol) {
+                            Some(value) => {
+                                budget.step()?;
+                                trees.push(value)
+                            },
+                            None => trees.push(tree),
+                        },
+                        Node::Binary(_, left, right) => {
+                            let (left_site, right_site): (SiteId, SiteId) = self.inputs(tree.site);
+                            tasks.push(Task::Binary(tree));
+                            tasks.push(Task::Replace(Tree{ node: right, site: right_site }, scope.clone(), depth + 1));
+                            tasks.push(Task::Replace(Tree{ node: left, site: left_site }, scope, depth + 1))
+                        },
+                        Node::With(symbol, replace, input) => {
+                            let (replace_site, input_site): (SiteId, SiteId) = self.inputs(tree.site);
+                            tasks.push(Task::Bind(symbol, Tree{ node: input, site: input_site }, scope.clone(), depth));
+                            tasks.push(Task::Replace(Tree{ node: replace, site: replace_site }, scope, depth + 1))
+                        },
+                    }
+                },
+                Task::Binary(tree) => {
+                    let right: Tree = trees.pop().unwrap();
+                    let left: Tree = trees.pop().unwrap();
+                    let node: Node = match self.node(tree.node) {
+                        Node::Binary(op, _, _) => Node::Binary(op, left.node, right.node),
+                        _ => unreachable!("Binary tasks are only pushed for Binary nodes"),
+                    };
+                    if node == self.node(tree.node) && (left.site, right.site) == self.inputs(tree.site) {
+                        trees.push(tree)
+                    } else {
+                        let span: Span = self.span(tree.site);
+                        trees.push(self.branch(node, span, left, right))
+                    }
+                },
+                Task::Bind(symbol, input, scope, depth) => {
+                    let value: Tree = trees.pop().unwrap();
+                    let scope: Scope = Some(Rc::new(Binder{ symbol, value, parent: scope }));
+                    tasks.push(Task::Replace(input, scope, depth + 1))
+                },
+            }
+        }
+        Ok(trees.pop().unwrap())
+    }
+
+    // calc evaluates the given tree, which replace has already been run on,
+    // charging the work to the given budget. The value of each node is
+    // remembered, so a node shared by several others is only evaluated once.
+    // An error is reported at the site where evaluation fails.
+    pub(crate) fn calc(&self, root: Tree, budget: &mut Budget) -> Result<i32, String> {
+        enum Task {
+            Eval(Tree, usize),
+            // Apply pops the values of both inputs and pushes the result,
+            // which is the value of the given node.
+            Apply(Operator, NodeId),
+        }
+        let mut memo: Vec<Option<i32>> = vec![None; self.nodes.len()];
+        let mut tasks: Vec<Task> = vec![Task::Eval(root, 0)];
+        let mut values: Vec<i32> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Eval(tree, depth) => {
+                    if let Some(val) = memo[tree.node.0 as usize] {
+                        values.push(val);
+                        continue
+                    }
+                    budget.visit(depth)?;
+                    match self.node(tree.node) {
+                        Node::Number(val) => values.push(val),
+                        Node::Binary(op, left, right) => {
+                            let (left_site, right_site): (SiteId, SiteId) = self.inputs(tree.site);
+                            tasks.push(Task::Apply(op, tree.node));
+                            tasks.push(Task::Eval(Tree{ node: right, site: right_site }, depth + 1));
+                            tasks.push(Task::Eval(Tree{ node: left, site: left_site }, depth + 1))
+                        },
+                        // A With that has been replaced evaluates to its input.
+                        Node::With(_, _, input) => {
+                            let (_, input_site): (SiteId, SiteId) = self.inputs(tree.site);
+                            tasks.push(Task::Eval(Tree{ node: input, site: input_site }, depth + 1))
+                        },
+                        Node::Id(symbol) => return Err(unbound_identifier(&self.id(symbol, tree.site))),
+                        Node::Error(index) => return Err(self.errors[index as usize].message.clone()),
+                    }
+                },
+                Task::Apply(op, id) => {
+                    let right: i32 = values.pop().unwrap();
+                    let left: i32 = values.pop().unwrap();
+                    budget.step()?;
+                    let val: i32 = op.apply(left, right)?;
+                    memo[id.0 as usize] = Some(val);
+                    values.push(val)
+                },
+            }
+        }
+        Ok(values.pop().unwrap())
+    }
+
+    fn meta(&self, site: SiteId) -> Meta {
+        Meta{ span: self.span(site), trivia: Trivia::default() }
+    }
+
+    fn id(&self, symbol: Symbol, site: SiteId) -> Id {
+        Id{ val: self.name(symbol).to_string(), meta: self.meta(site) }
+    }
+}
+
+// Scope is the chain of With bindings in effect during replace, innermost
+// first.
+type Scope = Option<Rc<Binder>>;
+
+// Binder maps an identifier to its replaced bound expression.
+struct Binder {
+    symbol: Symbol,
+    value:  Tree,
+    parent: Scope,
+}
+
+// lookup returns the tree bound to the given symbol in the given scope.
+fn lookup(scope: &Scope, symbol: Symbol) -> Option<Tree> {
+    let mut current: &Scope = scope;
+    while let Some(binder) = current {
+        if binder.symbol == symbol {
+            return Some(binder.value)
+        }
+        current = &binder.parent;
+    }
+    None
+}
+
+// meta_size returns the number of bytes allocated for the comments in the
+// given metadata.
+fn meta_size(meta: &Meta) -> usize {
+    let trivia: &Trivia = &meta.trivia;
+    let comments = trivia.leading.iter().chain(&trivia.trailing).chain(&trivia.inner);
+    (trivia.leading.capacity() + trivia.trailing.capacity() + trivia.inner.capacity()) * size_of::<Comment>()
+        + comments.map(|comment| comment.text.capacity()).sum::<usize>()
+}
+
+// error_size returns the number of bytes allocated for the given Error node,
+// other than the Error itself.
+fn error_size(error: &Error) -> usize {
+    error.message.capacity() + error.source.capacity() + meta_size(&error.meta)
+}
+
+// type_size returns the number of bytes allocated for the given type, other
+// than the Type itself.
+fn type_size(annotation: &Type) -> usize {
+    match annotation {
+        Type::Fun(from, to) => 2 * size_of::<Type>() + type_size(from) + type_size(to),
+        Type::List(item) => size_of::<Type>() + type_size(item),
+        Type::Record(fields) => {
+            let entries: usize = fields.capacity() * size_of::<(String, Type)>();
+            entries + fields.iter().map(|(name, ty)| name.capacity() + type_size(ty)).sum::<usize>()
+        },
+        _ => 0,
+    }
+}
+
+// id_size returns the number of bytes allocated for the given identifier,
+// other than the Id itself.
+fn id_size(id: &Id) -> usize {
+    id.val.capacity() + meta_size(&id.meta)
+}
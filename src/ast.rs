@@ -1,70 +1,534 @@
 // ============================================================================
 // GRAMMAR:
-// WAE = Number
-//     | (+ WAE WAE)
-//     | (- WAE WAE)
-//     | (* WAE WAE)
-//     | (/ WAE WAE)
-//     | (With ([x WAE]) WAE)
-//     | x
+// WAE  = Number
+//      | true
+//      | false
+//      | String
+//      | empty
+//      | (+ WAE WAE)
+//      | (- WAE WAE)
+//      | (* WAE WAE)
+//      | (/ WAE WAE)
+//      | (< WAE WAE)
+//      | (= WAE WAE)
+//      | (> WAE WAE)
+//      | (string-append WAE WAE)
+//      | (string-length WAE)
+//      | (substring WAE WAE WAE)
+//      | (number->string WAE)
+//      | (string->number WAE)
+//      | (cons WAE WAE)
+//      | (first WAE)
+//      | (rest WAE)
+//      | (empty? WAE)
+//      | (list WAE ...)
+//      | (record [x WAE] ...)
+//      | (get WAE x)
+//      | (if WAE WAE WAE)
+//      | (With ([x WAE]) WAE)
+//      | (With ([x : Type WAE]) WAE)
+//      | (fun (x) WAE)
+//      | (fun ([x : Type]) WAE)
+//      | (WAE WAE)
+//      | x
+// Type = number
+//      | boolean
+//      | string
+//      | (listof Type)
+//      | (record [x : Type] ...)
+//      | (Type -> Type)
 // ============================================================================
 
+use crate::reader::{Datum, Span, Trivia};
+use std::hash::{Hash, Hasher};
+
 // Expr is a node in an abstract syntax tree that represents an expression from
 // the above grammar.
-#[derive(Clone)]
+//
+// Trees can be far deeper than the call stack, so Expr implements Clone and
+// Drop with an explicit work stack instead of deriving them. Because of the
+// Drop implementation, a node cannot be moved out of an Expr; use take.
+#[derive(PartialEq, Eq, Hash)]
 pub enum Expr {
     Number(Box<Number>),
     Binary(Box<Binary>),
     With(Box<With>),
     Id(Box<Id>),
+    Error(Box<Error>),
+    Bool(Box<Bool>),
+    If(Box<If>),
+    Call(Box<Call>),
+    Fun(Box<Fun>),
+    App(Box<App>),
+    Str(Box<Str>),
+    Empty(Box<Empty>),
+    Record(Box<Record>),
+    Get(Box<Get>),
 }
 
 macro_rules! into_expr {
     ($id:ident) => {
-        impl Into<Expr> for $id {
-            fn into(self) -> Expr {
-                Expr::$id(Box::new(self))
+        impl From<$id> for Expr {
+            fn from(expr: $id) -> Expr {
+                Expr::$id(Box::new(expr))
             }
         }
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Number {
-    pub(crate) val: i32,
+    pub(crate) val:  i32,
+    pub(crate) meta: Meta,
 }
 
 into_expr!(Number);
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Binary {
     pub(crate) op:    Operator,
     pub(crate) left:  Expr,
     pub(crate) right: Expr,
+    pub(crate) meta:  Meta,
 }
 
 into_expr!(Binary);
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Operator { Add, Sub, Mul, Div }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct With {
     pub(crate) binding: Binding,
     pub(crate) input:   Expr,
+    pub(crate) meta:    Meta,
 }
 
 into_expr!(With);
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Binding {
     pub(crate) identifier: Box<Id>,
+    // annotation is the type the bound expression is declared to have, if
+    // any. Only the type checker reads it.
+    pub(crate) annotation: Option<Annotation>,
     pub(crate) replace:    Expr,
+    pub(crate) meta:       Meta,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Id {
-    pub(crate) val: String,
+    pub(crate) val:  String,
+    pub(crate) meta: Meta,
 }
 
 into_expr!(Id);
+
+// Error stands in for a datum that could not be parsed, so that a parser that
+// recovers from errors can still return a tree. Evaluating it fails with the
+// parse error.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Error {
+    pub(crate) message: String,
+    // source is the text of the datum, exactly as written.
+    pub(crate) source:  String,
+    pub(crate) meta:    Meta,
+}
+
+into_expr!(Error);
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Bool {
+    pub(crate) val:  bool,
+    pub(crate) meta: Meta,
+}
+
+into_expr!(Bool);
+
+// If evaluates its condition, which must be a boolean, and then only the
+// branch it selects.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct If {
+    pub(crate) condition: Expr,
+    pub(crate) then:      Expr,
+    pub(crate) otherwise: Expr,
+    pub(crate) meta:      Meta,
+}
+
+into_expr!(If);
+
+// Call applies a primitive to its arguments, which are evaluated left to
+// right.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Call {
+    pub(crate) prim: Primitive,
+    pub(crate) args: Vec<Expr>,
+    pub(crate) meta: Meta,
+}
+
+into_expr!(Call);
+
+// Primitive is an operation built into the language that is not one of the
+// arithmetic Operators.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Primitive {
+    Less, Equal, Greater,
+    StringAppend, StringLength, Substring, NumberToString, StringToNumber,
+    Cons, First, Rest, IsEmpty, List,
+}
+
+// Fun is a function of one parameter. Applying it substitutes the value of
+// the argument for the parameter in the body.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Fun {
+    pub(crate) param:      Box<Id>,
+    // annotation is the declared type of the parameter, if any.
+    pub(crate) annotation: Option<Annotation>,
+    pub(crate) body:       Expr,
+    pub(crate) meta:       Meta,
+}
+
+into_expr!(Fun);
+
+// App applies a function to an argument. The function is evaluated first.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct App {
+    pub(crate) fun:  Expr,
+    pub(crate) arg:  Expr,
+    pub(crate) meta: Meta,
+}
+
+into_expr!(App);
+
+// Str is a string literal. Its value has had the escape sequences in the
+// literal replaced.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Str {
+    pub(crate) val:  String,
+    pub(crate) meta: Meta,
+}
+
+into_expr!(Str);
+
+// Empty is the empty list. A non-empty list is built with cons or list, whose
+// last input is the rest of the list.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Empty {
+    pub(crate) meta: Meta,
+}
+
+into_expr!(Empty);
+
+// Record builds a record from its fields, whose values are evaluated in the
+// order they are written. Field names are not identifiers in scope, so
+// substitution leaves them alone.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Record {
+    pub(crate) fields: Vec<Field>,
+    pub(crate) meta:   Meta,
+}
+
+into_expr!(Record);
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Field {
+    pub(crate) name:  Box<Id>,
+    pub(crate) value: Expr,
+    pub(crate) meta:  Meta,
+}
+
+// Get reads the named field of a record.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Get {
+    pub(crate) record: Expr,
+    pub(crate) field:  Box<Id>,
+    pub(crate) meta:   Meta,
+}
+
+into_expr!(Get);
+
+// MAX_TYPE_DEPTH is how deeply a type annotation may be nested. Types are
+// small, and are still walked by recursion.
+pub(crate) const MAX_TYPE_DEPTH: usize = 100;
+
+// Type is the type of an expression, as written in an annotation.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Number,
+    Boolean,
+    String,
+    // Fun is the type of a function from its first type to its second.
+    Fun(Box<Type>, Box<Type>),
+    // List is the type of a list whose elements all have the given type.
+    List(Box<Type>),
+    // Record is the type of a record with the given fields, in order.
+    Record(Vec<(String, Type)>),
+}
+
+// Annotation is a type written in the source, along with the comments inside
+// it.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Annotation {
+    pub(crate) ty:   Type,
+    pub(crate) meta: TypeMeta,
+}
+
+impl From<Type> for Annotation {
+    fn from(ty: Type) -> Annotation {
+        Annotation{ ty, meta: TypeMeta::default() }
+    }
+}
+
+// TypeMeta holds the comments attached to a type and to each of its inputs, in
+// the order they are written: the two types of a Fun, the element type of a
+// List, or the fields of a Record, where each field has its name and its type
+// as inputs. Like Meta, it never affects equality or hashing.
+#[derive(Clone, Default)]
+pub struct TypeMeta {
+    pub(crate) trivia: Trivia,
+    pub(crate) inputs: Vec<TypeMeta>,
+}
+
+// NO_TYPE_META is the metadata of a type that has no comments.
+static NO_TYPE_META: TypeMeta = TypeMeta{
+    trivia: Trivia{ leading: Vec::new(), trailing: Vec::new(), inner: Vec::new() },
+    inputs: Vec::new(),
+};
+
+impl TypeMeta {
+    // input returns the metadata of the input at the given index, which is
+    // empty if the type was not parsed.
+    pub(crate) fn input(&self, index: usize) -> &TypeMeta {
+        self.inputs.get(index).unwrap_or(&NO_TYPE_META)
+    }
+}
+
+impl PartialEq for TypeMeta {
+    fn eq(&self, _: &TypeMeta) -> bool {
+        true
+    }
+}
+
+impl Eq for TypeMeta {}
+
+impl Hash for TypeMeta {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+impl Expr {
+    // meta returns the metadata of the node at the root of this expression.
+    pub(crate) fn meta(&self) -> &Meta {
+        match self {
+            Expr::Number(expr) => &expr.meta,
+            Expr::Binary(expr) => &expr.meta,
+            Expr::With(expr) => &expr.meta,
+            Expr::Id(expr) => &expr.meta,
+            Expr::Error(expr) => &expr.meta,
+            Expr::Bool(expr) => &expr.meta,
+            Expr::If(expr) => &expr.meta,
+            Expr::Call(expr) => &expr.meta,
+            Expr::Fun(expr) => &expr.meta,
+            Expr::App(expr) => &expr.meta,
+            Expr::Str(expr) => &expr.meta,
+            Expr::Empty(expr) => &expr.meta,
+            Expr::Record(expr) => &expr.meta,
+            Expr::Get(expr) => &expr.meta,
+        }
+    }
+
+    // take moves this expression out and leaves a placeholder behind.
+    pub(crate) fn take(&mut self) -> Expr {
+        std::mem::replace(self, hole())
+    }
+
+    // meta_mut returns the metadata of the node at the root of this expression.
+    pub(crate) fn meta_mut(&mut self) -> &mut Meta {
+        match self {
+            Expr::Number(expr) => &mut expr.meta,
+            Expr::Binary(expr) => &mut expr.meta,
+            Expr::With(expr) => &mut expr.meta,
+            Expr::Id(expr) => &mut expr.meta,
+            Expr::Error(expr) => &mut expr.meta,
+            Expr::Bool(expr) => &mut expr.meta,
+            Expr::If(expr) => &mut expr.meta,
+            Expr::Call(expr) => &mut expr.meta,
+            Expr::Fun(expr) => &mut expr.meta,
+            Expr::App(expr) => &mut expr.meta,
+            Expr::Str(expr) => &mut expr.meta,
+            Expr::Empty(expr) => &mut expr.meta,
+            Expr::Record(expr) => &mut expr.meta,
+            Expr::Get(expr) => &mut expr.meta,
+        }
+    }
+
+    // inputs returns the inputs of the node at the root of this expression,
+    // in source order.
+    pub(crate) fn inputs(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Number(_) | Expr::Id(_) | Expr::Error(_) | Expr::Bool(_) | Expr::Str(_) | Expr::Empty(_) => Vec::new(),
+            Expr::Binary(expr) => vec![&expr.left, &expr.right],
+            Expr::With(expr) => vec![&expr.binding.replace, &expr.input],
+            Expr::If(expr) => vec![&expr.condition, &expr.then, &expr.otherwise],
+            Expr::Call(expr) => expr.args.iter().collect(),
+            Expr::Fun(expr) => vec![&expr.body],
+            Expr::App(expr) => vec![&expr.fun, &expr.arg],
+            Expr::Record(expr) => expr.fields.iter().map(|field| &field.value).collect(),
+            Expr::Get(expr) => vec![&expr.record],
+        }
+    }
+
+    // inputs_mut returns the inputs of the node at the root of this
+    // expression, in source order.
+    pub(crate) fn inputs_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Number(_) | Expr::Id(_) | Expr::Error(_) | Expr::Bool(_) | Expr::Str(_) | Expr::Empty(_) => Vec::new(),
+            Expr::Binary(expr) => vec![&mut expr.left, &mut expr.right],
+            Expr::With(expr) => vec![&mut expr.binding.replace, &mut expr.input],
+            Expr::If(expr) => vec![&mut expr.condition, &mut expr.then, &mut expr.otherwise],
+            Expr::Call(expr) => expr.args.iter_mut().collect(),
+            Expr::Fun(expr) => vec![&mut expr.body],
+            Expr::App(expr) => vec![&mut expr.fun, &mut expr.arg],
+            Expr::Record(expr) => expr.fields.iter_mut().map(|field| &mut field.value).collect(),
+            Expr::Get(expr) => vec![&mut expr.record],
+        }
+    }
+
+    // is_leaf returns true if the node at the root of this expression has no
+    // inputs.
+    pub(crate) fn is_leaf(&self) -> bool {
+        matches!(self, Expr::Number(_) | Expr::Id(_) | Expr::Error(_) | Expr::Bool(_) | Expr::Str(_) | Expr::Empty(_))
+    }
+
+    // clone_node returns a copy of the node at the root of this expression
+    // whose inputs are placeholders.
+    fn clone_node(&self) -> Expr {
+        match self {
+            Expr::Number(expr) => Expr::Number(expr.clone()),
+            Expr::Id(expr) => Expr::Id(expr.clone()),
+            Expr::Error(expr) => Expr::Error(expr.clone()),
+            Expr::Bool(expr) => Expr::Bool(expr.clone()),
+            Expr::Str(expr) => Expr::Str(expr.clone()),
+            Expr::Empty(expr) => Expr::Empty(expr.clone()),
+            Expr::Binary(expr) => Binary{ op: expr.op, left: hole(), right: hole(), meta: expr.meta.clone() }.into(),
+            Expr::With(expr) => {
+                let binding: Binding = Binding{
+                    identifier: expr.binding.identifier.clone(),
+                    annotation: expr.binding.annotation.clone(),
+                    replace: hole(),
+                    meta: expr.binding.meta.clone(),
+                };
+                With{ binding, input: hole(), meta: expr.meta.clone() }.into()
+            },
+            Expr::If(expr) => If{ condition: hole(), then: hole(), otherwise: hole(), meta: expr.meta.clone() }.into(),
+            Expr::Call(expr) => Call{ prim: expr.prim, args: expr.args.iter().map(|_| hole()).collect(), meta: expr.meta.clone() }.into(),
+            Expr::Fun(expr) => {
+                let param: Box<Id> = expr.param.clone();
+                Fun{ param, annotation: expr.annotation.clone(), body: hole(), meta: expr.meta.clone() }.into()
+            },
+            Expr::App(expr) => App{ fun: hole(), arg: hole(), meta: expr.meta.clone() }.into(),
+            Expr::Record(expr) => {
+                let fields: Vec<Field> = expr.fields.iter()
+                    .map(|field| Field{ name: field.name.clone(), value: hole(), meta: field.meta.clone() })
+                    .collect();
+                Record{ fields, meta: expr.meta.clone() }.into()
+            },
+            Expr::Get(expr) => Get{ record: hole(), field: expr.field.clone(), meta: expr.meta.clone() }.into(),
+        }
+    }
+
+    // fill replaces the inputs of the node at the root of this expression with
+    // the given ones, in source order.
+    pub(crate) fn fill(&mut self, inputs: impl IntoIterator<Item = Expr>) {
+        for (slot, input) in self.inputs_mut().into_iter().zip(inputs) {
+            *slot = input;
+        }
+    }
+}
+
+// hole returns a placeholder for an input that is yet to be filled in.
+pub(crate) fn hole() -> Expr {
+    Number{ val: 0, meta: Meta::default() }.into()
+}
+
+impl Clone for Expr {
+    fn clone(&self) -> Expr {
+        enum Task<'a> {
+            Visit(&'a Expr),
+            // Build pops the copies of the inputs of a node, which are on top
+            // of the stack of copies in order, and pushes a copy of the node.
+            Build(&'a Expr),
+        }
+        let mut tasks: Vec<Task> = vec![Task::Visit(self)];
+        let mut copies: Vec<Expr> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(expr) if expr.is_leaf() => copies.push(expr.clone_node()),
+                Task::Visit(expr) => {
+                    tasks.push(Task::Build(expr));
+                    tasks.extend(expr.inputs().into_iter().rev().map(Task::Visit))
+                },
+                Task::Build(expr) => {
+                    let mut copy: Expr = expr.clone_node();
+                    let start: usize = copies.len() - expr.inputs().len();
+                    copy.fill(copies.drain(start..));
+                    copies.push(copy)
+                },
+            }
+        }
+        copies.pop().unwrap()
+    }
+}
+
+impl Drop for Expr {
+    // drop detaches the inner nodes below this one onto a stack, so that every
+    // node is dropped with nothing but leaves below it.
+    fn drop(&mut self) {
+        let mut stack: Vec<Expr> = Vec::new();
+        detach_children(self, &mut stack);
+        while let Some(mut expr) = stack.pop() {
+            detach_children(&mut expr, &mut stack);
+        }
+    }
+}
+
+// detach_children moves the inputs of the given node that have inputs of their
+// own onto the given stack.
+fn detach_children(expr: &mut Expr, stack: &mut Vec<Expr>) {
+    if expr.is_leaf() {
+        return
+    }
+    for child in expr.inputs_mut() {
+        if !child.is_leaf() {
+            stack.push(child.take())
+        }
+    }
+}
+
+// Meta holds information about where a node came from in the source text: its
+// span and the comments attached to it. It never affects equality or hashing,
+// so trees that differ only in layout (or that were built by a pass rather than
+// parsed) compare structurally equal.
+#[derive(Clone, Default)]
+pub struct Meta {
+    pub(crate) span:   Span,
+    pub(crate) trivia: Trivia,
+}
+
+impl Meta {
+    // of returns the metadata of the node read from the given datum.
+    pub(crate) fn of(datum: &Datum) -> Meta {
+        Meta{ span: datum.span, trivia: datum.trivia.clone() }
+    }
+}
+
+impl PartialEq for Meta {
+    fn eq(&self, _: &Meta) -> bool {
+        true
+    }
+}
+
+impl Eq for Meta {}
+
+impl Hash for Meta {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
@@ -0,0 +1,204 @@
+use crate::{Expr, Number, Binary, Operator, With, Id};
+use crate::ast::Error;
+use crate::calc::unsupported;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+// Instruction is a single operation of the stack virtual machine in vm.rs.
+//
+// Every With binding is assigned its own local slot. Substitution evaluates a
+// bound expression where its identifier is first reached (and never, if the
+// identifier is unused), and since the language has no branches that point is
+// known at compile time. The compiler therefore emits the code for a binding
+// immediately before its first use, followed by a Bind into its slot. Later
+// uses simply Load the slot, so no Unbind is needed at the end of the scope.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    // Const pushes the given value.
+    Const(i32),
+    // Load pushes the value held in the given local slot.
+    Load(usize),
+    // Bind pops a value and stores it in the given local slot.
+    Bind(usize),
+    // Add, Sub, Mul and Div pop the right and then the left operand, and push
+    // the result.
+    Add,
+    Sub,
+    Mul,
+    Div,
+    // Unbound fails with the error for the identifier at the given index in
+    // the chunk's name table.
+    Unbound(usize),
+    // Fail fails with the error at the given index in the chunk's error table.
+    Fail(usize),
+}
+
+// Chunk is a compiled expression: the instructions along with the tables
+// needed to run and disassemble them.
+pub struct Chunk {
+    pub(crate) code:   Vec<Instruction>,
+    // locals holds the identifier bound in each local slot.
+    pub(crate) locals: Vec<String>,
+    // names holds the identifiers referenced by Unbound instructions.
+    pub(crate) names:  Vec<Id>,
+    // errors holds the parse errors, and the errors for expressions outside
+    // the arithmetic language, reported by Fail instructions.
+    pub(crate) errors: Vec<String>,
+}
+
+// compile translates the given abstract syntax tree into bytecode that
+// evaluates to the same result as calc. Only the arithmetic language is
+// compiled: any other expression compiles to a Fail.
+pub fn compile(ast: &Expr) -> Chunk {
+    let mut compiler: Compiler = Compiler{
+        chunk: Chunk{ code: Vec::new(), locals: Vec::new(), names: Vec::new(), errors: Vec::new() },
+        scope: Vec::new(),
+        bindings: Vec::new(),
+    };
+    ast.compile(&mut compiler);
+    compiler.chunk
+}
+
+// disassemble returns a human-readable listing of the given chunk, one
+// instruction per line.
+pub fn disassemble(chunk: &Chunk) -> String {
+    let mut listing: String = String::new();
+    for (offset, instruction) in chunk.code.iter().enumerate() {
+        let comment: Option<&String> = match instruction {
+            Instruction::Load(slot) | Instruction::Bind(slot) => Some(&chunk.locals[*slot]),
+            Instruction::Unbound(name) => Some(&chunk.names[*name].val),
+            Instruction::Fail(error) => Some(&chunk.errors[*error]),
+            _ => None,
+        };
+        let line: String = match comment {
+            Some(name) => format!("{:04}  {:<12} ; {}", offset, instruction.to_string(), name),
+            None => format!("{:04}  {}", offset, instruction),
+        };
+        listing.push_str(line.trim_end());
+        listing.push('\n');
+    }
+    listing
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Const(val) => write!(f, "const {}", val),
+            Instruction::Load(slot) => write!(f, "load {}", slot),
+            Instruction::Bind(slot) => write!(f, "bind {}", slot),
+            Instruction::Add => write!(f, "add"),
+            Instruction::Sub => write!(f, "sub"),
+            Instruction::Mul => write!(f, "mul"),
+            Instruction::Div => write!(f, "div"),
+            Instruction::Unbound(name) => write!(f, "unbound {}", name),
+            Instruction::Fail(error) => write!(f, "fail {}", error),
+        }
+    }
+}
+
+// Scope maps each identifier in scope to its local slot, innermost last.
+type Scope<'a> = Vec<(&'a str, usize)>;
+
+// Compiler holds the state threaded through compilation.
+pub(crate) struct Compiler<'a> {
+    chunk: Chunk,
+    scope: Scope<'a>,
+    // bindings holds, for each local slot, the bound expression and the scope
+    // it must be compiled in. The expression is taken once it is emitted.
+    bindings: Vec<Option<(&'a Expr, Scope<'a>)>>,
+}
+
+impl<'a> Compiler<'a> {
+    fn emit(&mut self, instruction: Instruction) {
+        self.chunk.code.push(instruction)
+    }
+
+    // fail emits a Fail with the given error.
+    fn fail(&mut self, msg: String) {
+        let error: usize = self.chunk.errors.len();
+        self.chunk.errors.push(msg);
+        self.emit(Instruction::Fail(error))
+    }
+}
+
+// A type that implements Compilable can append the bytecode that evaluates it
+// to a chunk.
+pub(crate) trait Compilable {
+    // compile emits instructions that leave the value of this node on top of
+    // the operand stack.
+    fn compile<'a>(&'a self, compiler: &mut Compiler<'a>);
+}
+
+impl Compilable for Expr {
+    fn compile<'a>(&'a self, compiler: &mut Compiler<'a>) {
+        match self {
+            Expr::Number(expr) => expr.compile(compiler),
+            Expr::Binary(expr) => expr.compile(compiler),
+            Expr::With(expr) => expr.compile(compiler),
+            Expr::Id(expr) => expr.compile(compiler),
+            Expr::Error(expr) => expr.compile(compiler),
+            _ => compiler.fail(unsupported(self, "the bytecode compiler")),
+        }
+    }
+}
+
+impl Compilable for Number {
+    fn compile<'a>(&'a self, compiler: &mut Compiler<'a>) {
+        compiler.emit(Instruction::Const(self.val))
+    }
+}
+
+impl Compilable for Binary {
+    fn compile<'a>(&'a self, compiler: &mut Compiler<'a>) {
+        self.left.compile(compiler);
+        self.right.compile(compiler);
+        compiler.emit(match self.op {
+            Operator::Add => Instruction::Add,
+            Operator::Sub => Instruction::Sub,
+            Operator::Mul => Instruction::Mul,
+            Operator::Div => Instruction::Div,
+        })
+    }
+}
+
+impl Compilable for With {
+    fn compile<'a>(&'a self, compiler: &mut Compiler<'a>) {
+        // Defer the bound expression until its identifier is first used.
+        let slot: usize = compiler.chunk.locals.len();
+        compiler.chunk.locals.push(self.binding.identifier.val.clone());
+        compiler.bindings.push(Some((&self.binding.replace, compiler.scope.clone())));
+
+        compiler.scope.push((&self.binding.identifier.val, slot));
+        self.input.compile(compiler);
+        compiler.scope.pop();
+    }
+}
+
+impl Compilable for Error {
+    fn compile<'a>(&'a self, compiler: &mut Compiler<'a>) {
+        compiler.fail(self.message.clone())
+    }
+}
+
+impl Compilable for Id {
+    fn compile<'a>(&'a self, compiler: &mut Compiler<'a>) {
+        let slot: usize;
+        match compiler.scope.iter().rev().find(|(name, _)| *name == self.val) {
+            Some((_, s)) => slot = *s,
+            None => {
+                let name: usize = compiler.chunk.names.len();
+                compiler.chunk.names.push(self.clone());
+                return compiler.emit(Instruction::Unbound(name))
+            }
+        }
+        if let Some((replace, scope)) = compiler.bindings[slot].take() {
+            // First use: evaluate the bound expression in the scope where it
+            // was written, not the scope of this use.
+            let use_scope: Scope = std::mem::replace(&mut compiler.scope, scope);
+            replace.compile(compiler);
+            compiler.scope = use_scope;
+            compiler.emit(Instruction::Bind(slot));
+        }
+        compiler.emit(Instruction::Load(slot))
+    }
+}
@@ -1,54 +1,343 @@
-use crate::{Expr, Number, Binary, Operator, With, Id};
+use crate::{Expr, Operator, Binding, Id, Meta};
+use crate::ast::{Fun, Primitive};
+use crate::limits::{Budget, EvalLimits};
+use crate::pretty_print::Printable;
+use crate::reader::{quote, Span};
 use crate::subst::Substitutable;
+use crate::value::Value;
+use std::convert::TryFrom;
+use std::rc::Rc;
 
-// calc evaluates the given abstract syntax tree and returns the result.
+// calc evaluates the given abstract syntax tree, whose value must be a number,
+// and returns the result.
 pub fn calc(ast: &Expr) -> Result<i32, String> {
+    calc_with_limits(ast, &EvalLimits::default())
+}
+
+// calc_with_limits evaluates the given abstract syntax tree within the given
+// limits, which apply to substitution and evaluation together. Exceeding a
+// limit is reported with an error for which is_limit_exceeded returns true.
+pub fn calc_with_limits(ast: &Expr, limits: &EvalLimits) -> Result<i32, String> {
+    match eval_with_limits(ast, limits)? {
+        Value::Number(val) => Ok(val),
+        value => Err(mismatch("a number", &value, ast.meta().span)),
+    }
+}
+
+// eval evaluates the given abstract syntax tree and returns its value, which
+// may be of any type.
+pub fn eval(ast: &Expr) -> Result<Value, String> {
+    eval_with_limits(ast, &EvalLimits::default())
+}
+
+// eval_with_limits is eval within the given limits, as for calc_with_limits.
+pub fn eval_with_limits(ast: &Expr, limits: &EvalLimits) -> Result<Value, String> {
+    eval_within(ast.clone(), &mut Budget::new(limits, ast))
+}
+
+// eval_within is eval, charging the work done to the given budget.
+pub(crate) fn eval_within(ast: Expr, budget: &mut Budget) -> Result<Value, String> {
     // First carry out With substitution, then evaluate the resulting AST.
-    ast.clone().replace().calc()
+    ast.replace_within(budget)?.calc(budget)
 }
 
-// A type that implements Calculable can be evaluated for a float result.
+// A type that implements Calculable can be evaluated for a Value.
 pub(crate) trait Calculable {
-    // calc evaluates the expression rooted at this node and returns the result.
-    fn calc(&self) -> Result<i32, String>;
+    // calc evaluates the expression rooted at this node and returns the result,
+    // charging the work done to the given budget.
+    fn calc(self, budget: &mut Budget) -> Result<Value, String>;
 }
 
 impl Calculable for Expr {
-    fn calc(&self) -> Result<i32, String> {
-        return match self {
-            Expr::Number(expr) => expr.calc(),
-            Expr::Binary(expr) => expr.calc(),
-            Expr::With(expr) => expr.calc(),
-            Expr::Id(expr) => expr.calc(),
+    // calc walks the tree with an explicit work stack rather than recursing, so
+    // that its depth is bounded only by memory. Inputs are evaluated left to
+    // right, and the first error is returned. The tree is taken apart as it is
+    // evaluated, so that the body of an applied function, which is a new tree
+    // each time, is evaluated the same way as the rest.
+    fn calc(self, budget: &mut Budget) -> Result<Value, String> {
+        enum Task {
+            Eval(Expr, usize),
+            // Apply pops the values of both inputs, which were written at the
+            // given locations, and pushes the result.
+            Apply(Operator, [Span; 2]),
+            // Branch pops the value of a condition, written at the given
+            // location, and evaluates the first expression if it is true or
+            // the second if it is false.
+            Branch(Expr, Expr, Span, usize),
+            // Call pops the values of one argument for each of the given
+            // locations and pushes the result.
+            Call(Primitive, Vec<Span>),
+            // Record pops the values of the fields with the given names, which
+            // were pushed in order, and pushes the record.
+            Record(Vec<String>),
+            // Get pops a record, written at the given location, and pushes the
+            // value of the given field.
+            Get(Box<Id>, Span),
+            // Invoke pops an argument and then a function, written at the given
+            // locations, and evaluates the body of the function with the
+            // argument substituted for its parameter. The substituted argument
+            // is located where it was written, so that errors about it are too.
+            Invoke([Span; 2], usize),
+        }
+        let mut tasks: Vec<Task> = vec![Task::Eval(self, 0)];
+        let mut values: Vec<Value> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Eval(mut expr, depth) => {
+                    budget.visit(depth)?;
+                    match &mut expr {
+                        Expr::Number(expr) => values.push(Value::Number(expr.val)),
+                        Expr::Bool(expr) => values.push(Value::Bool(expr.val)),
+                        Expr::Str(expr) => values.push(Value::Str(std::mem::take(&mut expr.val))),
+                        Expr::Empty(_) => values.push(Value::Empty),
+                        Expr::Binary(expr) => {
+                            tasks.push(Task::Apply(expr.op, [expr.left.meta().span, expr.right.meta().span]));
+                            tasks.push(Task::Eval(expr.right.take(), depth + 1));
+                            tasks.push(Task::Eval(expr.left.take(), depth + 1))
+                        },
+                        // A With that has been replaced evaluates to its input.
+                        Expr::With(expr) => tasks.push(Task::Eval(expr.input.take(), depth + 1)),
+                        Expr::If(expr) => {
+                            let span: Span = expr.condition.meta().span;
+                            tasks.push(Task::Branch(expr.then.take(), expr.otherwise.take(), span, depth + 1));
+                            tasks.push(Task::Eval(expr.condition.take(), depth + 1))
+                        },
+                        Expr::Call(expr) => {
+                            tasks.push(Task::Call(expr.prim, expr.args.iter().map(|arg| arg.meta().span).collect()));
+                            for arg in expr.args.iter_mut().rev() {
+                                tasks.push(Task::Eval(arg.take(), depth + 1));
+                            }
+                        },
+                        Expr::Fun(expr) => {
+                            let body: Expr = expr.body.take();
+                            let fun: Fun = Fun{ param: expr.param.clone(), annotation: expr.annotation.take(), body, meta: expr.meta.clone() };
+                            values.push(Value::Fun(Box::new(fun)))
+                        },
+                        Expr::App(expr) => {
+                            tasks.push(Task::Invoke([expr.fun.meta().span, expr.arg.meta().span], depth + 1));
+                            tasks.push(Task::Eval(expr.arg.take(), depth + 1));
+                            tasks.push(Task::Eval(expr.fun.take(), depth + 1))
+                        },
+                        Expr::Record(expr) => {
+                            tasks.push(Task::Record(expr.fields.iter().map(|field| field.name.val.clone()).collect()));
+                            for field in expr.fields.iter_mut().rev() {
+                                tasks.push(Task::Eval(field.value.take(), depth + 1));
+                            }
+                        },
+                        Expr::Get(expr) => {
+                            tasks.push(Task::Get(expr.field.clone(), expr.record.meta().span));
+                            tasks.push(Task::Eval(expr.record.take(), depth + 1))
+                        },
+                        Expr::Id(expr) => return Err(unbound_identifier(expr)),
+                        Expr::Error(expr) => return Err(expr.message.clone()),
+                    }
+                },
+                Task::Apply(op, [left_span, right_span]) => {
+                    let right: Value = values.pop().unwrap();
+                    let left: i32 = number(&values.pop().unwrap(), left_span)?;
+                    let right: i32 = number(&right, right_span)?;
+                    budget.step()?;
+                    values.push(Value::Number(op.apply(left, right)?))
+                },
+                Task::Branch(then, otherwise, span, depth) => {
+                    budget.step()?;
+                    match values.pop().unwrap() {
+                        Value::Bool(true) => tasks.push(Task::Eval(then, depth)),
+                        Value::Bool(false) => tasks.push(Task::Eval(otherwise, depth)),
+                        value => return Err(mismatch("a boolean", &value, span)),
+                    }
+                },
+                Task::Call(prim, spans) => {
+                    let args: Vec<Value> = values.split_off(values.len() - spans.len());
+                    budget.step()?;
+                    values.push(prim.apply(&args, &spans)?)
+                },
+                Task::Record(names) => {
+                    let fields: Vec<Value> = values.split_off(values.len() - names.len());
+                    budget.step()?;
+                    values.push(Value::Record(Rc::new(names.into_iter().zip(fields).collect())))
+                },
+                Task::Get(field, span) => {
+                    let record: Value = values.pop().unwrap();
+                    let fields: &[(String, Value)] = match &record {
+                        Value::Record(fields) => fields,
+                        value => return Err(mismatch("a record", value, span)),
+                    };
+                    budget.step()?;
+                    match fields.iter().find(|(name, _)| *name == field.val) {
+                        Some((_, value)) => values.push(value.clone()),
+                        None => return Err(located(field.meta.span, &format!("record has no field: {}", field.val))),
+                    }
+                },
+                Task::Invoke([fun_span, arg_span], depth) => {
+                    let arg: Value = values.pop().unwrap();
+                    let mut fun: Value = values.pop().unwrap();
+                    let fun: &mut Fun = match &mut fun {
+                        Value::Fun(fun) => fun,
+                        value => return Err(mismatch("a function", value, fun_span)),
+                    };
+                    budget.step()?;
+                    let mut replace: Expr = arg.to_expr();
+                    replace.meta_mut().span = arg_span;
+                    let binding: Binding = Binding{ identifier: fun.param.clone(), annotation: None, replace, meta: Meta::default() };
+                    tasks.push(Task::Eval(fun.body.take().subst_within(&binding, budget)?, depth))
+                },
+            }
         }
+        Ok(values.pop().unwrap())
     }
 }
 
-impl Calculable for Number {
-    fn calc(&self) -> Result<i32, String> {
-        Ok(self.val)
+impl Operator {
+    // apply computes the result of this operator on the given operands. Every
+    // evaluator shares this method so that they agree on overflow and division
+    // by zero, which are reported as errors rather than panics.
+    pub(crate) fn apply(self, left: i32, right: i32) -> Result<i32, String> {
+        let result: Option<i32> = match self {
+            Operator::Add => left.checked_add(right),
+            Operator::Sub => left.checked_sub(right),
+            Operator::Mul => left.checked_mul(right),
+            Operator::Div if right == 0 => return Err(DIVISION_BY_ZERO.to_string()),
+            Operator::Div => left.checked_div(right),
+        };
+        result.ok_or_else(|| INTEGER_OVERFLOW.to_string())
     }
 }
 
-impl Calculable for Binary {
-    fn calc(&self) -> Result<i32, String> {
-        return match self.op {
-            Operator::Add => Ok(self.left.calc()? + self.right.calc()?),
-            Operator::Sub => Ok(self.left.calc()? - self.right.calc()?),
-            Operator::Mul => Ok(self.left.calc()? * self.right.calc()?),
-            Operator::Div => Ok(self.left.calc()? / self.right.calc()?),
+impl Primitive {
+    // apply computes the result of this primitive on the given arguments,
+    // which were written at the given locations. Strings are indexed by
+    // character, and a substring runs from its start index up to but not
+    // including its end index. The rest of a cons must be a list.
+    pub(crate) fn apply(self, args: &[Value], spans: &[Span]) -> Result<Value, String> {
+        match self {
+            Primitive::Less => Ok(Value::Bool(number(&args[0], spans[0])? < number(&args[1], spans[1])?)),
+            Primitive::Equal => Ok(Value::Bool(number(&args[0], spans[0])? == number(&args[1], spans[1])?)),
+            Primitive::Greater => Ok(Value::Bool(number(&args[0], spans[0])? > number(&args[1], spans[1])?)),
+            Primitive::StringAppend => {
+                let left: &str = string(&args[0], spans[0])?;
+                let right: &str = string(&args[1], spans[1])?;
+                Ok(Value::Str(format!("{}{}", left, right)))
+            },
+            Primitive::StringLength => {
+                let length: usize = string(&args[0], spans[0])?.chars().count();
+                i32::try_from(length).map(Value::Number).map_err(|_| INTEGER_OVERFLOW.to_string())
+            },
+            Primitive::Substring => {
+                let val: &str = string(&args[0], spans[0])?;
+                let start: i32 = number(&args[1], spans[1])?;
+                let end: i32 = number(&args[2], spans[2])?;
+                let length: usize = val.chars().count();
+                let start: usize = index(start, length, spans[1])?;
+                let end: usize = index(end, length, spans[2])?;
+                if end < start {
+                    return Err(located(spans[2], &format!("end index {} is before start index {}", end, start)))
+                }
+                Ok(Value::Str(val.chars().skip(start).take(end - start).collect()))
+            },
+            Primitive::NumberToString => Ok(Value::Str(number(&args[0], spans[0])?.to_string())),
+            Primitive::StringToNumber => {
+                let val: &str = string(&args[0], spans[0])?;
+                match val.parse::<i32>() {
+                    Ok(val) => Ok(Value::Number(val)),
+                    Err(_) => Err(located(spans[0], &format!("cannot convert {} to a number", quote(val)))),
+                }
+            },
+            Primitive::Cons => {
+                let rest: &Value = list(&args[1], spans[1])?;
+                Ok(Value::Cons(Rc::new((args[0].clone(), rest.clone()))))
+            },
+            Primitive::First => {
+                let (first, _) = pair(&args[0], spans[0])?;
+                Ok(first.clone())
+            },
+            Primitive::Rest => {
+                let (_, rest) = pair(&args[0], spans[0])?;
+                Ok(rest.clone())
+            },
+            Primitive::IsEmpty => Ok(Value::Bool(matches!(list(&args[0], spans[0])?, Value::Empty))),
+            Primitive::List => {
+                let cons = |rest: Value, item: &Value| Value::Cons(Rc::new((item.clone(), rest)));
+                Ok(args.iter().rev().fold(Value::Empty, cons))
+            },
         }
     }
 }
 
-impl Calculable for With {
-    fn calc(&self) -> Result<i32, String> {
-        self.input.calc()
+// number returns the given value if it is a number, and otherwise reports a
+// type error located at the given span.
+fn number(value: &Value, span: Span) -> Result<i32, String> {
+    match value {
+        Value::Number(val) => Ok(*val),
+        value => Err(mismatch("a number", value, span)),
     }
 }
 
-impl Calculable for Id {
-    fn calc(&self) -> Result<i32, String> {
-        Err(format!("failed to replace identifier: {}", self.val))
+// string returns the given value if it is a string, and otherwise reports a
+// type error located at the given span.
+fn string(value: &Value, span: Span) -> Result<&str, String> {
+    match value {
+        Value::Str(val) => Ok(val),
+        value => Err(mismatch("a string", value, span)),
     }
 }
+
+// list returns the given value if it is a list, and otherwise reports a type
+// error located at the given span.
+fn list(value: &Value, span: Span) -> Result<&Value, String> {
+    match value {
+        Value::Empty | Value::Cons(_) => Ok(value),
+        value => Err(mismatch("a list", value, span)),
+    }
+}
+
+// pair returns the first element of the given value and the list of the rest
+// of its elements if it is a non-empty list, and otherwise reports a type
+// error located at the given span.
+fn pair(value: &Value, span: Span) -> Result<(&Value, &Value), String> {
+    match value {
+        Value::Cons(cell) => Ok((&cell.0, &cell.1)),
+        value => Err(mismatch("a non-empty list", value, span)),
+    }
+}
+
+// index returns the given index into a string of the given length, which may
+// be anywhere from its start to its end inclusive, and otherwise reports an
+// error located at the given span.
+fn index(index: i32, length: usize, span: Span) -> Result<usize, String> {
+    match usize::try_from(index) {
+        Ok(index) if index <= length => Ok(index),
+        _ => Err(located(span, &format!("index {} is out of range for a string of length {}", index, length))),
+    }
+}
+
+// mismatch returns the error reported when evaluation finds the given value
+// where a value of the expected kind is required.
+pub(crate) fn mismatch(expected: &str, value: &Value, span: Span) -> String {
+    located(span, &format!("expected {} but found {}", expected, value.kind()))
+}
+
+// unbound_identifier returns the error reported when evaluation reaches an
+// identifier that no With binds, located at the identifier if it was parsed.
+pub(crate) fn unbound_identifier(id: &Id) -> String {
+    located(id.meta.span, &format!("failed to replace identifier: {}", id.val))
+}
+
+// unsupported returns the error reported when the named pass, which only
+// handles the arithmetic language, reaches the given node.
+pub(crate) fn unsupported(expr: &Expr, pass: &str) -> String {
+    located(expr.meta().span, &format!("{} does not support {} expressions", pass, expr.name()))
+}
+
+// located prefixes the given message with the given location, unless the node
+// it refers to was built by a pass rather than parsed.
+pub(crate) fn located(span: Span, msg: &str) -> String {
+    if span.line == 0 {
+        return msg.to_string()
+    }
+    format!("{}: {}", span, msg)
+}
+
+// Error messages for arithmetic that does not fit in an i32.
+pub(crate) const DIVISION_BY_ZERO: &str = "division by zero";
+pub(crate) const INTEGER_OVERFLOW: &str = "integer overflow";
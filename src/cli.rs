@@ -0,0 +1,260 @@
+use crate::Expr;
+use crate::debug::debug;
+use crate::limits::EvalLimits;
+use crate::format::{format, DEFAULT_WIDTH};
+use crate::dot::{to_dot, DotOptions};
+use crate::infer::{infer_program, Inference};
+use crate::parse::parse;
+use crate::program::{parse_program, parse_program_with_recovery, evaluate, Program, Form, Outcome};
+use crate::pretty_print::pretty_print;
+use crate::step::{trace, Trace};
+use crate::subst::Substitutable;
+use crate::typecheck::check_program;
+use crate::unparse::to_source;
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+// run executes the command named by the first of the given arguments and
+// returns the process exit code.
+//
+// USAGE:
+//   rinterp run [--typed] [--max-steps N] [--max-depth N] [--max-size N] [--timeout MS] [FILE]
+//   rinterp check [FILE]
+//   rinterp types [FILE]
+//   rinterp trace [--tree] [FILE]
+//   rinterp debug FILE
+//   rinterp fmt [--width N] [FILE]
+//   rinterp dot [--clusters] [--subst] [FILE]
+pub fn run(args: &[String]) -> i32 {
+    let result: Result<(), String> = match args[0].as_str() {
+        "run" => run_program(&args[1..]),
+        "check" => run_check(&args[1..]),
+        "types" => run_types(&args[1..]),
+        "trace" => run_trace(&args[1..]),
+        "debug" => run_debug(&args[1..]),
+        "fmt" => run_fmt(&args[1..]),
+        "dot" => run_dot(&args[1..]),
+        command => Err(format!("unknown command: {}", command)),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(msg) => {
+            eprintln!("Error: {}", msg);
+            1
+        }
+    }
+}
+
+// run_program evaluates the program read from the given file, or from standard
+// input if no file is given, and prints the outcome of each form on its own
+// line. It fails if any form fails. The options limit the work done by each
+// form, and the timeout applies to the whole program. With --typed, every form
+// is type checked first, and nothing is evaluated if any form fails to check.
+fn run_program(args: &[String]) -> Result<(), String> {
+    let mut limits: EvalLimits = EvalLimits::default();
+    let mut typed: bool = false;
+    let mut path: Option<&String> = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let limit: &mut Option<usize> = match arg.as_str() {
+            "--typed" => {
+                typed = true;
+                continue
+            },
+            "--max-steps" => &mut limits.max_steps,
+            "--max-depth" => &mut limits.max_depth,
+            "--max-size" => &mut limits.max_size,
+            "--timeout" => {
+                let value: &String = args.next().ok_or("expected a value for --timeout")?;
+                let millis: u64 = value.parse().map_err(|_| format!("invalid timeout: {}", value))?;
+                limits.deadline = Some(Instant::now() + Duration::from_millis(millis));
+                continue
+            },
+            _ if path.is_none() => {
+                path = Some(arg);
+                continue
+            },
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        };
+        let value: &String = args.next().ok_or_else(|| format!("expected a value for {}", arg))?;
+        *limit = Some(value.parse().map_err(|_| format!("invalid value for {}: {}", arg, value))?);
+    }
+    let program: Program = parse_program(&read_input(path)?)?;
+    if typed {
+        let mut failed: usize = 0;
+        for (form, result) in program.forms.iter().zip(check_program(&program)) {
+            if let Err(msg) = result {
+                println!("{}: type error: {}", form.span(), msg);
+                failed += 1
+            }
+        }
+        if failed > 0 {
+            return Err(format!("{} of {} forms failed to type check", failed, program.forms.len()))
+        }
+    }
+    let outcomes: Vec<Result<Outcome, String>> = evaluate(&program, &limits);
+    let mut failed: usize = 0;
+    for (form, outcome) in program.forms.iter().zip(outcomes) {
+        match outcome {
+            Ok(outcome) => println!("{}: {}", form.span(), outcome),
+            Err(msg) => {
+                println!("{}: error: {}", form.span(), msg);
+                failed += 1
+            },
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} forms failed", failed, program.forms.len()))
+    }
+    Ok(())
+}
+
+// run_check prints every parse error in the program read from the given file,
+// or from standard input if no file is given, one per line. It fails if there
+// are any.
+fn run_check(args: &[String]) -> Result<(), String> {
+    let path: Option<&String> = match args {
+        [] => None,
+        [path] => Some(path),
+        _ => return Err(format!("unexpected argument: {}", args[1])),
+    };
+    let (_, errors): (Program, Vec<String>) = parse_program_with_recovery(&read_input(path)?);
+    for msg in &errors {
+        println!("{}", msg);
+    }
+    if !errors.is_empty() {
+        return Err(format!("{} parse errors", errors.len()))
+    }
+    Ok(())
+}
+
+// run_types prints the inferred type of each form of the program read from the
+// given file, or from standard input if no file is given, followed by the type
+// of each identifier bound in the form, indented. It fails if any form has a
+// type error.
+fn run_types(args: &[String]) -> Result<(), String> {
+    let path: Option<&String> = match args {
+        [] => None,
+        [path] => Some(path),
+        _ => return Err(format!("unexpected argument: {}", args[1])),
+    };
+    let program: Program = parse_program(&read_input(path)?)?;
+    let mut failed: usize = 0;
+    for (form, result) in program.forms.iter().zip(infer_program(&program)) {
+        let inference: Inference = match result {
+            Ok(inference) => inference,
+            Err(msg) => {
+                println!("{}: type error: {}", form.span(), msg);
+                failed += 1;
+                continue
+            },
+        };
+        match form {
+            Form::Define(define) => println!("{}: {} : {}", form.span(), define.name.val, inference.ty),
+            Form::Expr(_) => println!("{}: {}", form.span(), inference.ty),
+        }
+        for (id, ty) in &inference.bindings {
+            println!("  {}: {} : {}", id.meta.span, id.val, ty);
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} forms failed to type check", failed, program.forms.len()))
+    }
+    Ok(())
+}
+
+// run_trace prints every step of the reduction of the expression read from the
+// given file, or from standard input if no file is given, as source text or,
+// with --tree, as trees.
+fn run_trace(args: &[String]) -> Result<(), String> {
+    let mut tree: bool = false;
+    let mut path: Option<&String> = None;
+    for arg in args {
+        match arg.as_str() {
+            "--tree" => tree = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    let steps: Trace = trace(&parse(read_input(path)?)?);
+    let print = |expr: &Expr| if tree { pretty_print(expr) } else { println!("{}", to_source(expr)) };
+    print(&steps.start);
+    for (i, step) in steps.steps.iter().enumerate() {
+        println!("-- step {}: {}", i + 1, step.reduction);
+        print(&step.expr);
+    }
+    match steps.error {
+        Some(msg) => Err(msg),
+        None => Ok(()),
+    }
+}
+
+// run_debug evaluates the expression read from the given file in the debugger,
+// which reads its commands from standard input, and prints the value.
+fn run_debug(args: &[String]) -> Result<(), String> {
+    let path: &String = match args {
+        [path] => path,
+        [] => return Err("expected a file to debug".to_string()),
+        _ => return Err(format!("unexpected argument: {}", args[1])),
+    };
+    let ast: Expr = parse(read_input(Some(path))?)?;
+    let val: i32 = debug(&ast, &mut std::io::stdin().lock(), &mut std::io::stdout())?;
+    println!("{}", val);
+    Ok(())
+}
+
+// run_fmt prints the formatted program read from the given file, or from
+// standard input if no file is given.
+fn run_fmt(args: &[String]) -> Result<(), String> {
+    let mut width: usize = DEFAULT_WIDTH;
+    let mut path: Option<&String> = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--width" => {
+                let value: &String = args.next().ok_or("expected a value for --width")?;
+                width = value.parse().map_err(|_| format!("invalid width: {}", value))?;
+            },
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    print!("{}", format(&read_input(path)?, width)?);
+    Ok(())
+}
+
+// run_dot prints the Graphviz rendering of the program read from the given
+// file, or from standard input if no file is given. With --subst, the tree is
+// rendered after With substitution.
+fn run_dot(args: &[String]) -> Result<(), String> {
+    let mut options: DotOptions = DotOptions{ clusters: false };
+    let mut subst: bool = false;
+    let mut path: Option<&String> = None;
+    for arg in args {
+        match arg.as_str() {
+            "--clusters" => options.clusters = true,
+            "--subst" => subst = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    let mut ast: Expr = parse(read_input(path)?)?;
+    if subst {
+        ast = ast.replace();
+    }
+    print!("{}", to_dot(&ast, &options));
+    Ok(())
+}
+
+// read_input returns the contents of the given file, or of standard input if no
+// file is given.
+fn read_input(path: Option<&String>) -> Result<String, String> {
+    let mut input: String = String::new();
+    match path {
+        Some(path) => input = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?,
+        None => {
+            std::io::stdin().read_to_string(&mut input).map_err(|err| err.to_string())?;
+        },
+    }
+    Ok(input)
+}
@@ -0,0 +1,232 @@
+use crate::{Expr, Number, Binary, Operator, With, Id};
+use crate::ast::Error;
+use crate::calc::{DIVISION_BY_ZERO, INTEGER_OVERFLOW};
+use crate::parse::INVALID_EXPRESSION;
+
+// to_c returns the source of a self-contained C99 function with the given name
+// that evaluates the given expression:
+//
+//   int name(int32_t a, int32_t b, ..., int32_t *out_value);
+//
+// Free identifiers become int32_t parameters in order of first appearance. The
+// function returns RINTERP_OK and stores the value through out_value, or
+// returns one of the other RINTERP_* status codes if evaluation overflows,
+// divides by zero or reaches an expression that failed to parse or that is
+// outside the arithmetic language;
+// rinterp_status_message maps a status to the same message
+// calc reports. Bound expressions are evaluated immediately before their first
+// use, as in the bytecode compiler, so errors are reported in the same order.
+pub fn to_c(ast: &Expr, name: &str) -> String {
+    let mut generator: Generator = Generator{
+        body: String::new(),
+        params: Vec::new(),
+        scope: Vec::new(),
+        bindings: Vec::new(),
+        temps: 0,
+    };
+    let result: String = ast.generate(&mut generator);
+
+    let mut params: Vec<String> = generator.params.iter()
+        .map(|param| format!("int32_t {}", c_name(param)))
+        .collect();
+    params.push("int32_t *out_value".to_string());
+
+    let mut source: String = String::new();
+    source.push_str(&prelude());
+    source.push_str(&format!("\nint {}({}) {{\n", name, params.join(", ")));
+    source.push_str(&generator.body);
+    source.push_str(&format!("    *out_value = {};\n", result));
+    source.push_str("    return RINTERP_OK;\n}\n");
+    source
+}
+
+// c_name returns the C identifier used for the given WAE identifier. WAE
+// identifiers are purely alphabetic, so they can only collide with C keywords
+// and never with the generated temporaries or out_value.
+fn c_name(name: &str) -> String {
+    if C_KEYWORDS.contains(&name) {
+        return format!("{}_", name)
+    }
+    name.to_string()
+}
+
+// Pending is a With binding whose C variable has not been emitted yet, along
+// with the scope it must be generated in.
+type Pending<'a> = (&'a Expr, Scope<'a>);
+
+// Scope maps each identifier in scope to the index of its binding, innermost
+// last.
+type Scope<'a> = Vec<(&'a str, usize)>;
+
+// Generator holds the state threaded through code generation.
+pub(crate) struct Generator<'a> {
+    body: String,
+    params: Vec<String>,
+    scope: Scope<'a>,
+    // bindings holds, for each With binding, either the expression still to be
+    // emitted or the C operand holding its value.
+    bindings: Vec<Result<String, Pending<'a>>>,
+    temps: usize,
+}
+
+impl<'a> Generator<'a> {
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("t{}", self.temps)
+    }
+
+    fn line(&mut self, line: &str) {
+        self.body.push_str("    ");
+        self.body.push_str(line);
+        self.body.push('\n')
+    }
+
+    // invalid emits a return of RINTERP_INVALID_EXPRESSION and returns a
+    // placeholder operand for the value that is never computed.
+    fn invalid(&mut self) -> String {
+        self.line("return RINTERP_INVALID_EXPRESSION;");
+        "0".to_string()
+    }
+}
+
+// A type that implements Generable can emit C statements that compute its
+// value.
+pub(crate) trait Generable {
+    // generate emits the statements computing this node and returns the C
+    // operand that holds its value.
+    fn generate<'a>(&'a self, generator: &mut Generator<'a>) -> String;
+}
+
+impl Generable for Expr {
+    fn generate<'a>(&'a self, generator: &mut Generator<'a>) -> String {
+        match self {
+            Expr::Number(expr) => expr.generate(generator),
+            Expr::Binary(expr) => expr.generate(generator),
+            Expr::With(expr) => expr.generate(generator),
+            Expr::Id(expr) => expr.generate(generator),
+            Expr::Error(expr) => expr.generate(generator),
+            _ => generator.invalid(),
+        }
+    }
+}
+
+impl Generable for Number {
+    fn generate<'a>(&'a self, _: &mut Generator<'a>) -> String {
+        if self.val == i32::MIN {
+            return "INT32_MIN".to_string()
+        }
+        if self.val < 0 {
+            return format!("({})", self.val)
+        }
+        self.val.to_string()
+    }
+}
+
+impl Generable for Binary {
+    fn generate<'a>(&'a self, generator: &mut Generator<'a>) -> String {
+        let left: String = self.left.generate(generator);
+        let right: String = self.right.generate(generator);
+        let temp: String = generator.temp();
+        if let Operator::Div = self.op {
+            generator.line(&format!("if ({} == 0) return RINTERP_DIVISION_BY_ZERO;", right));
+            generator.line(&format!("if ({} == INT32_MIN && {} == -1) return RINTERP_INTEGER_OVERFLOW;", left, right));
+            generator.line(&format!("int32_t {} = {} / {};", temp, left, right));
+            return temp
+        }
+        let symbol: &str = match self.op {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            _ => "*",
+        };
+        // The exact result of any of these operators on two int32_t values
+        // fits in an int64_t.
+        let wide: String = format!("w{}", &temp[1..]);
+        generator.line(&format!("int64_t {} = (int64_t){} {} (int64_t){};", wide, left, symbol, right));
+        generator.line(&format!("if ({} < INT32_MIN || {} > INT32_MAX) return RINTERP_INTEGER_OVERFLOW;", wide, wide));
+        generator.line(&format!("int32_t {} = (int32_t){};", temp, wide));
+        temp
+    }
+}
+
+impl Generable for With {
+    fn generate<'a>(&'a self, generator: &mut Generator<'a>) -> String {
+        // Defer the bound expression until its identifier is first used.
+        let index: usize = generator.bindings.len();
+        generator.bindings.push(Err((&self.binding.replace, generator.scope.clone())));
+
+        generator.scope.push((&self.binding.identifier.val, index));
+        let result: String = self.input.generate(generator);
+        generator.scope.pop();
+        result
+    }
+}
+
+impl Generable for Error {
+    fn generate<'a>(&'a self, generator: &mut Generator<'a>) -> String {
+        generator.invalid()
+    }
+}
+
+impl Generable for Id {
+    fn generate<'a>(&'a self, generator: &mut Generator<'a>) -> String {
+        let index: usize;
+        match generator.scope.iter().rev().find(|(name, _)| *name == self.val) {
+            Some((_, i)) => index = *i,
+            None => {
+                if !generator.params.contains(&self.val) {
+                    generator.params.push(self.val.clone());
+                }
+                return c_name(&self.val)
+            }
+        }
+        let pending: Pending<'a> = match &generator.bindings[index] {
+            Ok(operand) => return operand.clone(),
+            Err((replace, scope)) => (replace, scope.clone()),
+        };
+        // First use: evaluate the bound expression in the scope where it was
+        // written, not the scope of this use.
+        let (replace, scope) = pending;
+        let use_scope: Scope = std::mem::replace(&mut generator.scope, scope);
+        let operand: String = replace.generate(generator);
+        generator.scope = use_scope;
+        generator.line(&format!("/* {} = {} */", self.val, operand));
+        generator.bindings[index] = Ok(operand.clone());
+        operand
+    }
+}
+
+// prelude declares the status codes and message lookup shared by every
+// generated function. It is guarded so several functions can be concatenated
+// into one translation unit.
+fn prelude() -> String {
+    format!(concat!(
+        "#include <stdint.h>\n",
+        "\n",
+        "#ifndef RINTERP_STATUS\n",
+        "#define RINTERP_STATUS\n",
+        "#define RINTERP_OK 0\n",
+        "#define RINTERP_DIVISION_BY_ZERO 1\n",
+        "#define RINTERP_INTEGER_OVERFLOW 2\n",
+        "#define RINTERP_INVALID_EXPRESSION 3\n",
+        "\n",
+        "static const char *rinterp_status_message(int status) {{\n",
+        "    switch (status) {{\n",
+        "    case RINTERP_OK: return \"ok\";\n",
+        "    case RINTERP_DIVISION_BY_ZERO: return \"{}\";\n",
+        "    case RINTERP_INTEGER_OVERFLOW: return \"{}\";\n",
+        "    case RINTERP_INVALID_EXPRESSION: return \"{}\";\n",
+        "    default: return \"unknown status\";\n",
+        "    }}\n",
+        "}}\n",
+        "#endif\n",
+    ), DIVISION_BY_ZERO, INTEGER_OVERFLOW, INVALID_EXPRESSION)
+}
+
+// C_KEYWORDS are the C99 keywords, which cannot be used as parameter names.
+const C_KEYWORDS: [&str; 37] = [
+    "auto", "break", "case", "char", "const", "continue", "default", "do",
+    "double", "else", "enum", "extern", "float", "for", "goto", "if", "inline",
+    "int", "long", "register", "restrict", "return", "short", "signed",
+    "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned",
+    "void", "volatile", "while", "_Bool", "_Complex", "_Imaginary",
+];
@@ -0,0 +1,653 @@
+use crate::{Expr, Number, Binary, Operator, With, Id};
+use crate::ast::Error;
+use std::collections::HashMap;
+
+// to_wat returns a WebAssembly text module that evaluates the given expression.
+// The module exports two functions:
+//
+//   (func (export "eval") (param i32 ...) (result i32))
+//   (func (export "status") (result i32))
+//
+// Free identifiers become parameters of eval in order of first appearance.
+// After calling eval, status returns 0 if the result is valid, 1 if evaluation
+// divided by zero, 2 if it overflowed or 3 if it reached an expression that
+// failed to parse or that is outside the arithmetic language, matching the
+// status codes of the C backend. Bound expressions are evaluated immediately before their first use,
+// as in the bytecode compiler, so errors are reported in the same order.
+pub fn to_wat(ast: &Expr) -> String {
+    let mut generator: Generator = Generator{
+        body: String::new(),
+        params: Vec::new(),
+        locals: Vec::new(),
+        scope: Vec::new(),
+        bindings: Vec::new(),
+        temps: 0,
+    };
+    let result: String = ast.generate(&mut generator);
+    generator.line(&result);
+
+    let mut module: String = String::new();
+    module.push_str("(module\n");
+    module.push_str("  (global $status (mut i32) (i32.const 0))\n");
+    module.push_str("  (func $status (export \"status\") (result i32)\n");
+    module.push_str("    global.get $status)\n");
+    module.push_str("  (func $eval (export \"eval\")");
+    for param in &generator.params {
+        module.push_str(&format!(" (param ${} i32)", param));
+    }
+    module.push_str(" (result i32)\n");
+    for (local, ty) in &generator.locals {
+        module.push_str(&format!("    (local ${} {})\n", local, ty));
+    }
+    module.push_str("    i32.const 0\n");
+    module.push_str("    global.set $status\n");
+    module.push_str(&generator.body);
+    // Close the function and the module on the final instruction's line.
+    module.truncate(module.trim_end().len());
+    module.push_str("))\n");
+    module
+}
+
+// Pending is a With binding whose local has not been set yet, along with the
+// scope it must be generated in.
+type Pending<'a> = (&'a Expr, Scope<'a>);
+
+// Scope maps each identifier in scope to the index of its binding, innermost
+// last.
+type Scope<'a> = Vec<(&'a str, usize)>;
+
+// Generator holds the state threaded through code generation.
+pub(crate) struct Generator<'a> {
+    body: String,
+    params: Vec<String>,
+    locals: Vec<(String, &'static str)>,
+    scope: Scope<'a>,
+    // bindings holds, for each With binding, either the expression still to be
+    // emitted or the instruction that pushes its value.
+    bindings: Vec<Result<String, Pending<'a>>>,
+    temps: usize,
+}
+
+impl<'a> Generator<'a> {
+    // temp declares a fresh local of the given type. Temporaries contain a
+    // digit, so they never collide with the alphabetic parameter names.
+    fn temp(&mut self, ty: &'static str) -> String {
+        self.temps += 1;
+        let prefix: &str = if ty == "i64" { "w" } else { "t" };
+        let name: String = format!("{}{}", prefix, self.temps);
+        self.locals.push((name.clone(), ty));
+        name
+    }
+
+    fn line(&mut self, line: &str) {
+        self.body.push_str("    ");
+        self.body.push_str(line);
+        self.body.push('\n')
+    }
+
+    // fail emits an early return that records the given status.
+    fn fail(&mut self, status: i32) {
+        self.line("if");
+        self.line(&format!("  i32.const {}", status));
+        self.line("  global.set $status");
+        self.line("  i32.const 0");
+        self.line("  return");
+        self.line("end");
+    }
+
+    // invalid emits an early return with STATUS_INVALID_EXPRESSION and returns
+    // a placeholder for the value that is never computed.
+    fn invalid(&mut self) -> String {
+        self.line("i32.const 1");
+        self.fail(STATUS_INVALID_EXPRESSION);
+        "i32.const 0".to_string()
+    }
+}
+
+// A type that implements Generable can emit WebAssembly instructions that
+// compute its value.
+pub(crate) trait Generable {
+    // generate emits the instructions computing this node and returns a single
+    // instruction that pushes its value.
+    fn generate<'a>(&'a self, generator: &mut Generator<'a>) -> String;
+}
+
+impl Generable for Expr {
+    fn generate<'a>(&'a self, generator: &mut Generator<'a>) -> String {
+        match self {
+            Expr::Number(expr) => expr.generate(generator),
+            Expr::Binary(expr) => expr.generate(generator),
+            Expr::With(expr) => expr.generate(generator),
+            Expr::Id(expr) => expr.generate(generator),
+            Expr::Error(expr) => expr.generate(generator),
+            _ => generator.invalid(),
+        }
+    }
+}
+
+impl Generable for Number {
+    fn generate<'a>(&'a self, _: &mut Generator<'a>) -> String {
+        format!("i32.const {}", self.val)
+    }
+}
+
+impl Generable for Binary {
+    fn generate<'a>(&'a self, generator: &mut Generator<'a>) -> String {
+        let left: String = self.left.generate(generator);
+        let right: String = self.right.generate(generator);
+        let narrow: String = generator.temp("i32");
+        if let Operator::Div = self.op {
+            generator.line(&right);
+            generator.line("i32.eqz");
+            generator.fail(STATUS_DIVISION_BY_ZERO);
+            generator.line(&left);
+            generator.line(&format!("i32.const {}", i32::MIN));
+            generator.line("i32.eq");
+            generator.line(&right);
+            generator.line("i32.const -1");
+            generator.line("i32.eq");
+            generator.line("i32.and");
+            generator.fail(STATUS_INTEGER_OVERFLOW);
+            generator.line(&left);
+            generator.line(&right);
+            generator.line("i32.div_s");
+            generator.line(&format!("local.set ${}", narrow));
+            return format!("local.get ${}", narrow)
+        }
+        // Compute the exact result in 64 bits, then check that it survives a
+        // round trip through 32 bits.
+        let wide: String = generator.temp("i64");
+        generator.line(&left);
+        generator.line("i64.extend_i32_s");
+        generator.line(&right);
+        generator.line("i64.extend_i32_s");
+        generator.line(match self.op {
+            Operator::Add => "i64.add",
+            Operator::Sub => "i64.sub",
+            _ => "i64.mul",
+        });
+        generator.line(&format!("local.tee ${}", wide));
+        generator.line(&format!("local.get ${}", wide));
+        generator.line("i32.wrap_i64");
+        generator.line("i64.extend_i32_s");
+        generator.line("i64.ne");
+        generator.fail(STATUS_INTEGER_OVERFLOW);
+        generator.line(&format!("local.get ${}", wide));
+        generator.line("i32.wrap_i64");
+        generator.line(&format!("local.set ${}", narrow));
+        format!("local.get ${}", narrow)
+    }
+}
+
+impl Generable for With {
+    fn generate<'a>(&'a self, generator: &mut Generator<'a>) -> String {
+        // Defer the bound expression until its identifier is first used.
+        let index: usize = generator.bindings.len();
+        generator.bindings.push(Err((&self.binding.replace, generator.scope.clone())));
+
+        generator.scope.push((&self.binding.identifier.val, index));
+        let result: String = self.input.generate(generator);
+        generator.scope.pop();
+        result
+    }
+}
+
+impl Generable for Error {
+    fn generate<'a>(&'a self, generator: &mut Generator<'a>) -> String {
+        generator.invalid()
+    }
+}
+
+impl Generable for Id {
+    fn generate<'a>(&'a self, generator: &mut Generator<'a>) -> String {
+        let index: usize;
+        match generator.scope.iter().rev().find(|(name, _)| *name == self.val) {
+            Some((_, i)) => index = *i,
+            None => {
+                if !generator.params.contains(&self.val) {
+                    generator.params.push(self.val.clone());
+                }
+                return format!("local.get ${}", self.val)
+            }
+        }
+        let pending: Pending<'a> = match &generator.bindings[index] {
+            Ok(push) => return push.clone(),
+            Err((replace, scope)) => (replace, scope.clone()),
+        };
+        // First use: evaluate the bound expression in the scope where it was
+        // written, not the scope of this use.
+        let (replace, scope) = pending;
+        let use_scope: Scope = std::mem::replace(&mut generator.scope, scope);
+        let push: String = replace.generate(generator);
+        generator.scope = use_scope;
+        generator.line(&format!(";; {} = {}", self.val, push));
+        generator.bindings[index] = Ok(push.clone());
+        push
+    }
+}
+
+// Status codes reported by the exported status function.
+const STATUS_DIVISION_BY_ZERO: i32 = 1;
+const STATUS_INTEGER_OVERFLOW: i32 = 2;
+const STATUS_INVALID_EXPRESSION: i32 = 3;
+
+// ============================================================================
+// VALIDATION
+// ============================================================================
+
+// validate checks that the given WebAssembly text is a well-formed module
+// exporting an eval function, and type-checks the bodies of its functions.
+//
+// It understands the subset of the text format that to_wat emits: globals,
+// functions with inline exports, params, results and locals, and flat
+// (unfolded) instruction sequences with if/else/end blocks.
+pub fn validate(wat: &str) -> Result<(), String> {
+    let tokens: Vec<String> = tokenize(wat)?;
+    let mut position: usize = 0;
+    let module: SExpr = read_sexpr(&tokens, &mut position)?;
+    if position != tokens.len() {
+        return Err(format!("unexpected token after module: {}", tokens[position]))
+    }
+    let fields: &[SExpr] = match &module {
+        SExpr::List(items) if items.first() == Some(&SExpr::Atom("module".to_string())) => &items[1..],
+        _ => return Err("expected (module ...)".to_string()),
+    };
+
+    let mut globals: HashMap<String, (ValType, bool)> = HashMap::new();
+    let mut exports: Vec<String> = Vec::new();
+    let mut funcs: Vec<&[SExpr]> = Vec::new();
+    for field in fields {
+        let items: &[SExpr] = field.list().ok_or("expected a module field")?;
+        match items.first().and_then(SExpr::atom) {
+            Some("global") => {
+                let (name, global) = validate_global(&items[1..])?;
+                if globals.insert(name.clone(), global).is_some() {
+                    return Err(format!("duplicate global: {}", name))
+                }
+            },
+            Some("func") => funcs.push(&items[1..]),
+            Some(other) => return Err(format!("unsupported module field: {}", other)),
+            None => return Err("expected a module field".to_string()),
+        }
+    }
+
+    let mut func_names: Vec<String> = Vec::new();
+    for func in &funcs {
+        if let Some(name) = func.first().and_then(SExpr::atom).filter(|name| name.starts_with('$')) {
+            if func_names.iter().any(|other| other == name) {
+                return Err(format!("duplicate function: {}", name))
+            }
+            func_names.push(name.to_string());
+        }
+        validate_func(func, &globals, &mut exports)?;
+    }
+    if !exports.iter().any(|export| export == "eval") {
+        return Err("module does not export an eval function".to_string())
+    }
+    Ok(())
+}
+
+// ValType is a WebAssembly value type.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum ValType { I32, I64 }
+
+impl ValType {
+    fn parse(name: &str) -> Result<ValType, String> {
+        match name {
+            "i32" => Ok(ValType::I32),
+            "i64" => Ok(ValType::I64),
+            _ => Err(format!("unsupported value type: {}", name)),
+        }
+    }
+}
+
+// validate_global checks a (global $name (mut type) (type.const n)) field and
+// returns its name, type and mutability.
+fn validate_global(items: &[SExpr]) -> Result<(String, (ValType, bool)), String> {
+    if items.len() != 3 {
+        return Err("expected a name, type and initializer for global".to_string())
+    }
+    let name: &str = items[0].atom().filter(|name| name.starts_with('$')).ok_or("expected a global name")?;
+    let (ty, mutable): (ValType, bool) = match &items[1] {
+        SExpr::Atom(ty) => (ValType::parse(ty)?, false),
+        SExpr::List(mutable) if mutable.len() == 2 && mutable[0].atom() == Some("mut") => {
+            (ValType::parse(mutable[1].atom().ok_or("expected a global type")?)?, true)
+        },
+        _ => return Err(format!("malformed type for global {}", name)),
+    };
+    let init: &[SExpr] = items[2].list().ok_or("expected a constant initializer for global")?;
+    if init.len() != 2 || init[0].atom() != Some(const_instr(ty)) {
+        return Err(format!("initializer of global {} must be a {}", name, const_instr(ty)))
+    }
+    parse_const(ty, init[1].atom().unwrap_or(""))?;
+    Ok((name.to_string(), (ty, mutable)))
+}
+
+// validate_func checks the signature and body of a function, recording its
+// inline exports.
+fn validate_func(items: &[SExpr], globals: &HashMap<String, (ValType, bool)>, exports: &mut Vec<String>) -> Result<(), String> {
+    let mut locals: HashMap<String, ValType> = HashMap::new();
+    let mut results: Vec<ValType> = Vec::new();
+    let mut rest: &[SExpr] = items;
+    if rest.first().and_then(SExpr::atom).is_some_and(|name| name.starts_with('$')) {
+        rest = &rest[1..];
+    }
+
+    // The signature precedes the body, in the order exports, params, results,
+    // locals.
+    let mut stage: usize = 0;
+    while let Some(SExpr::List(field)) = rest.first() {
+        let kind: &str = field.first().and_then(SExpr::atom).unwrap_or("");
+        let field_stage: usize = match kind {
+            "export" => 0,
+            "param" => 1,
+            "result" => 2,
+            "local" => 3,
+            _ => return Err(format!("unexpected function field: {}", kind)),
+        };
+        if field_stage < stage {
+            return Err(format!("function field out of order: {}", kind))
+        }
+        stage = field_stage;
+        match kind {
+            "export" => match field.get(1) {
+                Some(SExpr::Str(name)) if field.len() == 2 => {
+                    if exports.contains(name) {
+                        return Err(format!("duplicate export: {}", name))
+                    }
+                    exports.push(name.clone())
+                },
+                _ => return Err("expected an export name".to_string()),
+            },
+            "result" => {
+                for ty in &field[1..] {
+                    results.push(ValType::parse(ty.atom().ok_or("expected a result type")?)?);
+                }
+            },
+            _ => declare_locals(&field[1..], &mut locals)?,
+        }
+        rest = &rest[1..];
+    }
+
+    let mut checker: Checker = Checker{ locals: &locals, globals, stack: Vec::new(), blocks: Vec::new(), unreachable: false };
+    checker.check_body(rest)?;
+    checker.finish(&results)
+}
+
+// declare_locals records the locals or params declared by either
+// (param $name type) or (param type ...).
+fn declare_locals(items: &[SExpr], locals: &mut HashMap<String, ValType>) -> Result<(), String> {
+    if let Some(name) = items.first().and_then(SExpr::atom).filter(|name| name.starts_with('$')) {
+        if items.len() != 2 {
+            return Err(format!("expected exactly one type for {}", name))
+        }
+        let ty: ValType = ValType::parse(items[1].atom().ok_or("expected a local type")?)?;
+        if locals.insert(name.to_string(), ty).is_some() {
+            return Err(format!("duplicate local: {}", name))
+        }
+        return Ok(())
+    }
+    for ty in items {
+        let ty: ValType = ValType::parse(ty.atom().ok_or("expected a local type")?)?;
+        locals.insert(locals.len().to_string(), ty);
+    }
+    Ok(())
+}
+
+// Checker simulates the operand stack of a function body.
+struct Checker<'a> {
+    locals: &'a HashMap<String, ValType>,
+    globals: &'a HashMap<String, (ValType, bool)>,
+    stack: Vec<ValType>,
+    // blocks holds, for each enclosing if, the stack height at its start and
+    // whether the code before it was unreachable.
+    blocks: Vec<(usize, bool)>,
+    // unreachable is set by return until the end of the enclosing block. The
+    // stack is then polymorphic: popping below the block's height succeeds.
+    unreachable: bool,
+}
+
+impl<'a> Checker<'a> {
+    fn check_body(&mut self, body: &[SExpr]) -> Result<(), String> {
+        let mut i: usize = 0;
+        while i < body.len() {
+            let name: &str = body[i].atom().ok_or("expected a flat instruction")?;
+            i += 1;
+            if IMMEDIATE_INSTRS.contains(&name) {
+                let immediate: &str = body.get(i).and_then(SExpr::atom)
+                    .ok_or_else(|| format!("expected an immediate for {}", name))?;
+                i += 1;
+                self.check_immediate(name, immediate)?;
+            } else {
+                self.check_instr(name)?;
+            }
+        }
+        if !self.blocks.is_empty() {
+            return Err("expected end of if block".to_string())
+        }
+        Ok(())
+    }
+
+    fn check_instr(&mut self, name: &str) -> Result<(), String> {
+        use ValType::{I32, I64};
+        match name {
+            "i32.add" | "i32.sub" | "i32.mul" | "i32.div_s" | "i32.and" | "i32.or" | "i32.eq" | "i32.ne" => self.op(&[I32, I32], &[I32]),
+            "i64.add" | "i64.sub" | "i64.mul" => self.op(&[I64, I64], &[I64]),
+            "i64.eq" | "i64.ne" => self.op(&[I64, I64], &[I32]),
+            "i32.eqz" => self.op(&[I32], &[I32]),
+            "i64.extend_i32_s" => self.op(&[I32], &[I64]),
+            "i32.wrap_i64" => self.op(&[I64], &[I32]),
+            "if" => {
+                self.op(&[I32], &[])?;
+                self.blocks.push((self.stack.len(), self.unreachable));
+                Ok(())
+            },
+            "else" | "end" => {
+                let (height, unreachable) = *self.blocks.last().ok_or_else(|| format!("{} outside of a block", name))?;
+                if !self.unreachable && self.stack.len() != height {
+                    return Err(format!("if block changes the stack height by {}", self.stack.len() as isize - height as isize))
+                }
+                self.stack.truncate(height);
+                self.unreachable = unreachable;
+                if name == "end" {
+                    self.blocks.pop();
+                }
+                Ok(())
+            },
+            "return" | "unreachable" => {
+                self.unreachable = true;
+                Ok(())
+            },
+            _ => Err(format!("unsupported instruction: {}", name)),
+        }
+    }
+
+    fn check_immediate(&mut self, name: &str, immediate: &str) -> Result<(), String> {
+        match name {
+            "i32.const" => parse_const(ValType::I32, immediate).and_then(|_| self.op(&[], &[ValType::I32])),
+            "i64.const" => parse_const(ValType::I64, immediate).and_then(|_| self.op(&[], &[ValType::I64])),
+            "local.get" | "local.set" | "local.tee" => {
+                let ty: ValType = *self.locals.get(immediate).ok_or_else(|| format!("unknown local: {}", immediate))?;
+                match name {
+                    "local.get" => self.op(&[], &[ty]),
+                    "local.set" => self.op(&[ty], &[]),
+                    _ => self.op(&[ty], &[ty]),
+                }
+            },
+            _ => {
+                let (ty, mutable) = *self.globals.get(immediate).ok_or_else(|| format!("unknown global: {}", immediate))?;
+                if name == "global.get" {
+                    return self.op(&[], &[ty])
+                }
+                if !mutable {
+                    return Err(format!("global {} is immutable", immediate))
+                }
+                self.op(&[ty], &[])
+            },
+        }
+    }
+
+    // op pops the given operand types (the last one from the top of the stack)
+    // and pushes the given result types.
+    fn op(&mut self, params: &[ValType], results: &[ValType]) -> Result<(), String> {
+        let height: usize = self.blocks.last().map_or(0, |(height, _)| *height);
+        for expected in params.iter().rev() {
+            if self.stack.len() > height {
+                let actual: ValType = self.stack.pop().unwrap();
+                if actual != *expected {
+                    return Err(format!("type mismatch: expected {:?} but found {:?}", expected, actual))
+                }
+            } else if !self.unreachable {
+                return Err(format!("stack underflow: expected {:?}", expected))
+            }
+        }
+        self.stack.extend_from_slice(results);
+        Ok(())
+    }
+
+    // finish checks that the body leaves exactly the function's results.
+    fn finish(&self, results: &[ValType]) -> Result<(), String> {
+        let matches: bool = if self.unreachable {
+            results.ends_with(&self.stack)
+        } else {
+            self.stack == results
+        };
+        if !matches {
+            return Err(format!("function body leaves {:?} but returns {:?}", self.stack, results))
+        }
+        Ok(())
+    }
+}
+
+// IMMEDIATE_INSTRS are the supported instructions that take one immediate.
+const IMMEDIATE_INSTRS: [&str; 7] = [
+    "i32.const", "i64.const", "local.get", "local.set", "local.tee", "global.get", "global.set",
+];
+
+fn const_instr(ty: ValType) -> &'static str {
+    match ty {
+        ValType::I32 => "i32.const",
+        ValType::I64 => "i64.const",
+    }
+}
+
+// parse_const checks that the given atom is a decimal integer literal of the
+// given type.
+fn parse_const(ty: ValType, literal: &str) -> Result<(), String> {
+    let valid: bool = match ty {
+        ValType::I32 => literal.parse::<i32>().is_ok() || literal.parse::<u32>().is_ok(),
+        ValType::I64 => literal.parse::<i64>().is_ok() || literal.parse::<u64>().is_ok(),
+    };
+    if !valid {
+        return Err(format!("invalid {:?} literal: {}", ty, literal))
+    }
+    Ok(())
+}
+
+// SExpr is a node of the text format's S-expression syntax.
+#[derive(PartialEq, Eq)]
+enum SExpr {
+    Atom(String),
+    Str(String),
+    List(Vec<SExpr>),
+}
+
+impl SExpr {
+    fn atom(&self) -> Option<&str> {
+        match self {
+            SExpr::Atom(atom) => Some(atom),
+            _ => None,
+        }
+    }
+
+    fn list(&self) -> Option<&[SExpr]> {
+        match self {
+            SExpr::List(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+// tokenize splits the given text into parentheses, strings (kept with their
+// quotes) and atoms, skipping whitespace and comments.
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens: Vec<String> = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i: usize = 0;
+    while i < chars.len() {
+        let ch: char = chars[i];
+        if ch.is_whitespace() {
+            i += 1;
+        } else if ch == ';' && chars.get(i + 1) == Some(&';') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if ch == '(' && chars.get(i + 1) == Some(&';') {
+            let mut depth: usize = 0;
+            loop {
+                if i + 1 >= chars.len() {
+                    return Err("unterminated block comment".to_string())
+                }
+                if chars[i] == '(' && chars[i + 1] == ';' {
+                    depth += 1;
+                    i += 2;
+                } else if chars[i] == ';' && chars[i + 1] == ')' {
+                    depth -= 1;
+                    i += 2;
+                    if depth == 0 {
+                        break
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+        } else if ch == '(' || ch == ')' {
+            tokens.push(ch.to_string());
+            i += 1;
+        } else if ch == '"' {
+            let start: usize = i;
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                i += if chars[i] == '\\' { 2 } else { 1 };
+            }
+            if i >= chars.len() {
+                return Err("unterminated string".to_string())
+            }
+            i += 1;
+            tokens.push(chars[start..i].iter().collect());
+        } else {
+            let start: usize = i;
+            while i < chars.len() && !chars[i].is_whitespace() && chars[i] != '(' && chars[i] != ')' {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        }
+    }
+    Ok(tokens)
+}
+
+// read_sexpr reads one S-expression starting at the given token.
+fn read_sexpr(tokens: &[String], position: &mut usize) -> Result<SExpr, String> {
+    let token: &String = tokens.get(*position).ok_or("unexpected end of input")?;
+    *position += 1;
+    if token == ")" {
+        return Err("unexpected closing parenthesis".to_string())
+    }
+    if token.starts_with('"') {
+        return Ok(SExpr::Str(token[1..token.len() - 1].to_string()))
+    }
+    if token != "(" {
+        return Ok(SExpr::Atom(token.clone()))
+    }
+    let mut items: Vec<SExpr> = Vec::new();
+    loop {
+        match tokens.get(*position).map(String::as_str) {
+            Some(")") => {
+                *position += 1;
+                return Ok(SExpr::List(items))
+            },
+            Some(_) => items.push(read_sexpr(tokens, position)?),
+            None => return Err("expected closing parenthesis".to_string()),
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{Expr, With, Binding, Id, Meta};
+use std::collections::{HashMap, HashSet};
+
+// cse returns an expression equivalent to the given one in which structurally
+// identical subexpressions are computed once and bound to fresh identifiers.
+// It is the inverse of With substitution:
+//   (+ (* (+ x 1) (+ x 1)) (* (+ x 1) (+ x 1)))
+//   =>
+//   (with ([tmpa (* (+ x 1) (+ x 1))]) (+ tmpa tmpa))
+pub fn cse(ast: &Expr) -> Expr {
+    let mut names: FreshNames = FreshNames::new(ast);
+    eliminate(ast.clone(), &mut names)
+}
+
+// eliminate hoists common subexpressions out of every scope in the given
+// expression.
+//
+// A scope is a maximal region of the tree that does not cross a With, a
+// function body or a branch of an If. Within a scope every identifier refers to
+// the same binding, so structurally identical subtrees are guaranteed to
+// evaluate to the same value, and every one of them is evaluated, so hoisting
+// them cannot introduce an error. Each With starts two new scopes: its bound
+// expression and its input.
+fn eliminate(expr: Expr, names: &mut FreshNames) -> Expr {
+    let expr: Expr = eliminate_nested(expr, names);
+    hoist(expr, names)
+}
+
+// eliminate_nested runs eliminate on the scopes introduced by the With, Fun
+// and If expressions directly reachable from the given scope.
+fn eliminate_nested(mut expr: Expr, names: &mut FreshNames) -> Expr {
+    match &mut expr {
+        Expr::With(node) => {
+            node.binding.replace = eliminate(node.binding.replace.take(), names);
+            node.input = eliminate(node.input.take(), names)
+        },
+        Expr::Fun(node) => node.body = eliminate(node.body.take(), names),
+        Expr::If(node) => {
+            node.condition = eliminate_nested(node.condition.take(), names);
+            node.then = eliminate(node.then.take(), names);
+            node.otherwise = eliminate(node.otherwise.take(), names)
+        },
+        _ => {
+            for input in expr.inputs_mut() {
+                *input = eliminate_nested(input.take(), names);
+            }
+        },
+    }
+    expr
+}
+
+// hoist repeatedly binds the largest profitable repeated subexpression of the
+// given scope to a fresh identifier, then wraps the scope in the resulting
+// With expressions.
+fn hoist(scope: Expr, names: &mut FreshNames) -> Expr {
+    let mut body: Expr = scope;
+    let mut bindings: Vec<Binding> = Vec::new();
+    while let Some(common) = find_common(&body, &bindings) {
+        let id: Id = names.fresh();
+        body = replace_common(body, &common, &id);
+        for binding in bindings.iter_mut() {
+            let replace: Expr = std::mem::replace(&mut binding.replace, id.clone().into());
+            binding.replace = replace_common(replace, &common, &id);
+        }
+        bindings.push(Binding{ identifier: Box::new(id), annotation: None, replace: common, meta: Meta::default() });
+    }
+
+    // Bindings that mention another fresh identifier must be nested inside
+    // the With that binds it.
+    let mut result: Expr = body;
+    for binding in order_bindings(bindings).into_iter().rev() {
+        result = With{ binding, input: result, meta: Meta::default() }.into();
+    }
+    result
+}
+
+// find_common returns the largest subexpression that occurs often enough in the
+// scope (including the expressions already hoisted out of it) that binding it
+// to an identifier makes the tree smaller. Ties are broken by the first
+// occurrence in a pre-order traversal.
+fn find_common(body: &Expr, bindings: &[Binding]) -> Option<Expr> {
+    let mut counts: HashMap<&Expr, usize> = HashMap::new();
+    let mut order: Vec<&Expr> = Vec::new();
+    count_subexprs(body, &mut counts, &mut order);
+    for binding in bindings {
+        count_subexprs(&binding.replace, &mut counts, &mut order);
+    }
+
+    let mut best: Option<(&Expr, usize)> = None;
+    for expr in order {
+        let size: usize = size(expr);
+        if !is_profitable(size, counts[expr]) {
+            continue
+        }
+        match best {
+            Some((_, best_size)) if best_size >= size => {},
+            _ => best = Some((expr, size)),
+        }
+    }
+    best.map(|(expr, _)| expr.clone())
+}
+
+// count_subexprs records how many times each compound subexpression of the
+// given scope occurs, without descending into nested scopes.
+fn count_subexprs<'a>(expr: &'a Expr, counts: &mut HashMap<&'a Expr, usize>, order: &mut Vec<&'a Expr>) {
+    if expr.is_leaf() {
+        return
+    }
+    let count: &mut usize = counts.entry(expr).or_insert(0);
+    if *count == 0 {
+        order.push(expr)
+    }
+    *count += 1;
+    for input in scope_inputs(expr) {
+        count_subexprs(input, counts, order);
+    }
+}
+
+// replace_common replaces every occurrence of the given subexpression in the
+// scope with the given identifier.
+fn replace_common(mut expr: Expr, common: &Expr, id: &Id) -> Expr {
+    if expr == *common {
+        return id.clone().into()
+    }
+    let inputs: usize = scope_inputs(&expr).len();
+    for input in expr.inputs_mut().into_iter().take(inputs) {
+        *input = replace_common(input.take(), common, id);
+    }
+    expr
+}
+
+// scope_inputs returns the inputs of the given node that are in the same scope
+// as the node. They are always a prefix of its inputs.
+fn scope_inputs(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::With(_) | Expr::Fun(_) => Vec::new(),
+        Expr::If(expr) => vec![&expr.condition],
+        _ => expr.inputs(),
+    }
+}
+
+// order_bindings sorts the hoisted bindings from outermost to innermost so that
+// every binding is in scope wherever it is mentioned.
+fn order_bindings(mut pending: Vec<Binding>) -> Vec<Binding> {
+    let mut ordered: Vec<Binding> = Vec::new();
+    while !pending.is_empty() {
+        let next: usize = pending.iter()
+            .position(|binding| !pending.iter().any(|other| mentions(&binding.replace, &other.identifier)))
+            .expect("hoisted bindings cannot be mutually dependent");
+        ordered.push(pending.remove(next));
+    }
+    ordered
+}
+
+// mentions returns true if the given identifier occurs anywhere in the given
+// expression.
+pub(crate) fn mentions(expr: &Expr, id: &Id) -> bool {
+    // Explicit stack, as subst.rs checks bound expressions however deep they
+    // are.
+    let mut stack: Vec<&Expr> = vec![expr];
+    while let Some(expr) = stack.pop() {
+        match expr {
+            Expr::Id(expr) if expr.val == id.val => return true,
+            _ => stack.extend(expr.inputs()),
+        }
+    }
+    false
+}
+
+// size returns the number of nodes in the given expression.
+pub(crate) fn size(expr: &Expr) -> usize {
+    // Explicit stack, as limits.rs sizes whole programs however deep they are.
+    let mut size: usize = 0;
+    let mut stack: Vec<&Expr> = vec![expr];
+    while let Some(expr) = stack.pop() {
+        // With and Fun also count the identifier they bind.
+        size += match expr {
+            Expr::With(_) | Expr::Fun(_) => 2,
+            _ => 1,
+        };
+        stack.extend(expr.inputs());
+    }
+    size
+}
+
+// is_profitable returns true if binding a subexpression of the given size that
+// occurs the given number of times shrinks the tree. Hoisting costs a With, the
+// bound identifier, one copy of the subexpression and one identifier per use.
+fn is_profitable(size: usize, count: usize) -> bool {
+    count >= 2 && count * size > size + count + 2
+}
+
+// FreshNames generates identifiers that do not occur anywhere in a given
+// expression, so that hoisting (or renaming, in step.rs) can never capture or
+// shadow a user binding.
+pub(crate) struct FreshNames {
+    used: HashSet<String>,
+    next: usize,
+}
+
+impl FreshNames {
+    pub(crate) fn new(ast: &Expr) -> FreshNames {
+        let mut used: HashSet<String> = HashSet::new();
+        collect_names(ast, &mut used);
+        FreshNames{ used, next: 0 }
+    }
+
+    // reserve marks every identifier in the given expression as used too.
+    pub(crate) fn reserve(&mut self, expr: &Expr) {
+        collect_names(expr, &mut self.used)
+    }
+
+    // fresh returns the next unused identifier: tmpa, tmpb, ..., tmpz, tmpaa...
+    pub(crate) fn fresh(&mut self) -> Id {
+        loop {
+            let mut suffix: String = String::new();
+            let mut n: usize = self.next;
+            loop {
+                suffix.insert(0, (b'a' + (n % 26) as u8) as char);
+                if n < 26 {
+                    break
+                }
+                n = n / 26 - 1;
+            }
+            self.next += 1;
+            let val: String = format!("tmp{}", suffix);
+            if self.used.insert(val.clone()) {
+                return Id{ val, meta: Meta::default() }
+            }
+        }
+    }
+}
+
+// collect_names adds every identifier in the given expression to the set.
+fn collect_names(expr: &Expr, names: &mut HashSet<String>) {
+    let mut stack: Vec<&Expr> = vec![expr];
+    while let Some(expr) = stack.pop() {
+        match expr {
+            Expr::With(expr) => {
+                names.insert(expr.binding.identifier.val.clone());
+            },
+            Expr::Fun(expr) => {
+                names.insert(expr.param.val.clone());
+            },
+            Expr::Id(expr) => {
+                names.insert(expr.val.clone());
+            },
+            _ => {},
+        }
+        stack.extend(expr.inputs());
+    }
+}
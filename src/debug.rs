@@ -0,0 +1,295 @@
+use crate::{Expr, Id};
+use crate::calc::{unbound_identifier, unsupported};
+use crate::reader::Span;
+use crate::unparse::to_source;
+use std::cell::RefCell;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+// debug evaluates the given abstract syntax tree under the control of commands
+// read line by line from the given input, writing the debugger's responses to
+// the given output, and returns the value of the tree.
+//
+// The session starts paused at the root. While paused, the commands are:
+//
+//   step, s            pause at the next node to be evaluated
+//   next, n            pause at the next node that is not inside this one
+//   finish, f          pause once the current node has a value
+//   continue, c        run until a breakpoint is reached
+//   break LINE:COL     pause at the node that starts at the given location
+//   break NAME         pause where NAME is bound or looked up
+//   delete N           remove the Nth breakpoint
+//   breakpoints        list the breakpoints
+//   env, e             print the With bindings in scope, innermost first
+//   where, w           print the node that is about to be evaluated
+//   quit, q            stop evaluating
+//
+// Evaluation uses an environment of With bindings rather than substitution. A
+// bound expression is evaluated the first time its identifier is looked up,
+// in the environment where it was bound, so the result matches calc. At the end
+// of the input the session continues without pausing. Only the arithmetic
+// language can be debugged: any other expression fails when it is reached.
+pub fn debug(ast: &Expr, input: &mut dyn BufRead, output: &mut dyn Write) -> Result<i32, String> {
+    let mut debugger: Debugger = Debugger{ input, output, breakpoints: Vec::new(), mode: Mode::Step, detached: false };
+    debugger.eval(ast, &None, 0)
+}
+
+// Breakpoint is a condition under which evaluation pauses.
+pub enum Breakpoint {
+    // At matches the node whose source text starts at the given line and
+    // column.
+    At(usize, usize),
+    // Identifier matches a With that binds the name and every lookup of it.
+    Identifier(String),
+}
+
+// Mode decides where evaluation next pauses, regardless of breakpoints.
+#[derive(Copy, Clone)]
+enum Mode {
+    // Step pauses at the next node.
+    Step,
+    // Next pauses at the next node at the given depth or above.
+    Next(usize),
+    // Finish pauses when a node at the given depth has been evaluated.
+    Finish(usize),
+    // Continue pauses only at breakpoints.
+    Continue,
+}
+
+// Env is the chain of With bindings in scope, innermost first.
+type Env = Option<Rc<Frame>>;
+
+// Frame is a single With binding. Its value is computed on first lookup, in
+// the environment of the enclosing bindings.
+struct Frame {
+    name:   String,
+    expr:   Expr,
+    value:  RefCell<Option<Result<i32, String>>>,
+    parent: Env,
+}
+
+struct Debugger<'a> {
+    input:       &'a mut dyn BufRead,
+    output:      &'a mut dyn Write,
+    breakpoints: Vec<Breakpoint>,
+    mode:        Mode,
+    // detached is set once the input is exhausted.
+    detached:    bool,
+}
+
+impl<'a> Debugger<'a> {
+    // eval evaluates the given node in the given environment. Depth counts the
+    // nodes being evaluated around this one, including bound expressions that
+    // are being forced by a lookup.
+    fn eval(&mut self, expr: &Expr, env: &Env, depth: usize) -> Result<i32, String> {
+        let paused: bool = self.pause(expr, env, depth)?;
+        let result: Result<i32, String> = match expr {
+            Expr::Number(expr) => Ok(expr.val),
+            Expr::Binary(expr) => {
+                let left: i32 = self.eval(&expr.left, env, depth + 1)?;
+                let right: i32 = self.eval(&expr.right, env, depth + 1)?;
+                expr.op.apply(left, right)
+            },
+            Expr::With(expr) => {
+                let frame: Frame = Frame{
+                    name: expr.binding.identifier.val.clone(),
+                    expr: expr.binding.replace.clone(),
+                    value: RefCell::new(None),
+                    parent: env.clone(),
+                };
+                self.eval(&expr.input, &Some(Rc::new(frame)), depth + 1)
+            },
+            Expr::Id(id) => self.lookup(id, env, depth),
+            Expr::Error(expr) => Err(expr.message.clone()),
+            _ => Err(unsupported(expr, "the debugger")),
+        };
+        let finished: bool = matches!(self.mode, Mode::Finish(d) if depth <= d);
+        if (paused || finished) && !self.detached {
+            match &result {
+                Ok(val) => self.print(&format!("{} => {}", describe(expr), val)),
+                Err(msg) => self.print(&format!("{} => error: {}", describe(expr), msg)),
+            }
+            if finished {
+                self.mode = Mode::Step;
+            }
+        }
+        result
+    }
+
+    // lookup returns the value bound to the given identifier, evaluating the
+    // bound expression if this is its first use.
+    fn lookup(&mut self, id: &Id, env: &Env, depth: usize) -> Result<i32, String> {
+        let mut current: &Env = env;
+        while let Some(frame) = current {
+            if frame.name == id.val {
+                if let Some(value) = frame.value.borrow().clone() {
+                    return value
+                }
+                let value: Result<i32, String> = self.eval(&frame.expr, &frame.parent, depth + 1);
+                *frame.value.borrow_mut() = Some(value.clone());
+                return value
+            }
+            current = &frame.parent;
+        }
+        Err(unbound_identifier(id))
+    }
+
+    // pause reads and runs commands if evaluation should pause before the
+    // given node, and returns whether it paused.
+    fn pause(&mut self, expr: &Expr, env: &Env, depth: usize) -> Result<bool, String> {
+        if self.detached {
+            return Ok(false)
+        }
+        let stop: bool = match self.mode {
+            Mode::Step => true,
+            Mode::Next(d) => depth <= d,
+            Mode::Finish(_) | Mode::Continue => false,
+        };
+        let hit: Option<usize> = self.breakpoints.iter().position(|breakpoint| breakpoint.matches(expr));
+        if !stop && hit.is_none() {
+            return Ok(false)
+        }
+        if let Some(i) = hit {
+            self.print(&format!("Breakpoint {}", i + 1));
+        }
+        self.print(&format!("Paused at {}", describe(expr)));
+        loop {
+            let mut line: String = String::new();
+            match self.input.read_line(&mut line) {
+                Ok(0) | Err(_) => {
+                    self.detached = true;
+                    return Ok(true)
+                },
+                Ok(_) => {},
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {},
+                ["step"] | ["s"] => {
+                    self.mode = Mode::Step;
+                    return Ok(true)
+                },
+                ["next"] | ["n"] => {
+                    self.mode = Mode::Next(depth);
+                    return Ok(true)
+                },
+                ["finish"] | ["f"] => {
+                    self.mode = Mode::Finish(depth);
+                    return Ok(true)
+                },
+                ["continue"] | ["c"] => {
+                    self.mode = Mode::Continue;
+                    return Ok(true)
+                },
+                ["break", target] | ["b", target] => match Breakpoint::parse(target) {
+                    Ok(breakpoint) => {
+                        self.breakpoints.push(breakpoint);
+                        self.print(&format!("Breakpoint {} set", self.breakpoints.len()))
+                    },
+                    Err(msg) => self.print(&format!("Error: {}", msg)),
+                },
+                ["delete", n] | ["d", n] => match n.parse::<usize>() {
+                    Ok(n) if n >= 1 && n <= self.breakpoints.len() => {
+                        self.breakpoints.remove(n - 1);
+                        self.print(&format!("Breakpoint {} deleted", n))
+                    },
+                    _ => self.print(&format!("Error: no breakpoint {}", n)),
+                },
+                ["breakpoints"] => {
+                    let lines: Vec<String> = self.breakpoints.iter().enumerate()
+                        .map(|(i, breakpoint)| format!("{}: {}", i + 1, breakpoint.describe()))
+                        .collect();
+                    if lines.is_empty() {
+                        self.print("No breakpoints")
+                    }
+                    for line in lines {
+                        self.print(&line)
+                    }
+                },
+                ["env"] | ["e"] => self.print_env(env),
+                ["where"] | ["w"] => self.print(&describe(expr)),
+                ["quit"] | ["q"] => return Err(DEBUGGER_QUIT.to_string()),
+                _ => self.print(&format!("Error: unknown command: {}", line.trim())),
+            }
+        }
+    }
+
+    // print_env prints the bindings in the given environment, innermost first.
+    // A binding that is shadowed by a more recent one is marked as such.
+    fn print_env(&mut self, env: &Env) {
+        let mut lines: Vec<String> = Vec::new();
+        let mut seen: Vec<&str> = Vec::new();
+        let mut current: &Env = env;
+        while let Some(frame) = current {
+            let value: String = match &*frame.value.borrow() {
+                Some(Ok(val)) => val.to_string(),
+                Some(Err(msg)) => format!("error: {}", msg),
+                None => format!("not yet evaluated: {}", to_source(&frame.expr)),
+            };
+            let shadowed: &str = if seen.contains(&frame.name.as_str()) { " (shadowed)" } else { "" };
+            lines.push(format!("{} = {}{}", frame.name, value, shadowed));
+            seen.push(&frame.name);
+            current = &frame.parent;
+        }
+        if lines.is_empty() {
+            lines.push("No bindings".to_string())
+        }
+        for line in lines {
+            self.print(&line)
+        }
+    }
+
+    fn print(&mut self, text: &str) {
+        let _ = writeln!(self.output, "{}", text);
+    }
+}
+
+impl Breakpoint {
+    // parse reads a breakpoint written as LINE:COL or as an identifier.
+    fn parse(text: &str) -> Result<Breakpoint, String> {
+        if let Some((line, column)) = text.split_once(':') {
+            return match (line.parse::<usize>(), column.parse::<usize>()) {
+                (Ok(line), Ok(column)) => Ok(Breakpoint::At(line, column)),
+                _ => Err(format!("invalid location: {}", text)),
+            }
+        }
+        if !text.chars().all(char::is_alphabetic) {
+            return Err(format!("expected a location or an identifier: {}", text))
+        }
+        Ok(Breakpoint::Identifier(text.to_string()))
+    }
+
+    // matches returns true if evaluation should pause before the given node.
+    fn matches(&self, expr: &Expr) -> bool {
+        match self {
+            Breakpoint::At(line, column) => {
+                let span: Span = expr.meta().span;
+                span.line == *line && span.column == *column
+            },
+            Breakpoint::Identifier(name) => match expr {
+                Expr::Id(id) => id.val == *name,
+                Expr::With(with) => with.binding.identifier.val == *name,
+                _ => false,
+            },
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Breakpoint::At(line, column) => format!("at {}:{}", line, column),
+            Breakpoint::Identifier(name) => format!("on {}", name),
+        }
+    }
+}
+
+// describe returns the location and source text of the given node.
+fn describe(expr: &Expr) -> String {
+    let span: Span = expr.meta().span;
+    if span.line == 0 {
+        return to_source(expr)
+    }
+    format!("{}: {}", span, to_source(expr))
+}
+
+// DEBUGGER_QUIT is the error returned when the session is ended by a command.
+pub(crate) const DEBUGGER_QUIT: &str = "debugging session ended";
@@ -0,0 +1,107 @@
+use crate::Expr;
+use crate::pretty_print::Printable;
+
+// DotOptions configures the Graphviz rendering of a tree.
+pub struct DotOptions {
+    // clusters draws a box around the subtree of every With, labelled with the
+    // identifier it binds.
+    pub clusters: bool,
+}
+
+// to_dot returns a Graphviz DOT digraph of the given abstract syntax tree. Nodes
+// are labelled with their Printable name and value, and edges with the role of
+// the child (left, right, binding, body, ...).
+pub fn to_dot(ast: &Expr, options: &DotOptions) -> String {
+    let mut writer: DotWriter = DotWriter{ options, nodes: 0, clusters: 0, edges: Vec::new() };
+    let mut dot: String = String::new();
+    dot.push_str("digraph ast {\n");
+    dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+    writer.write_node(ast, &mut dot, 1);
+    for edge in &writer.edges {
+        dot.push_str("    ");
+        dot.push_str(edge);
+        dot.push('\n');
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+// DotWriter numbers nodes and clusters while the tree is walked. Node
+// declarations are nested inside their cluster, while edges are collected and
+// written at the top level.
+struct DotWriter<'a> {
+    options: &'a DotOptions,
+    nodes: usize,
+    clusters: usize,
+    edges: Vec<String>,
+}
+
+impl<'a> DotWriter<'a> {
+    // write_node declares the given node and its descendants. The tree is
+    // walked with an explicit stack of borrowed nodes, so that deep trees
+    // neither overflow the call stack nor are copied.
+    fn write_node(&mut self, root: &dyn Printable, dot: &mut String, depth: usize) {
+        enum Task<'b> {
+            // Visit declares a node, at the given indentation, that is the
+            // child of the given node along the edge with the given label.
+            Visit(&'b dyn Printable, Option<(String, &'static str)>, usize),
+            // Close ends the cluster of a With, which was opened at the given
+            // indentation.
+            Close(usize),
+            // Edge records an edge once its child's subtree is declared.
+            Edge(String),
+        }
+        let mut tasks: Vec<Task> = vec![Task::Visit(root, None, depth)];
+        while let Some(task) = tasks.pop() {
+            let (node, parent, mut depth) = match task {
+                Task::Visit(node, parent, depth) => (node, parent, depth),
+                Task::Close(depth) => {
+                    indent(dot, depth);
+                    dot.push_str("}\n");
+                    continue
+                },
+                Task::Edge(edge) => {
+                    self.edges.push(edge);
+                    continue
+                },
+            };
+            let id: String = format!("n{}", self.nodes);
+            self.nodes += 1;
+            if let Some((parent, label)) = parent {
+                tasks.push(Task::Edge(format!("{} -> {} [label=\"{}\"];", parent, id, label)));
+            }
+
+            if let Some(identifier) = node.scope().filter(|_| self.options.clusters) {
+                indent(dot, depth);
+                dot.push_str(&format!("subgraph cluster_{} {{\n", self.clusters));
+                tasks.push(Task::Close(depth));
+                self.clusters += 1;
+                depth += 1;
+                indent(dot, depth);
+                dot.push_str(&format!("label=\"with {}\"; style=dashed;\n", escape(&identifier)));
+            }
+
+            let label: String = match node.detail() {
+                Some(detail) => format!("{}: {}", node.name(), detail),
+                None => node.name(),
+            };
+            indent(dot, depth);
+            dot.push_str(&format!("{} [label=\"{}\"];\n", id, escape(&label)));
+
+            let labels: Vec<&'static str> = node.edge_labels();
+            for (i, child) in node.children().into_iter().enumerate().rev() {
+                let label: &'static str = labels.get(i).copied().unwrap_or("");
+                tasks.push(Task::Visit(child, Some((id.clone(), label)), depth));
+            }
+        }
+    }
+}
+
+fn indent(dot: &mut String, depth: usize) {
+    dot.push_str(&"    ".repeat(depth))
+}
+
+// escape escapes the given text for use in a quoted DOT string.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,352 @@
+use crate::{Expr, Binary, With, Binding, Id};
+use crate::ast::{If, Call, Fun, App, Annotation, Record, Field, Get};
+use crate::parse::{OPEN_PAREN, CLOSE_PAREN, OPEN_BRACE, CLOSE_BRACE, WITH_OP, IF_OP, COLON, RECORD_OP, GET_OP, DEFINE_OP};
+use crate::program::{parse_program, Program, Form, Define};
+use crate::reader::{Comment, CommentKind, Trivia};
+use crate::unparse::{Unparsable, unparse_param};
+
+// format parses the given program and re-indents it Lisp-style so that lines
+// fit within the given width where possible:
+//
+//   (define y (* (+ a b)
+//                (- a b)))
+//   (with ([x y])
+//     (+ x
+//        1))
+//
+// Each definition and expression of the program starts on a new line. An
+// expression that fits on the rest of its line is printed on one line.
+// Otherwise the arguments of an operator are placed on separate lines, aligned
+// after the operator, and the input of a With, the value of a definition or
+// the body of a function is indented by two spaces below its binding, name or
+// parameter. Comments are kept next to the node they are attached to, and an
+// expression that contains a line comment is always broken over several lines.
+// Layout depends only on the parsed tree, so formatting is idempotent.
+pub fn format(source: &str, width: usize) -> Result<String, String> {
+    let program: Program = parse_program(source)?;
+    let mut writer: Writer = Writer{ out: String::new(), line_ended: false };
+    for form in &program.forms {
+        match form {
+            Form::Define(define) => define.layout(&mut writer, width),
+            Form::Expr(expr) => expr.layout(&mut writer, width),
+        }
+        writer.newline(0)
+    }
+    Ok(writer.out)
+}
+
+// Writer accumulates formatted text. After a line comment, the line is ended
+// and the next token has to start on a new line.
+pub(crate) struct Writer {
+    out: String,
+    line_ended: bool,
+}
+
+impl Writer {
+    // column returns the column that the next character will be written at.
+    fn column(&self) -> usize {
+        match self.out.rfind('\n') {
+            Some(i) => self.out[i + 1..].chars().count(),
+            None => self.out.chars().count(),
+        }
+    }
+
+    fn push(&mut self, c: char) {
+        self.out.push(c)
+    }
+
+    fn push_str(&mut self, text: &str) {
+        self.out.push_str(text)
+    }
+
+    // newline starts a new line indented to the given column.
+    fn newline(&mut self, column: usize) {
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(column));
+        self.line_ended = false
+    }
+
+    // separate writes the space between two tokens on a line, or starts a new
+    // line indented to the given column if the current line has ended.
+    fn separate(&mut self, column: usize) {
+        if self.line_ended {
+            self.newline(column)
+        } else {
+            self.out.push(' ')
+        }
+    }
+
+    // close writes a closing delimiter, on a new line indented to the given
+    // column if the current line has ended.
+    fn close(&mut self, c: char, column: usize) {
+        if self.line_ended {
+            self.newline(column)
+        }
+        self.out.push(c)
+    }
+
+    // leading writes the comments that lead a node starting at the given
+    // column.
+    fn leading(&mut self, trivia: &Trivia, column: usize) {
+        for comment in &trivia.leading {
+            self.out.push_str(&comment.text);
+            if comment.kind == CommentKind::Line {
+                self.newline(column)
+            } else {
+                self.out.push(' ')
+            }
+        }
+    }
+
+    // trailing writes the comments that trail a node starting at the given
+    // column.
+    fn trailing(&mut self, trivia: &Trivia, column: usize) {
+        self.after(&trivia.trailing, column)
+    }
+
+    // inner writes the inner comments of a node, after its inputs, which
+    // start at the given column.
+    fn inner(&mut self, trivia: &Trivia, column: usize) {
+        self.after(&trivia.inner, column)
+    }
+
+    // after writes comments that follow a token on its line.
+    fn after(&mut self, comments: &[Comment], column: usize) {
+        for comment in comments {
+            self.separate(column);
+            self.out.push_str(&comment.text);
+            self.line_ended = comment.kind == CommentKind::Line;
+        }
+    }
+}
+
+// A type that implements Layout can write a formatted rendering of itself.
+pub(crate) trait Layout: Unparsable {
+    // layout writes this node and its comments, starting at the writer's
+    // current column. Lines after the first are indented to absolute columns.
+    fn layout(&self, writer: &mut Writer, width: usize) {
+        let column: usize = writer.column();
+        let trivia: Option<&Trivia> = self.trivia();
+        if let Some(trivia) = trivia {
+            writer.leading(trivia, column);
+        }
+        let mut flat: String = String::new();
+        self.unparse_node(&mut flat);
+        if !flat.contains('\n') && writer.column() + flat.len() <= width {
+            writer.push_str(&flat)
+        } else {
+            self.layout_broken(writer, width)
+        }
+        if let Some(trivia) = trivia {
+            writer.trailing(trivia, column);
+        }
+    }
+
+    // layout_broken writes this node, without its own comments, split over
+    // several lines.
+    fn layout_broken(&self, writer: &mut Writer, width: usize);
+}
+
+impl Layout for Expr {
+    fn layout_broken(&self, writer: &mut Writer, width: usize) {
+        match self {
+            Expr::Binary(expr) => expr.layout_broken(writer, width),
+            Expr::With(expr) => expr.layout_broken(writer, width),
+            Expr::If(expr) => expr.layout_broken(writer, width),
+            Expr::Call(expr) => expr.layout_broken(writer, width),
+            Expr::Fun(expr) => expr.layout_broken(writer, width),
+            Expr::App(expr) => expr.layout_broken(writer, width),
+            Expr::Record(expr) => expr.layout_broken(writer, width),
+            Expr::Get(expr) => expr.layout_broken(writer, width),
+            // Atoms cannot be broken.
+            _ => {
+                let mut text: String = String::new();
+                self.unparse_node(&mut text);
+                writer.push_str(&text)
+            },
+        }
+    }
+}
+
+impl Layout for Binary {
+    fn layout_broken(&self, writer: &mut Writer, width: usize) {
+        let column: usize = writer.column();
+        writer.push(OPEN_PAREN);
+        self.op.unparse(&mut writer.out);
+        writer.push(' ');
+        let arg_column: usize = writer.column();
+        self.left.layout(writer, width);
+        writer.newline(arg_column);
+        self.right.layout(writer, width);
+        writer.close(CLOSE_PAREN, column)
+    }
+}
+
+impl Layout for With {
+    fn layout_broken(&self, writer: &mut Writer, width: usize) {
+        let column: usize = writer.column();
+        writer.push(OPEN_PAREN);
+        writer.push_str(WITH_OP);
+        writer.push(' ');
+        self.binding.layout(writer, width);
+        writer.newline(column + 2);
+        self.input.layout(writer, width);
+        writer.close(CLOSE_PAREN, column)
+    }
+}
+
+impl Layout for Binding {
+    fn layout_broken(&self, writer: &mut Writer, width: usize) {
+        // The bound expression is aligned after the identifier, or placed on
+        // the next line if a comment ends the identifier's line.
+        let column: usize = writer.column();
+        writer.push(OPEN_PAREN);
+        writer.push(OPEN_BRACE);
+        self.identifier.layout(writer, width);
+        if let Some(annotation) = &self.annotation {
+            writer.separate(column + 2);
+            writer.push_str(COLON);
+            writer.push(' ');
+            annotation.layout(writer, width);
+        }
+        writer.separate(column + 2);
+        self.replace.layout(writer, width);
+        writer.close(CLOSE_BRACE, column + 1);
+        writer.close(CLOSE_PAREN, column)
+    }
+}
+
+impl Layout for Annotation {
+    // layout_broken writes the type as it is printed flat, since types are
+    // never broken over lines.
+    fn layout_broken(&self, writer: &mut Writer, _: usize) {
+        self.unparse_node(&mut writer.out)
+    }
+}
+
+impl Layout for If {
+    fn layout_broken(&self, writer: &mut Writer, width: usize) {
+        let column: usize = writer.column();
+        writer.push(OPEN_PAREN);
+        writer.push_str(IF_OP);
+        writer.push(' ');
+        let arg_column: usize = writer.column();
+        self.condition.layout(writer, width);
+        writer.newline(arg_column);
+        self.then.layout(writer, width);
+        writer.newline(arg_column);
+        self.otherwise.layout(writer, width);
+        writer.close(CLOSE_PAREN, column)
+    }
+}
+
+impl Layout for Call {
+    fn layout_broken(&self, writer: &mut Writer, width: usize) {
+        let column: usize = writer.column();
+        writer.push(OPEN_PAREN);
+        self.prim.unparse(&mut writer.out);
+        if !self.args.is_empty() {
+            writer.push(' ');
+        }
+        let arg_column: usize = writer.column();
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                writer.newline(arg_column);
+            }
+            arg.layout(writer, width);
+        }
+        writer.inner(&self.meta.trivia, arg_column);
+        writer.close(CLOSE_PAREN, column)
+    }
+}
+
+impl Layout for Fun {
+    fn layout_broken(&self, writer: &mut Writer, width: usize) {
+        let column: usize = writer.column();
+        unparse_param(self, &mut writer.out);
+        writer.newline(column + 2);
+        self.body.layout(writer, width);
+        writer.close(CLOSE_PAREN, column)
+    }
+}
+
+impl Layout for App {
+    fn layout_broken(&self, writer: &mut Writer, width: usize) {
+        // The argument is aligned with the function.
+        let column: usize = writer.column();
+        writer.push(OPEN_PAREN);
+        self.fun.layout(writer, width);
+        writer.newline(column + 1);
+        self.arg.layout(writer, width);
+        writer.close(CLOSE_PAREN, column)
+    }
+}
+
+impl Layout for Record {
+    fn layout_broken(&self, writer: &mut Writer, width: usize) {
+        // The fields are aligned after the keyword.
+        let column: usize = writer.column();
+        writer.push(OPEN_PAREN);
+        writer.push_str(RECORD_OP);
+        if !self.fields.is_empty() {
+            writer.push(' ');
+        }
+        let field_column: usize = writer.column();
+        for (i, field) in self.fields.iter().enumerate() {
+            if i > 0 {
+                writer.newline(field_column);
+            }
+            field.layout(writer, width);
+        }
+        writer.inner(&self.meta.trivia, field_column);
+        writer.close(CLOSE_PAREN, column)
+    }
+}
+
+impl Layout for Field {
+    fn layout_broken(&self, writer: &mut Writer, width: usize) {
+        // The value is aligned after the name, as in a Binding.
+        let column: usize = writer.column();
+        writer.push(OPEN_BRACE);
+        self.name.layout(writer, width);
+        writer.separate(column + 1);
+        self.value.layout(writer, width);
+        writer.close(CLOSE_BRACE, column)
+    }
+}
+
+impl Layout for Get {
+    fn layout_broken(&self, writer: &mut Writer, width: usize) {
+        let column: usize = writer.column();
+        writer.push(OPEN_PAREN);
+        writer.push_str(GET_OP);
+        writer.push(' ');
+        let arg_column: usize = writer.column();
+        self.record.layout(writer, width);
+        writer.newline(arg_column);
+        self.field.layout(writer, width);
+        writer.close(CLOSE_PAREN, column)
+    }
+}
+
+impl Layout for Define {
+    fn layout_broken(&self, writer: &mut Writer, width: usize) {
+        let column: usize = writer.column();
+        writer.push(OPEN_PAREN);
+        writer.push_str(DEFINE_OP);
+        writer.push(' ');
+        self.name.layout(writer, width);
+        writer.newline(column + 2);
+        self.value.layout(writer, width);
+        writer.close(CLOSE_PAREN, column)
+    }
+}
+
+impl Layout for Id {
+    fn layout_broken(&self, writer: &mut Writer, _: usize) {
+        writer.push_str(&self.val)
+    }
+}
+
+// DEFAULT_WIDTH is the line width used when none is configured.
+pub const DEFAULT_WIDTH: usize = 80;
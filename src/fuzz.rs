@@ -0,0 +1,144 @@
+use crate::Expr;
+use crate::bytecode::compile;
+use crate::calc::calc_with_limits;
+use crate::generate::{random_expr, GenOptions, Rng};
+use crate::limits::{EvalLimits, is_limit_exceeded};
+use crate::parse::{parse, parse_with_recovery};
+use crate::unparse::to_source;
+use crate::vm::run;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+// FuzzReport summarises a fuzzing run.
+pub struct FuzzReport {
+    // cases counts the sources checked: each generated program and each
+    // mutation of it.
+    pub cases:     usize,
+    // parsed counts the sources that parsed.
+    pub parsed:    usize,
+    // evaluated counts the parsed sources in the arithmetic language whose
+    // evaluation finished within FUZZ_LIMITS, so that both evaluators could be
+    // compared.
+    pub evaluated: usize,
+    // failures describes each broken invariant, with the source that broke it.
+    pub failures:  Vec<String>,
+}
+
+// fuzz checks the invariants of the parser and evaluators on the given number
+// of random programs, made by random_expr with the given options, and on a
+// random mutation of the source text of each. For every source:
+//
+//   - neither parse nor parse_with_recovery panics, and they agree on whether
+//     the source has errors;
+//   - a tree that parses prints as source text that parses to the same tree
+//     and prints the same way again;
+//   - evaluation by substitution (calc) and direct evaluation (the bytecode
+//     VM) give the same value or the same error, if the tree is in the
+//     arithmetic language that the VM covers.
+//
+// The run is deterministic for a given seed.
+pub fn fuzz(seed: u64, cases: usize, options: &GenOptions) -> FuzzReport {
+    let mut rng: Rng = Rng::new(seed);
+    let mut report: FuzzReport = FuzzReport{ cases: 0, parsed: 0, evaluated: 0, failures: Vec::new() };
+    for _ in 0..cases {
+        let source: String = to_source(&random_expr(&mut rng, options));
+        let mutant: String = mutate(&mut rng, &source);
+        check(&source, &mut report);
+        check(&mutant, &mut report);
+    }
+    report
+}
+
+// check checks the invariants on the given source text and records the result
+// in the given report.
+fn check(source: &str, report: &mut FuzzReport) {
+    report.cases += 1;
+    let parsed = catch_unwind(AssertUnwindSafe(|| parse(source.to_string())));
+    let recovered = catch_unwind(AssertUnwindSafe(|| parse_with_recovery(source)));
+    let (parsed, (_, errors)) = match (parsed, recovered) {
+        (Ok(parsed), Ok(recovered)) => (parsed, recovered),
+        _ => return report.failures.push(format!("parsing panicked: {:?}", source)),
+    };
+    if parsed.is_ok() != errors.is_empty() {
+        return report.failures.push(format!("parse and parse_with_recovery disagree: {:?}", source))
+    }
+    let ast: Expr = match parsed {
+        Ok(ast) => ast,
+        Err(_) => return,
+    };
+    report.parsed += 1;
+
+    let printed: String = to_source(&ast);
+    match parse(printed.clone()) {
+        Ok(reparsed) if reparsed == ast && to_source(&reparsed) == printed => {},
+        _ => return report.failures.push(format!("printing does not round-trip: {:?} printed as {:?}", source, printed)),
+    }
+
+    if !is_arithmetic(&ast) {
+        return
+    }
+    let substituted: Result<i32, String> = calc_with_limits(&ast, &FUZZ_LIMITS);
+    if substituted.as_ref().is_err_and(|msg| is_limit_exceeded(msg)) {
+        return
+    }
+    report.evaluated += 1;
+    let direct: Result<i32, String> = run(&compile(&ast));
+    if substituted != direct {
+        report.failures.push(format!("calc gives {:?} but the VM gives {:?}: {:?}", substituted, direct, source))
+    }
+}
+
+// is_arithmetic returns true if the given tree uses only numbers, arithmetic,
+// With and identifiers, or holds parse errors.
+fn is_arithmetic(ast: &Expr) -> bool {
+    let mut stack: Vec<&Expr> = vec![ast];
+    while let Some(expr) = stack.pop() {
+        match expr {
+            Expr::Number(_) | Expr::Binary(_) | Expr::With(_) | Expr::Id(_) | Expr::Error(_) => stack.extend(expr.inputs()),
+            _ => return false,
+        }
+    }
+    true
+}
+
+// mutate returns the given source text with a few random edits: characters
+// deleted or replaced, tokens inserted, or a piece of the text duplicated.
+fn mutate(rng: &mut Rng, source: &str) -> String {
+    let mut chars: Vec<char> = source.chars().collect();
+    for _ in 0..=rng.below(3) {
+        let at: usize = rng.below(chars.len() as u64 + 1) as usize;
+        match rng.below(4) {
+            0 => {
+                let end: usize = chars.len().min(at + 1 + rng.below(3) as usize);
+                chars.drain(at..end);
+            },
+            1 => {
+                let token: &&str = rng.pick(&TOKENS);
+                chars.splice(at..at, token.chars());
+            },
+            2 => {
+                let start: usize = rng.below(chars.len() as u64 + 1) as usize;
+                let end: usize = chars.len().min(start + rng.below(12) as usize);
+                let piece: Vec<char> = chars[start..end].to_vec();
+                chars.splice(at..at, piece);
+            },
+            _ => {
+                if at < chars.len() {
+                    chars[at] = rng.pick(&TOKENS).chars().next().unwrap();
+                }
+            },
+        }
+    }
+    chars.into_iter().collect()
+}
+
+// TOKENS are the pieces of text that mutate inserts: delimiters, keywords,
+// numbers at and beyond the limits of an i32, comments, string quotes and
+// escapes, and a character that takes more than one byte.
+const TOKENS: [&str; 24] = [
+    "(", ")", "[", "]", " ", "\n", "with", "define", "+", "-", "*", "/",
+    "0", "42", "2147483647", "2147483648", "x", ";", "#|", "|#", "#;", "\"", "\\", "é",
+];
+
+// FUZZ_LIMITS bounds the evaluation of each source, since random programs can
+// expand exponentially under substitution.
+const FUZZ_LIMITS: EvalLimits = EvalLimits{ max_steps: Some(100_000), max_depth: None, max_size: Some(100_000), deadline: None };
@@ -0,0 +1,99 @@
+use crate::{Expr, Number, Binary, Operator, With, Binding, Id, Meta};
+
+// Rng is a xorshift pseudo-random number generator. It is deterministic for a
+// given seed, so a failing case can be reproduced from the seed alone.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    // new returns a generator for the given seed. Xorshift never leaves the
+    // zero state, so a seed of zero is replaced.
+    pub fn new(seed: u64) -> Rng {
+        Rng{ state: if seed == 0 { 0x2545_f491_4f6c_dd1d } else { seed } }
+    }
+
+    // below returns a number less than the given bound, which must not be
+    // zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state % bound
+    }
+
+    // chance returns true with the given probability, in percent.
+    pub fn chance(&mut self, percent: u32) -> bool {
+        self.below(100) < percent as u64
+    }
+
+    // pick returns a random element of the given non-empty slice.
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+}
+
+// GenOptions controls the shape of the expressions made by random_expr.
+#[derive(Clone)]
+pub struct GenOptions {
+    // max_depth bounds the depth of the tree; a leaf has depth 0.
+    pub max_depth: usize,
+    // leaf_percent is the chance that a node above the maximum depth is a
+    // leaf anyway.
+    pub leaf_percent: u32,
+    // binding_percent is the chance that a node with inputs is a With rather
+    // than a Binary.
+    pub binding_percent: u32,
+    // ill_scoped allows identifiers that no enclosing With binds. Otherwise
+    // every identifier is bound, and a leaf outside any With is a Number.
+    pub ill_scoped: bool,
+}
+
+impl Default for GenOptions {
+    fn default() -> GenOptions {
+        GenOptions{ max_depth: 5, leaf_percent: 25, binding_percent: 50, ill_scoped: true }
+    }
+}
+
+// random_expr returns a random expression that the parser can produce, shaped
+// by the given options. Numbers are small, so that arithmetic sometimes
+// overflows or divides by zero without always doing so.
+pub fn random_expr(rng: &mut Rng, options: &GenOptions) -> Expr {
+    let mut scope: Vec<String> = Vec::new();
+    generate(rng, options, options.max_depth, &mut scope)
+}
+
+// generate returns a random expression of at most the given depth in which the
+// identifiers in scope are bound.
+fn generate(rng: &mut Rng, options: &GenOptions, depth: usize, scope: &mut Vec<String>) -> Expr {
+    if depth == 0 || rng.chance(options.leaf_percent) {
+        let free: bool = options.ill_scoped && rng.chance(20);
+        return match rng.below(2) {
+            0 if free => Id{ val: rng.pick(&NAMES).to_string(), meta: Meta::default() }.into(),
+            0 if !scope.is_empty() => Id{ val: rng.pick(scope).clone(), meta: Meta::default() }.into(),
+            _ => Number{ val: *rng.pick(&NUMBERS), meta: Meta::default() }.into(),
+        }
+    }
+    if rng.chance(options.binding_percent) {
+        let name: String = rng.pick(&NAMES).to_string();
+        let replace: Expr = generate(rng, options, depth - 1, scope);
+        scope.push(name.clone());
+        let input: Expr = generate(rng, options, depth - 1, scope);
+        scope.pop();
+        let identifier: Box<Id> = Box::new(Id{ val: name, meta: Meta::default() });
+        let binding: Binding = Binding{ identifier, annotation: None, replace, meta: Meta::default() };
+        return With{ binding, input, meta: Meta::default() }.into()
+    }
+    let op: Operator = *rng.pick(&[Operator::Add, Operator::Sub, Operator::Mul, Operator::Div]);
+    let left: Expr = generate(rng, options, depth - 1, scope);
+    let right: Expr = generate(rng, options, depth - 1, scope);
+    Binary{ op, left, right, meta: Meta::default() }.into()
+}
+
+// NAMES are the identifiers used by random_expr, including ones that start
+// with a keyword.
+const NAMES: [&str; 5] = ["x", "y", "foo", "withx", "definey"];
+
+// NUMBERS are the literals used by random_expr, including ones near the limits
+// of an i32.
+const NUMBERS: [i32; 8] = [1, 2, 3, 7, 10, 255, 65536, i32::MAX];
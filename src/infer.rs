@@ -0,0 +1,826 @@
+use crate::{Expr, Number, Binary, With, Binding, Id};
+use crate::ast::{Error, Bool, If, Call, Primitive, Fun, App, Type, Str, Empty, Record, Get};
+use crate::calc::located;
+use crate::parse::{ARROW, COLON, NUMBER_TYPE, BOOLEAN_TYPE, STRING_TYPE, LISTOF_TYPE, RECORD_OP};
+use crate::program::{Program, Form};
+use crate::reader::Span;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+// infer returns the most general type of the given abstract syntax tree and of
+// every identifier bound in it, without requiring any annotations. Annotations
+// that are present are checked.
+//
+// Inference is Hindley-Milner: every expression is given a type made of type
+// variables, which unification refines as the uses of the expression are
+// found. The type of a With binding is generalized over the variables that do
+// not occur in the enclosing scope, so that a bound function can be used at
+// several types:
+//
+//   (with ([id (fun (x) x)]) (if (id true) (id 1) 2))
+//
+// Function parameters are not generalized, and the record in a field access
+// must already have a known record type. Every type records the expression
+// that gave rise to it, so that a type error names both sides of the conflict.
+pub fn infer(ast: &Expr) -> Result<Inference, String> {
+    let mut inferer: Inferer = Inferer{ vars: Vec::new(), scope: Vec::new(), bindings: Vec::new(), tasks: Vec::new(), types: Vec::new() };
+    inferer.infer_form(ast).map(|(_, inference)| inference)
+}
+
+// infer_program infers the type of each form of the given program in order.
+// Every form sees the generalized types of the definitions that precede it, as
+// in evaluate, and a definition that fails to check leaves its name unbound
+// from then on.
+pub fn infer_program(program: &Program) -> Vec<Result<Inference, String>> {
+    let mut inferer: Inferer = Inferer{ vars: Vec::new(), scope: Vec::new(), bindings: Vec::new(), tasks: Vec::new(), types: Vec::new() };
+    let mut inferences: Vec<Result<Inference, String>> = Vec::new();
+    for form in &program.forms {
+        let result: Result<Inference, String> = match form {
+            Form::Define(define) => {
+                let result: Result<(Scheme, Inference), String> = inferer.infer_form(&define.value);
+                inferer.scope.retain(|(name, _)| *name != define.name.val);
+                result.map(|(scheme, inference)| {
+                    inferer.scope.push((&define.name.val, scheme));
+                    inference
+                })
+            },
+            Form::Expr(expr) => inferer.infer_form(expr).map(|(_, inference)| inference),
+        };
+        inferences.push(result);
+    }
+    inferences
+}
+
+// Inference is the result of inferring the types of an expression.
+pub struct Inference {
+    // ty is the type of the whole expression.
+    pub ty:       Scheme,
+    // bindings holds the type of every identifier bound by a With or a
+    // function in the expression, in the order they are written.
+    pub bindings: Vec<(Box<Id>, Scheme)>,
+}
+
+// Mono is a type that may contain type variables. Every type other than a
+// variable records the location of the expression or annotation that required
+// it, and a variable records where it was introduced.
+pub(crate) enum Mono {
+    Number(Span),
+    Boolean(Span),
+    String(Span),
+    Fun(Box<Mono>, Box<Mono>, Span),
+    List(Box<Mono>, Span),
+    Record(Vec<(String, Mono)>, Span),
+    Var(usize, Span),
+}
+
+// Scheme is a type in which the given variables may stand for any type.
+#[derive(Clone)]
+pub struct Scheme {
+    vars: Vec<usize>,
+    ty:   Mono,
+}
+
+// Inferer holds the state threaded through inference.
+pub(crate) struct Inferer<'a> {
+    // vars holds the type each variable has been unified with, if any.
+    vars:     Vec<Option<Mono>>,
+    // scope maps each identifier in scope to its type, innermost last.
+    scope:    Vec<(&'a str, Scheme)>,
+    // bindings holds the identifiers bound so far in the current form, and
+    // their types once they are known.
+    bindings: Vec<(&'a Id, Option<Scheme>)>,
+    // tasks holds the inference still to do in the current form, and types
+    // the types of the expressions inferred so far.
+    tasks:    Vec<Task<'a>>,
+    types:    Vec<Mono>,
+}
+
+// Task is a piece of inference still to be done.
+enum Task<'a> {
+    // Infer pushes the type of the expression.
+    Infer(&'a Expr),
+    // Unify pops the type of the expression and unifies the given type with
+    // it.
+    Unify(Mono, &'a Expr),
+    Push(Mono),
+    // Bind pops the type of the bound expression of the binding, checks it
+    // against the annotation, and binds the identifier, in the given slot, to
+    // its generalized type.
+    Bind(usize, &'a Binding),
+    Unbind,
+    // Finish pops the types of the inputs of the node and pushes its type.
+    Finish(&'a dyn InferableNode),
+}
+
+impl<'a> Inferer<'a> {
+    // infer_form infers the type of a top-level expression and generalizes it,
+    // along with the types of the identifiers bound in it. It returns the
+    // scheme of the expression, to bring a definition into scope, and the
+    // inference to report.
+    fn infer_form(&mut self, expr: &'a Expr) -> Result<(Scheme, Inference), String> {
+        let start: usize = self.bindings.len();
+        let result: Result<Mono, String> = self.infer(expr);
+        let bindings: Vec<(&'a Id, Option<Scheme>)> = self.bindings.split_off(start);
+        let scheme: Scheme = self.generalize(&result?);
+        // Number the variables in the order they appear, so that they are
+        // named consistently across the whole form.
+        let mut numbers: HashMap<usize, usize> = HashMap::new();
+        let ty: Scheme = self.number_scheme(&scheme, &mut numbers);
+        let bindings: Vec<(Box<Id>, Scheme)> = bindings.into_iter()
+            .map(|(id, scheme)| (Box::new(id.clone()), self.number_scheme(&scheme.unwrap(), &mut numbers)))
+            .collect();
+        Ok((scheme, Inference{ ty, bindings }))
+    }
+
+    // infer returns the type of the given expression, which may contain
+    // variables that are refined later. It walks the tree with an explicit
+    // work stack rather than by recursion, so that its depth is bounded only
+    // by memory. The scope is left as it was.
+    fn infer(&mut self, expr: &'a Expr) -> Result<Mono, String> {
+        let depth: usize = self.scope.len();
+        self.tasks = vec![Task::Infer(expr)];
+        self.types.clear();
+        let result: Result<Mono, String> = self.run();
+        self.tasks.clear();
+        self.scope.truncate(depth);
+        result
+    }
+
+    // run does the tasks in order and returns the one type they leave.
+    fn run(&mut self) -> Result<Mono, String> {
+        while let Some(task) = self.tasks.pop() {
+            match task {
+                Task::Infer(expr) => expr.infer_node(self)?,
+                Task::Unify(expected, expr) => {
+                    let found: Mono = self.types.pop().unwrap();
+                    self.unify(&expected, &found, expr.meta().span)?
+                },
+                Task::Push(ty) => self.types.push(ty),
+                Task::Bind(slot, binding) => {
+                    let ty: Mono = self.types.pop().unwrap();
+                    if let Some(annotation) = &binding.annotation {
+                        let expected: Mono = Mono::from_type(&annotation.ty, binding.identifier.meta.span);
+                        self.unify(&expected, &ty, binding.replace.meta().span)?;
+                    }
+                    let scheme: Scheme = self.generalize(&ty);
+                    self.bind(slot, scheme)
+                },
+                Task::Unbind => {
+                    self.scope.pop();
+                },
+                Task::Finish(node) => node.finish(self)?,
+            }
+        }
+        Ok(self.types.pop().unwrap())
+    }
+
+    // then schedules the given tasks to be done next, in order.
+    fn then(&mut self, tasks: Vec<Task<'a>>) {
+        self.tasks.extend(tasks.into_iter().rev())
+    }
+
+    // fresh returns a new type variable introduced at the given location.
+    fn fresh(&mut self, span: Span) -> Mono {
+        self.vars.push(None);
+        Mono::Var(self.vars.len() - 1, span)
+    }
+
+    // bind records the type of a bound identifier, in the slot reserved for
+    // it, and brings the identifier into scope.
+    fn bind(&mut self, slot: usize, scheme: Scheme) {
+        let id: &'a Id = self.bindings[slot].0;
+        self.bindings[slot].1 = Some(scheme.clone());
+        self.scope.push((&id.val, scheme));
+    }
+
+    // reserve keeps a slot for the type of the given bound identifier, so that
+    // bindings are reported in the order they are written.
+    fn reserve(&mut self, id: &'a Id) -> usize {
+        self.bindings.push((id, None));
+        self.bindings.len() - 1
+    }
+
+    // root returns the type that the given type stands for at its root: the
+    // type that a bound variable is bound to, through any chain of variables.
+    fn root<'t>(&'t self, ty: &'t Mono) -> &'t Mono {
+        let mut ty: &Mono = ty;
+        while let Mono::Var(var, _) = ty {
+            match &self.vars[*var] {
+                Some(bound) => ty = bound,
+                None => break,
+            }
+        }
+        ty
+    }
+
+    // shallow returns the given type with any bound variable at its root
+    // replaced by the type it is bound to.
+    fn shallow(&self, ty: &Mono) -> Mono {
+        self.root(ty).clone()
+    }
+
+    // resolve returns the given type with every bound variable replaced by the
+    // type it is bound to.
+    fn resolve(&self, ty: &Mono) -> Mono {
+        map(ty, &mut |ty| Mapped::Copy(self.root(ty)))
+    }
+
+    // number_scheme returns the given scheme, resolved, with each variable
+    // replaced by its number in the given map, which numbers new variables in
+    // the order they are reached.
+    fn number_scheme(&self, scheme: &Scheme, numbers: &mut HashMap<usize, usize>) -> Scheme {
+        let ty: Mono = number(&self.resolve(&scheme.ty), numbers);
+        let vars: Vec<usize> = scheme.vars.iter().filter_map(|var| numbers.get(var).copied()).collect();
+        Scheme{ vars, ty }
+    }
+
+    // free_vars appends the unbound variables in the given type to the list,
+    // in the order they are reached.
+    fn free_vars(&self, ty: &Mono, vars: &mut Vec<usize>) {
+        let mut seen: HashSet<usize> = vars.iter().copied().collect();
+        let mut stack: Vec<&Mono> = vec![ty];
+        while let Some(ty) = stack.pop() {
+            match self.root(ty) {
+                Mono::Var(var, _) => {
+                    if seen.insert(*var) {
+                        vars.push(*var)
+                    }
+                },
+                ty => stack.extend(ty.inputs().into_iter().rev()),
+            }
+        }
+    }
+
+    // generalize returns the scheme of the given type over the variables that
+    // do not occur in the types in scope.
+    fn generalize(&self, ty: &Mono) -> Scheme {
+        let mut in_scope: HashSet<usize> = HashSet::new();
+        for (_, scheme) in &self.scope {
+            let mut vars: Vec<usize> = Vec::new();
+            self.free_vars(&scheme.ty, &mut vars);
+            in_scope.extend(vars.into_iter().filter(|var| !scheme.vars.contains(var)));
+        }
+        let mut vars: Vec<usize> = Vec::new();
+        self.free_vars(ty, &mut vars);
+        vars.retain(|var| !in_scope.contains(var));
+        Scheme{ vars, ty: ty.clone() }
+    }
+
+    // instantiate returns the given scheme with a fresh variable, introduced at
+    // the given location, for each of its variables.
+    fn instantiate(&mut self, scheme: &Scheme, span: Span) -> Mono {
+        let fresh: HashMap<usize, Mono> = scheme.vars.iter().map(|var| (*var, self.fresh(span))).collect();
+        self.rename(&scheme.ty, &fresh)
+    }
+
+    fn rename(&self, ty: &Mono, fresh: &HashMap<usize, Mono>) -> Mono {
+        map(ty, &mut |ty| match self.root(ty) {
+            Mono::Var(var, _) if fresh.contains_key(var) => Mapped::Replace(fresh[var].clone()),
+            ty => Mapped::Copy(ty),
+        })
+    }
+
+    // unify makes the two given types equal, binding variables as needed. The
+    // error for a conflict is located at the given site, the expression whose
+    // type was found to differ from the expected one. The inputs of the types
+    // are unified in order, with a stack of the pairs still to unify.
+    fn unify(&mut self, expected: &Mono, found: &Mono, site: Span) -> Result<(), String> {
+        let mut pairs: Vec<(Mono, Mono)> = vec![(expected.clone(), found.clone())];
+        while let Some((expected, found)) = pairs.pop() {
+            let (mut expected, mut found): (Mono, Mono) = (self.shallow(&expected), self.shallow(&found));
+            match (&mut expected, &mut found) {
+                (Mono::Number(_), Mono::Number(_)) | (Mono::Boolean(_), Mono::Boolean(_)) | (Mono::String(_), Mono::String(_)) => {},
+                (Mono::Var(a, _), Mono::Var(b, _)) if a == b => {},
+                (Mono::Var(..), _) => self.bind_var(expected, found, site)?,
+                (_, Mono::Var(..)) => self.bind_var(found, expected, site)?,
+                (Mono::Fun(expected_param, expected_result, _), Mono::Fun(found_param, found_result, _)) => {
+                    pairs.push((expected_result.take(), found_result.take()));
+                    pairs.push((expected_param.take(), found_param.take()))
+                },
+                (Mono::List(expected_item, _), Mono::List(found_item, _)) => pairs.push((expected_item.take(), found_item.take())),
+                // Records unify only if they have the same fields in the same
+                // order.
+                (Mono::Record(expected_fields, _), Mono::Record(found_fields, _))
+                    if expected_fields.len() == found_fields.len()
+                    && expected_fields.iter().zip(found_fields.iter()).all(|((a, _), (b, _))| a == b) => {
+                    for ((_, expected_ty), (_, found_ty)) in expected_fields.iter_mut().zip(found_fields.iter_mut()).rev() {
+                        pairs.push((expected_ty.take(), found_ty.take()));
+                    }
+                },
+                _ => {
+                    let mut names: Names = Names::default();
+                    let msg: String = format!("expected {} (from {}) but found {} (from {})",
+                        names.show(&self.resolve(&expected)), expected.origin(), names.show(&self.resolve(&found)), found.origin());
+                    return Err(located(site, &msg))
+                },
+            }
+        }
+        Ok(())
+    }
+
+    // bind_var binds the given unbound variable to the given type, unless the
+    // type contains the variable, which would make the type infinite.
+    fn bind_var(&mut self, var: Mono, ty: Mono, site: Span) -> Result<(), String> {
+        let index: usize = match var {
+            Mono::Var(index, _) => index,
+            _ => unreachable!("only variables are bound"),
+        };
+        let mut vars: Vec<usize> = Vec::new();
+        self.free_vars(&ty, &mut vars);
+        if vars.contains(&index) {
+            let mut names: Names = Names::default();
+            let msg: String = format!("infinite type: {} (from {}) occurs in {} (from {})",
+                names.show(&var), var.origin(), names.show(&self.resolve(&ty)), ty.origin());
+            return Err(located(site, &msg))
+        }
+        self.vars[index] = Some(ty);
+        Ok(())
+    }
+}
+
+impl Mono {
+    // from_type returns the type written as the given annotation at the given
+    // location.
+    fn from_type(annotation: &Type, span: Span) -> Mono {
+        match annotation {
+            Type::Number => Mono::Number(span),
+            Type::Boolean => Mono::Boolean(span),
+            Type::String => Mono::String(span),
+            Type::Fun(param, result) => Mono::Fun(Box::new(Mono::from_type(param, span)), Box::new(Mono::from_type(result, span)), span),
+            Type::List(item) => Mono::List(Box::new(Mono::from_type(item, span)), span),
+            Type::Record(fields) => Mono::Record(fields.iter().map(|(name, ty)| (name.clone(), Mono::from_type(ty, span))).collect(), span),
+        }
+    }
+
+    // origin returns the location of the expression that gave rise to this
+    // type.
+    fn origin(&self) -> Span {
+        match self {
+            Mono::Number(span) | Mono::Boolean(span) | Mono::String(span) | Mono::Fun(_, _, span) | Mono::List(_, span)
+            | Mono::Record(_, span) | Mono::Var(_, span) => *span,
+        }
+    }
+
+    // is_leaf returns true if this type is not built from other types.
+    fn is_leaf(&self) -> bool {
+        matches!(self, Mono::Number(_) | Mono::Boolean(_) | Mono::String(_) | Mono::Var(..))
+    }
+
+    // inputs returns the types this type is built from, in the order they are
+    // written.
+    fn inputs(&self) -> Vec<&Mono> {
+        match self {
+            Mono::Fun(param, result, _) => vec![param, result],
+            Mono::List(item, _) => vec![item],
+            Mono::Record(fields, _) => fields.iter().map(|(_, ty)| ty).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn inputs_mut(&mut self) -> Vec<&mut Mono> {
+        match self {
+            Mono::Fun(param, result, _) => vec![param, result],
+            Mono::List(item, _) => vec![item],
+            Mono::Record(fields, _) => fields.iter_mut().map(|(_, ty)| ty).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // take moves this type out, leaving a placeholder in its place.
+    fn take(&mut self) -> Mono {
+        std::mem::replace(self, Mono::Number(Span::default()))
+    }
+
+    // copy_node returns a copy of the node at the root of this type, with a
+    // placeholder for each of its inputs.
+    fn copy_node(&self) -> Mono {
+        let hole = || Box::new(Mono::Number(Span::default()));
+        match self {
+            Mono::Number(span) => Mono::Number(*span),
+            Mono::Boolean(span) => Mono::Boolean(*span),
+            Mono::String(span) => Mono::String(*span),
+            Mono::Fun(_, _, span) => Mono::Fun(hole(), hole(), *span),
+            Mono::List(_, span) => Mono::List(hole(), *span),
+            Mono::Record(fields, span) => Mono::Record(fields.iter().map(|(name, _)| (name.clone(), *hole())).collect(), *span),
+            Mono::Var(var, span) => Mono::Var(*var, *span),
+        }
+    }
+}
+
+impl Clone for Mono {
+    fn clone(&self) -> Mono {
+        map(self, &mut Mapped::Copy)
+    }
+}
+
+impl Drop for Mono {
+    // drop detaches the inner types below this one onto a stack, so that
+    // every type is dropped with nothing but leaves below it.
+    fn drop(&mut self) {
+        let mut stack: Vec<Mono> = Vec::new();
+        detach_inputs(self, &mut stack);
+        while let Some(mut ty) = stack.pop() {
+            detach_inputs(&mut ty, &mut stack);
+        }
+    }
+}
+
+// detach_inputs moves the inputs of the given type that have inputs of their
+// own onto the given stack.
+fn detach_inputs(ty: &mut Mono, stack: &mut Vec<Mono>) {
+    if ty.is_leaf() {
+        return
+    }
+    for input in ty.inputs_mut() {
+        if !input.is_leaf() {
+            stack.push(input.take())
+        }
+    }
+}
+
+// Mapped is what the function passed to map makes of a node of a type.
+enum Mapped<'t> {
+    // Replace puts the given type in place of the node.
+    Replace(Mono),
+    // Copy copies the given node in place of the node, and maps its inputs in
+    // turn.
+    Copy(&'t Mono),
+}
+
+// map returns a copy of the given type in which the given function decides
+// what becomes of each node, visiting the nodes in the order they are written.
+// It walks the type with an explicit work stack, like Expr::clone, so that
+// types of any depth can be copied.
+fn map<'t>(ty: &'t Mono, visit: &mut dyn FnMut(&'t Mono) -> Mapped<'t>) -> Mono {
+    enum Task<'t> {
+        Visit(&'t Mono),
+        // Build pops the copies of the inputs of a node, which are on top of
+        // the stack of copies in order, and pushes a copy of the node.
+        Build(&'t Mono),
+    }
+    let mut tasks: Vec<Task> = vec![Task::Visit(ty)];
+    let mut copies: Vec<Mono> = Vec::new();
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Visit(ty) => match visit(ty) {
+                Mapped::Replace(ty) => copies.push(ty),
+                Mapped::Copy(ty) if ty.is_leaf() => copies.push(ty.copy_node()),
+                Mapped::Copy(ty) => {
+                    tasks.push(Task::Build(ty));
+                    tasks.extend(ty.inputs().into_iter().rev().map(Task::Visit))
+                },
+            },
+            Task::Build(ty) => {
+                let mut copy: Mono = ty.copy_node();
+                let start: usize = copies.len() - ty.inputs().len();
+                for (slot, input) in copy.inputs_mut().into_iter().zip(copies.drain(start..)) {
+                    *slot = input;
+                }
+                copies.push(copy)
+            },
+        }
+    }
+    copies.pop().unwrap()
+}
+
+// number returns the given resolved type with each variable replaced by its
+// number in the given map, adding the variables that are not in it yet.
+fn number(ty: &Mono, numbers: &mut HashMap<usize, usize>) -> Mono {
+    map(ty, &mut |ty| match ty {
+        Mono::Var(var, span) => {
+            let next: usize = numbers.len();
+            Mapped::Replace(Mono::Var(*numbers.entry(*var).or_insert(next), *span))
+        },
+        ty => Mapped::Copy(ty),
+    })
+}
+
+impl Display for Scheme {
+    // fmt writes the type with its variables named after their numbers: 'a,
+    // 'b, and so on. Every scheme in an Inference is numbered together.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", show(&self.ty, &mut var_name))
+    }
+}
+
+// Names gives the type variables in one error message their printed names, in
+// the order they are reached.
+#[derive(Default)]
+struct Names {
+    names: HashMap<usize, String>,
+}
+
+impl Names {
+    fn show(&mut self, ty: &Mono) -> String {
+        let names: &mut HashMap<usize, String> = &mut self.names;
+        show(ty, &mut |var| {
+            let next: usize = names.len();
+            names.entry(var).or_insert_with(|| var_name(next)).clone()
+        })
+    }
+}
+
+// show returns the given resolved type as it is written in annotations, with
+// each variable named by the given function. It writes the type with a stack
+// of the pieces still to write, so that types of any depth can be shown.
+fn show(ty: &Mono, name: &mut dyn FnMut(usize) -> String) -> String {
+    enum Piece<'t> {
+        Type(&'t Mono),
+        Text(String),
+    }
+    let mut text: String = String::new();
+    let mut pieces: Vec<Piece> = vec![Piece::Type(ty)];
+    while let Some(piece) = pieces.pop() {
+        let ty: &Mono = match piece {
+            Piece::Type(ty) => ty,
+            Piece::Text(piece) => {
+                text.push_str(&piece);
+                continue
+            },
+        };
+        match ty {
+            Mono::Number(_) => text.push_str(NUMBER_TYPE),
+            Mono::Boolean(_) => text.push_str(BOOLEAN_TYPE),
+            Mono::String(_) => text.push_str(STRING_TYPE),
+            Mono::Fun(param, result, _) => {
+                text.push('(');
+                pieces.extend(vec![Piece::Text(")".to_string()), Piece::Type(result), Piece::Text(format!(" {} ", ARROW)), Piece::Type(param)])
+            },
+            Mono::List(item, _) => {
+                text.push_str(&format!("({} ", LISTOF_TYPE));
+                pieces.extend(vec![Piece::Text(")".to_string()), Piece::Type(item)])
+            },
+            Mono::Record(fields, _) => {
+                text.push_str(&format!("({}", RECORD_OP));
+                pieces.push(Piece::Text(")".to_string()));
+                for (field, ty) in fields.iter().rev() {
+                    pieces.extend(vec![Piece::Text("]".to_string()), Piece::Type(ty), Piece::Text(format!(" [{} {} ", field, COLON))])
+                }
+            },
+            Mono::Var(var, _) => text.push_str(&name(*var)),
+        }
+    }
+    text
+}
+
+// var_name returns the name of the nth type variable in a message: 'a, 'b, ...,
+// 'z, 'a1, 'b1, ...
+fn var_name(n: usize) -> String {
+    let letter: char = (b'a' + (n % 26) as u8) as char;
+    if n < 26 {
+        return format!("'{}", letter)
+    }
+    format!("'{}{}", letter, n / 26)
+}
+
+// A type that implements InferableNode is a node whose type can be inferred
+// without recursing into its inputs.
+pub(crate) trait InferableNode {
+    // infer_node either pushes the type of this node, which may contain
+    // variables that are refined later, or schedules the tasks that infer the
+    // types of its inputs and then combine them.
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String>;
+
+    // finish pops the types of the inputs of this node, which infer_node
+    // scheduled, and pushes its type.
+    fn finish<'a>(&'a self, _: &mut Inferer<'a>) -> Result<(), String> {
+        unreachable!("finish is only scheduled by nodes that implement it")
+    }
+}
+
+impl InferableNode for Expr {
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        match self {
+            Expr::Number(expr) => expr.infer_node(inferer),
+            Expr::Binary(expr) => expr.infer_node(inferer),
+            Expr::With(expr) => expr.infer_node(inferer),
+            Expr::Id(expr) => expr.infer_node(inferer),
+            Expr::Error(expr) => expr.infer_node(inferer),
+            Expr::Bool(expr) => expr.infer_node(inferer),
+            Expr::If(expr) => expr.infer_node(inferer),
+            Expr::Call(expr) => expr.infer_node(inferer),
+            Expr::Fun(expr) => expr.infer_node(inferer),
+            Expr::App(expr) => expr.infer_node(inferer),
+            Expr::Str(expr) => expr.infer_node(inferer),
+            Expr::Empty(expr) => expr.infer_node(inferer),
+            Expr::Record(expr) => expr.infer_node(inferer),
+            Expr::Get(expr) => expr.infer_node(inferer),
+        }
+    }
+}
+
+impl InferableNode for Number {
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        inferer.types.push(Mono::Number(self.meta.span));
+        Ok(())
+    }
+}
+
+impl InferableNode for Binary {
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        let number: Mono = Mono::Number(self.meta.span);
+        inferer.then(vec![
+            Task::Infer(&self.left),
+            Task::Unify(number.clone(), &self.left),
+            Task::Infer(&self.right),
+            Task::Unify(number.clone(), &self.right),
+            Task::Push(number),
+        ]);
+        Ok(())
+    }
+}
+
+impl InferableNode for With {
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        let slot: usize = inferer.reserve(&self.binding.identifier);
+        inferer.then(vec![
+            Task::Infer(&self.binding.replace),
+            Task::Bind(slot, &self.binding),
+            Task::Infer(&self.input),
+            Task::Unbind,
+        ]);
+        Ok(())
+    }
+}
+
+impl InferableNode for Id {
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        let scheme: Scheme = match inferer.scope.iter().rev().find(|(name, _)| *name == self.val) {
+            Some((_, scheme)) => scheme.clone(),
+            None => return Err(located(self.meta.span, &format!("unbound identifier: {}", self.val))),
+        };
+        let ty: Mono = inferer.instantiate(&scheme, self.meta.span);
+        inferer.types.push(ty);
+        Ok(())
+    }
+}
+
+impl InferableNode for Error {
+    fn infer_node<'a>(&'a self, _: &mut Inferer<'a>) -> Result<(), String> {
+        Err(self.message.clone())
+    }
+}
+
+impl InferableNode for Bool {
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        inferer.types.push(Mono::Boolean(self.meta.span));
+        Ok(())
+    }
+}
+
+impl InferableNode for If {
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        inferer.then(vec![
+            Task::Infer(&self.condition),
+            Task::Unify(Mono::Boolean(self.meta.span), &self.condition),
+            Task::Infer(&self.then),
+            Task::Infer(&self.otherwise),
+            Task::Finish(self),
+        ]);
+        Ok(())
+    }
+
+    fn finish<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        let otherwise: Mono = inferer.types.pop().unwrap();
+        let then: Mono = inferer.types.last().unwrap().clone();
+        inferer.unify(&then, &otherwise, self.otherwise.meta().span)
+    }
+}
+
+impl InferableNode for Call {
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        let span: Span = self.meta.span;
+        let (params, result) = match self.prim.signature() {
+            Some((params, result)) => {
+                let params: Vec<Mono> = params.iter().map(|param| Mono::from_type(param, span)).collect();
+                (params, Mono::from_type(&result, span))
+            },
+            // A list primitive is used at a fresh element type each time.
+            None => {
+                let item: Mono = inferer.fresh(span);
+                let list: Mono = Mono::List(Box::new(item.clone()), span);
+                match self.prim {
+                    Primitive::Cons => (vec![item, list.clone()], list),
+                    Primitive::First => (vec![list], item),
+                    Primitive::Rest => (vec![list.clone()], list),
+                    Primitive::IsEmpty => (vec![list], Mono::Boolean(span)),
+                    _ => (vec![item; self.args.len()], list),
+                }
+            },
+        };
+        let mut tasks: Vec<Task> = Vec::new();
+        for (param, arg) in params.into_iter().zip(&self.args) {
+            tasks.extend(vec![Task::Infer(arg), Task::Unify(param, arg)]);
+        }
+        tasks.push(Task::Push(result));
+        inferer.then(tasks);
+        Ok(())
+    }
+}
+
+impl InferableNode for Fun {
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        let param: Mono = match &self.annotation {
+            Some(annotation) => Mono::from_type(&annotation.ty, self.param.meta.span),
+            None => inferer.fresh(self.param.meta.span),
+        };
+        let slot: usize = inferer.reserve(&self.param);
+        inferer.bind(slot, Scheme{ vars: Vec::new(), ty: param });
+        inferer.then(vec![Task::Infer(&self.body), Task::Finish(self)]);
+        Ok(())
+    }
+
+    fn finish<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        let result: Mono = inferer.types.pop().unwrap();
+        let (_, param): (&str, Scheme) = inferer.scope.pop().unwrap();
+        inferer.types.push(Mono::Fun(Box::new(param.ty), Box::new(result), self.meta.span));
+        Ok(())
+    }
+}
+
+impl InferableNode for App {
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        inferer.then(vec![Task::Infer(&self.fun), Task::Infer(&self.arg), Task::Finish(self)]);
+        Ok(())
+    }
+
+    fn finish<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        let arg: Mono = inferer.types.pop().unwrap();
+        let fun: Mono = inferer.types.pop().unwrap();
+        let mut fun: Mono = inferer.shallow(&fun);
+        let result: Mono = match &mut fun {
+            Mono::Fun(param, result, _) => {
+                inferer.unify(param, &arg, self.arg.meta().span)?;
+                result.take()
+            },
+            _ => {
+                let result: Mono = inferer.fresh(self.meta.span);
+                let expected: Mono = Mono::Fun(Box::new(arg), Box::new(result.clone()), self.meta.span);
+                inferer.unify(&expected, &fun, self.fun.meta().span)?;
+                result
+            },
+        };
+        inferer.types.push(result);
+        Ok(())
+    }
+}
+
+impl InferableNode for Str {
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        inferer.types.push(Mono::String(self.meta.span));
+        Ok(())
+    }
+}
+
+impl InferableNode for Empty {
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        let item: Mono = inferer.fresh(self.meta.span);
+        inferer.types.push(Mono::List(Box::new(item), self.meta.span));
+        Ok(())
+    }
+}
+
+impl InferableNode for Record {
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        let mut tasks: Vec<Task> = self.fields.iter().map(|field| Task::Infer(&field.value)).collect();
+        tasks.push(Task::Finish(self));
+        inferer.then(tasks);
+        Ok(())
+    }
+
+    fn finish<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        let start: usize = inferer.types.len() - self.fields.len();
+        let types: Vec<Mono> = inferer.types.split_off(start);
+        let fields: Vec<(String, Mono)> = self.fields.iter().map(|field| field.name.val.clone()).zip(types).collect();
+        inferer.types.push(Mono::Record(fields, self.meta.span));
+        Ok(())
+    }
+}
+
+impl InferableNode for Get {
+    fn infer_node<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        inferer.then(vec![Task::Infer(&self.record), Task::Finish(self)]);
+        Ok(())
+    }
+
+    fn finish<'a>(&'a self, inferer: &mut Inferer<'a>) -> Result<(), String> {
+        let record: Mono = inferer.types.pop().unwrap();
+        let span: Span = self.record.meta().span;
+        let mut record: Mono = inferer.shallow(&record);
+        let ty: Mono = match &mut record {
+            Mono::Record(fields, _) => match fields.iter_mut().find(|(name, _)| *name == self.field.val) {
+                Some((_, ty)) => ty.take(),
+                None => return Err(located(self.field.meta.span, &format!("record has no field: {}", self.field.val))),
+            },
+            Mono::Var(..) => return Err(located(span, "the type of the record is not known here; give it a type annotation")),
+            ty => {
+                let msg: String = format!("expected a record but found {} (from {})",
+                    Names::default().show(&inferer.resolve(ty)), ty.origin());
+                return Err(located(span, &msg))
+            },
+        };
+        inferer.types.push(ty);
+        Ok(())
+    }
+}
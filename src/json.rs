@@ -0,0 +1,1060 @@
+// ============================================================================
+// SCHEMA:
+// Expr     = {"type": "Number", "value": <integer>}
+//          | {"type": "Binary", "op": Operator, "left": Expr, "right": Expr}
+//          | {"type": "With", "binding": Binding, "body": Expr}
+//          | {"type": "Id", "name": Name}
+//          | {"type": "Error", "message": <string>, "source": <string>}
+//          | {"type": "Bool", "value": <boolean>}
+//          | {"type": "If", "condition": Expr, "then": Expr, "else": Expr}
+//          | {"type": "Call", "op": Primitive, "args": [Expr, ...]}
+//          | {"type": "Fun", "param": Name, "annotation": Type, "body": Expr}
+//          | {"type": "App", "function": Expr, "argument": Expr}
+//          | {"type": "Str", "value": <string>}
+//          | {"type": "Empty"}
+//          | {"type": "Record", "fields": [Field, ...]}
+//          | {"type": "Get", "record": Expr, "field": Name}
+// Binding  = {"identifier": Name, "annotation": Type, "value": Expr}
+// Field    = {"name": Name, "value": Expr}
+// Operator = "+" | "-" | "*" | "/"
+// Primitive = "<" | "=" | ">" | "string-append" | "string-length" | "substring"
+//          | "number->string" | "string->number" | "cons" | "first" | "rest"
+//          | "empty?" | "list"
+// Type     = "number" | "boolean" | "string" | {"from": Type, "to": Type}
+//          | {"listof": Type} | {"record": [FieldType, ...]}
+// FieldType = {"name": Name, "type": Type}
+// Name     = a string of one or more alphabetic characters other than a keyword
+//
+// Integers must fit in an i32, and a Call must have as many args as its
+// primitive takes; a list takes any number. The fields of a record, and of a
+// record type, must have distinct names. Every field shown is required except
+// annotation, and no other fields are allowed; field order does not matter.
+// ============================================================================
+
+use crate::{Expr, Number, Binary, Operator, With, Binding, Id, Meta};
+use crate::ast::hole;
+use crate::ast::{Error, Bool, If, Call, Primitive, Fun, App, Type, Annotation, Str, Empty, Record, Field, Get, MAX_TYPE_DEPTH};
+use crate::parse::{arity, ADD_OP, SUB_OP, MUL_OP, DIV_OP, LESS_OP, EQUAL_OP, GREATER_OP, NUMBER_TYPE, BOOLEAN_TYPE, KEYWORDS};
+use crate::parse::{STRING_APPEND_OP, STRING_LENGTH_OP, SUBSTRING_OP, NUMBER_TO_STRING_OP, STRING_TO_NUMBER_OP, STRING_TYPE};
+use crate::parse::{CONS_OP, FIRST_OP, REST_OP, IS_EMPTY_OP, LIST_OP};
+
+// to_json returns the JSON encoding of the given abstract syntax tree.
+pub fn to_json(ast: &Expr) -> String {
+    ast.encode().to_string()
+}
+
+// from_json decodes an abstract syntax tree from the given JSON text. Errors
+// name the offending location, either as a line and column for malformed JSON
+// or as a path such as $.binding.value.left for a schema violation.
+pub fn from_json(text: &str) -> Result<Expr, String> {
+    let json: Json = Reader{ chars: text.chars().collect(), position: 0 }.read_document()?;
+    Expr::decode(&json, "$")
+}
+
+// Json is a parsed JSON value. Object fields keep their order, and numbers keep
+// their source text so that integers are never rounded.
+#[derive(Clone, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+// Json is dropped with an explicit stack, like Expr, so that dropping a deeply
+// nested value does not overflow the stack.
+impl Drop for Json {
+    fn drop(&mut self) {
+        let mut stack: Vec<Json> = Vec::new();
+        match self {
+            Json::Array(items) => stack.append(items),
+            Json::Object(fields) => stack.extend(fields.drain(..).map(|(_, val)| val)),
+            _ => return,
+        }
+        while let Some(mut json) = stack.pop() {
+            match &mut json {
+                Json::Array(items) => stack.append(items),
+                Json::Object(fields) => stack.extend(fields.drain(..).map(|(_, val)| val)),
+                _ => {},
+            }
+        }
+    }
+}
+
+impl Json {
+    fn kind(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "a boolean",
+            Json::Number(_) => "a number",
+            Json::String(_) => "a string",
+            Json::Array(_) => "an array",
+            Json::Object(_) => "an object",
+        }
+    }
+}
+
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(val) => write!(f, "{}", val),
+            Json::Number(val) => write!(f, "{}", val),
+            Json::String(val) => write_string(f, val),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, val)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", val)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+// write_string writes the given text as a quoted, escaped JSON string.
+fn write_string(f: &mut std::fmt::Formatter<'_>, text: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for ch in text.chars() {
+        match ch {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+// ============================================================================
+// ENCODING
+// ============================================================================
+
+// A type that implements Encodable can be converted to its JSON encoding.
+pub(crate) trait Encodable {
+    fn encode(&self) -> Json;
+}
+
+impl Encodable for Expr {
+    fn encode(&self) -> Json {
+        match self {
+            Expr::Number(expr) => expr.encode(),
+            Expr::Binary(expr) => expr.encode(),
+            Expr::With(expr) => expr.encode(),
+            Expr::Id(expr) => expr.encode(),
+            Expr::Error(expr) => expr.encode(),
+            Expr::Bool(expr) => expr.encode(),
+            Expr::If(expr) => expr.encode(),
+            Expr::Call(expr) => expr.encode(),
+            Expr::Fun(expr) => expr.encode(),
+            Expr::App(expr) => expr.encode(),
+            Expr::Str(expr) => expr.encode(),
+            Expr::Empty(expr) => expr.encode(),
+            Expr::Record(expr) => expr.encode(),
+            Expr::Get(expr) => expr.encode(),
+        }
+    }
+}
+
+impl Encodable for Number {
+    fn encode(&self) -> Json {
+        Json::Object(vec!(
+            ("type".to_string(), Json::String("Number".to_string())),
+            ("value".to_string(), Json::Number(self.val.to_string())),
+        ))
+    }
+}
+
+impl Encodable for Binary {
+    fn encode(&self) -> Json {
+        Json::Object(vec!(
+            ("type".to_string(), Json::String("Binary".to_string())),
+            ("op".to_string(), self.op.encode()),
+            ("left".to_string(), self.left.encode()),
+            ("right".to_string(), self.right.encode()),
+        ))
+    }
+}
+
+impl Encodable for Operator {
+    fn encode(&self) -> Json {
+        Json::String(match self {
+            Operator::Add => ADD_OP,
+            Operator::Sub => SUB_OP,
+            Operator::Mul => MUL_OP,
+            Operator::Div => DIV_OP,
+        }.to_string())
+    }
+}
+
+impl Encodable for With {
+    fn encode(&self) -> Json {
+        Json::Object(vec!(
+            ("type".to_string(), Json::String("With".to_string())),
+            ("binding".to_string(), self.binding.encode()),
+            ("body".to_string(), self.input.encode()),
+        ))
+    }
+}
+
+impl Encodable for Binding {
+    fn encode(&self) -> Json {
+        let mut fields: Vec<(String, Json)> = vec!(("identifier".to_string(), Json::String(self.identifier.val.clone())));
+        if let Some(annotation) = &self.annotation {
+            fields.push(("annotation".to_string(), annotation.ty.encode()));
+        }
+        fields.push(("value".to_string(), self.replace.encode()));
+        Json::Object(fields)
+    }
+}
+
+impl Encodable for Id {
+    fn encode(&self) -> Json {
+        Json::Object(vec!(
+            ("type".to_string(), Json::String("Id".to_string())),
+            ("name".to_string(), Json::String(self.val.clone())),
+        ))
+    }
+}
+
+impl Encodable for Error {
+    fn encode(&self) -> Json {
+        Json::Object(vec!(
+            ("type".to_string(), Json::String("Error".to_string())),
+            ("message".to_string(), Json::String(self.message.clone())),
+            ("source".to_string(), Json::String(self.source.clone())),
+        ))
+    }
+}
+
+impl Encodable for Bool {
+    fn encode(&self) -> Json {
+        Json::Object(vec!(
+            ("type".to_string(), Json::String("Bool".to_string())),
+            ("value".to_string(), Json::Bool(self.val)),
+        ))
+    }
+}
+
+impl Encodable for If {
+    fn encode(&self) -> Json {
+        Json::Object(vec!(
+            ("type".to_string(), Json::String("If".to_string())),
+            ("condition".to_string(), self.condition.encode()),
+            ("then".to_string(), self.then.encode()),
+            ("else".to_string(), self.otherwise.encode()),
+        ))
+    }
+}
+
+impl Encodable for Call {
+    fn encode(&self) -> Json {
+        Json::Object(vec!(
+            ("type".to_string(), Json::String("Call".to_string())),
+            ("op".to_string(), self.prim.encode()),
+            ("args".to_string(), Json::Array(self.args.iter().map(Expr::encode).collect())),
+        ))
+    }
+}
+
+impl Encodable for Primitive {
+    fn encode(&self) -> Json {
+        Json::String(match self {
+            Primitive::Less => LESS_OP,
+            Primitive::Equal => EQUAL_OP,
+            Primitive::Greater => GREATER_OP,
+            Primitive::StringAppend => STRING_APPEND_OP,
+            Primitive::StringLength => STRING_LENGTH_OP,
+            Primitive::Substring => SUBSTRING_OP,
+            Primitive::NumberToString => NUMBER_TO_STRING_OP,
+            Primitive::StringToNumber => STRING_TO_NUMBER_OP,
+            Primitive::Cons => CONS_OP,
+            Primitive::First => FIRST_OP,
+            Primitive::Rest => REST_OP,
+            Primitive::IsEmpty => IS_EMPTY_OP,
+            Primitive::List => LIST_OP,
+        }.to_string())
+    }
+}
+
+impl Encodable for Fun {
+    fn encode(&self) -> Json {
+        let mut fields: Vec<(String, Json)> = vec!(
+            ("type".to_string(), Json::String("Fun".to_string())),
+            ("param".to_string(), Json::String(self.param.val.clone())),
+        );
+        if let Some(annotation) = &self.annotation {
+            fields.push(("annotation".to_string(), annotation.ty.encode()));
+        }
+        fields.push(("body".to_string(), self.body.encode()));
+        Json::Object(fields)
+    }
+}
+
+impl Encodable for App {
+    fn encode(&self) -> Json {
+        Json::Object(vec!(
+            ("type".to_string(), Json::String("App".to_string())),
+            ("function".to_string(), self.fun.encode()),
+            ("argument".to_string(), self.arg.encode()),
+        ))
+    }
+}
+
+impl Encodable for Str {
+    fn encode(&self) -> Json {
+        Json::Object(vec!(
+            ("type".to_string(), Json::String("Str".to_string())),
+            ("value".to_string(), Json::String(self.val.clone())),
+        ))
+    }
+}
+
+impl Encodable for Empty {
+    fn encode(&self) -> Json {
+        Json::Object(vec!(
+            ("type".to_string(), Json::String("Empty".to_string())),
+        ))
+    }
+}
+
+impl Encodable for Record {
+    fn encode(&self) -> Json {
+        Json::Object(vec!(
+            ("type".to_string(), Json::String("Record".to_string())),
+            ("fields".to_string(), Json::Array(self.fields.iter().map(Field::encode).collect())),
+        ))
+    }
+}
+
+impl Encodable for Field {
+    fn encode(&self) -> Json {
+        Json::Object(vec!(
+            ("name".to_string(), Json::String(self.name.val.clone())),
+            ("value".to_string(), self.value.encode()),
+        ))
+    }
+}
+
+impl Encodable for Get {
+    fn encode(&self) -> Json {
+        Json::Object(vec!(
+            ("type".to_string(), Json::String("Get".to_string())),
+            ("record".to_string(), self.record.encode()),
+            ("field".to_string(), Json::String(self.field.val.clone())),
+        ))
+    }
+}
+
+impl Encodable for Type {
+    fn encode(&self) -> Json {
+        match self {
+            Type::Number => Json::String(NUMBER_TYPE.to_string()),
+            Type::Boolean => Json::String(BOOLEAN_TYPE.to_string()),
+            Type::String => Json::String(STRING_TYPE.to_string()),
+            Type::Fun(from, to) => Json::Object(vec!(
+                ("from".to_string(), from.encode()),
+                ("to".to_string(), to.encode()),
+            )),
+            Type::List(item) => Json::Object(vec!(
+                ("listof".to_string(), item.encode()),
+            )),
+            Type::Record(fields) => {
+                let fields: Vec<Json> = fields.iter()
+                    .map(|(name, ty)| Json::Object(vec!(
+                        ("name".to_string(), Json::String(name.clone())),
+                        ("type".to_string(), ty.encode()),
+                    )))
+                    .collect();
+                Json::Object(vec!(("record".to_string(), Json::Array(fields))))
+            },
+        }
+    }
+}
+
+// ============================================================================
+// DECODING
+// ============================================================================
+
+// A type that implements Decodable can be constructed from its JSON encoding.
+pub(crate) trait Decodable: Sized {
+    // decode validates the given JSON, found at the given path, against the
+    // schema and returns the value it encodes.
+    fn decode(json: &Json, path: &str) -> Result<Self, String>;
+}
+
+impl Decodable for Expr {
+    // decode builds the tree with an explicit work stack rather than by
+    // recursion, so that its depth is bounded only by memory.
+    fn decode(json: &Json, path: &str) -> Result<Expr, String> {
+        enum Task<'a> {
+            Decode(&'a Json, String),
+            // Build pops the given number of inputs of the node, which were
+            // pushed in order, and fills them in.
+            Build(Expr, usize),
+        }
+        let mut tasks: Vec<Task> = vec![Task::Decode(json, path.to_string())];
+        let mut results: Vec<Expr> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Decode(json, path) => {
+                    let (node, inputs): (Expr, Inputs) = decode_node(json, &path)?;
+                    if inputs.is_empty() {
+                        results.push(node)
+                    } else {
+                        tasks.push(Task::Build(node, inputs.len()));
+                        tasks.extend(inputs.into_iter().rev().map(|(json, path)| Task::Decode(json, path)))
+                    }
+                },
+                Task::Build(mut node, count) => {
+                    let start: usize = results.len() - count;
+                    node.fill(results.drain(start..));
+                    results.push(node)
+                },
+            }
+        }
+        Ok(results.pop().unwrap())
+    }
+}
+
+// decode_node decodes the node at the root of the given JSON, found at the
+// given path, with placeholder inputs.
+fn decode_node<'a>(json: &'a Json, path: &str) -> Result<(Expr, Inputs<'a>), String> {
+    let fields: Fields = Fields::new(json, path)?;
+    let kind: String = fields.string("type")?;
+    match kind.as_str() {
+        "Number" => Ok((Number::decode(json, path)?.into(), Vec::new())),
+        "Binary" => Binary::decode_node(json, path).map(|(node, inputs)| (node.into(), inputs)),
+        "With" => With::decode_node(json, path).map(|(node, inputs)| (node.into(), inputs)),
+        "Id" => Ok((Id::decode(json, path)?.into(), Vec::new())),
+        "Error" => Ok((Error::decode(json, path)?.into(), Vec::new())),
+        "Bool" => Ok((Bool::decode(json, path)?.into(), Vec::new())),
+        "If" => If::decode_node(json, path).map(|(node, inputs)| (node.into(), inputs)),
+        "Call" => Call::decode_node(json, path).map(|(node, inputs)| (node.into(), inputs)),
+        "Fun" => Fun::decode_node(json, path).map(|(node, inputs)| (node.into(), inputs)),
+        "App" => App::decode_node(json, path).map(|(node, inputs)| (node.into(), inputs)),
+        "Str" => Ok((Str::decode(json, path)?.into(), Vec::new())),
+        "Empty" => Ok((Empty::decode(json, path)?.into(), Vec::new())),
+        "Record" => Record::decode_node(json, path).map(|(node, inputs)| (node.into(), inputs)),
+        "Get" => Get::decode_node(json, path).map(|(node, inputs)| (node.into(), inputs)),
+        _ => Err(format!("{}.type: unknown expression type: {}", path, kind)),
+    }
+}
+
+// A type that implements DecodableNode is a node with inputs that can be
+// constructed from its JSON encoding. Its inputs are left for Expr::decode, so
+// that decoding never recurses into them.
+pub(crate) trait DecodableNode: Sized {
+    // decode_node validates the given JSON, found at the given path, against
+    // the schema and returns the node it encodes with placeholder inputs, along
+    // with the JSON of each input and its path, in source order.
+    fn decode_node<'a>(json: &'a Json, path: &str) -> Result<(Self, Inputs<'a>), String>;
+}
+
+// Inputs are the JSON encodings of the inputs of a node, each with its path.
+type Inputs<'a> = Vec<(&'a Json, String)>;
+
+impl Decodable for Number {
+    fn decode(json: &Json, path: &str) -> Result<Number, String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["type", "value"])?;
+        let val: i32 = match fields.get("value")? {
+            Json::Number(val) => val.parse::<i32>()
+                .map_err(|_| format!("{}.value: expected an integer that fits in an i32 but found {}", path, val))?,
+            other => return Err(format!("{}.value: expected a number but found {}", path, other.kind())),
+        };
+        Ok(Number{ val, meta: Meta::default() })
+    }
+}
+
+impl DecodableNode for Binary {
+    fn decode_node<'a>(json: &'a Json, path: &str) -> Result<(Binary, Inputs<'a>), String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["type", "op", "left", "right"])?;
+        let op: Operator = Operator::decode(fields.get("op")?, &format!("{}.op", path))?;
+        let inputs: Inputs = vec![fields.input("left")?, fields.input("right")?];
+        Ok((Binary{ op, left: hole(), right: hole(), meta: Meta::default() }, inputs))
+    }
+}
+
+impl Decodable for Operator {
+    fn decode(json: &Json, path: &str) -> Result<Operator, String> {
+        match json {
+            Json::String(op) if op == ADD_OP => Ok(Operator::Add),
+            Json::String(op) if op == SUB_OP => Ok(Operator::Sub),
+            Json::String(op) if op == MUL_OP => Ok(Operator::Mul),
+            Json::String(op) if op == DIV_OP => Ok(Operator::Div),
+            Json::String(op) => Err(format!("{}: unknown operator: {}", path, op)),
+            other => Err(format!("{}: expected an operator string but found {}", path, other.kind())),
+        }
+    }
+}
+
+impl DecodableNode for With {
+    fn decode_node<'a>(json: &'a Json, path: &str) -> Result<(With, Inputs<'a>), String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["type", "binding", "body"])?;
+        let (binding, mut inputs) = Binding::decode_node(fields.get("binding")?, &format!("{}.binding", path))?;
+        inputs.push(fields.input("body")?);
+        Ok((With{ binding, input: hole(), meta: Meta::default() }, inputs))
+    }
+}
+
+impl DecodableNode for Binding {
+    fn decode_node<'a>(json: &'a Json, path: &str) -> Result<(Binding, Inputs<'a>), String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["identifier", "annotation", "value"])?;
+        let identifier: String = fields.name("identifier")?;
+        let annotation: Option<Annotation> = fields.annotation()?.map(Annotation::from);
+        let inputs: Inputs = vec![fields.input("value")?];
+        let identifier: Box<Id> = Box::new(Id{ val: identifier, meta: Meta::default() });
+        Ok((Binding{ identifier, annotation, replace: hole(), meta: Meta::default() }, inputs))
+    }
+}
+
+impl Decodable for Id {
+    fn decode(json: &Json, path: &str) -> Result<Id, String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["type", "name"])?;
+        Ok(Id{ val: fields.name("name")?, meta: Meta::default() })
+    }
+}
+
+impl Decodable for Error {
+    fn decode(json: &Json, path: &str) -> Result<Error, String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["type", "message", "source"])?;
+        Ok(Error{ message: fields.string("message")?, source: fields.string("source")?, meta: Meta::default() })
+    }
+}
+
+impl Decodable for Bool {
+    fn decode(json: &Json, path: &str) -> Result<Bool, String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["type", "value"])?;
+        match fields.get("value")? {
+            Json::Bool(val) => Ok(Bool{ val: *val, meta: Meta::default() }),
+            other => Err(format!("{}.value: expected a boolean but found {}", path, other.kind())),
+        }
+    }
+}
+
+impl DecodableNode for If {
+    fn decode_node<'a>(json: &'a Json, path: &str) -> Result<(If, Inputs<'a>), String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["type", "condition", "then", "else"])?;
+        let inputs: Inputs = vec![fields.input("condition")?, fields.input("then")?, fields.input("else")?];
+        Ok((If{ condition: hole(), then: hole(), otherwise: hole(), meta: Meta::default() }, inputs))
+    }
+}
+
+impl DecodableNode for Call {
+    fn decode_node<'a>(json: &'a Json, path: &str) -> Result<(Call, Inputs<'a>), String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["type", "op", "args"])?;
+        let prim: Primitive = Primitive::decode(fields.get("op")?, &format!("{}.op", path))?;
+        let items: &Vec<Json> = fields.array("args")?;
+        if let Some(arity) = arity(prim) {
+            if items.len() != arity {
+                return Err(format!("{}.args: expected {} arguments but found {}", path, arity, items.len()))
+            }
+        }
+        let inputs: Inputs = items.iter().enumerate()
+            .map(|(i, item)| (item, format!("{}.args[{}]", path, i)))
+            .collect();
+        Ok((Call{ prim, args: items.iter().map(|_| hole()).collect(), meta: Meta::default() }, inputs))
+    }
+}
+
+impl Decodable for Primitive {
+    fn decode(json: &Json, path: &str) -> Result<Primitive, String> {
+        match json {
+            Json::String(op) if op == LESS_OP => Ok(Primitive::Less),
+            Json::String(op) if op == EQUAL_OP => Ok(Primitive::Equal),
+            Json::String(op) if op == GREATER_OP => Ok(Primitive::Greater),
+            Json::String(op) if op == STRING_APPEND_OP => Ok(Primitive::StringAppend),
+            Json::String(op) if op == STRING_LENGTH_OP => Ok(Primitive::StringLength),
+            Json::String(op) if op == SUBSTRING_OP => Ok(Primitive::Substring),
+            Json::String(op) if op == NUMBER_TO_STRING_OP => Ok(Primitive::NumberToString),
+            Json::String(op) if op == STRING_TO_NUMBER_OP => Ok(Primitive::StringToNumber),
+            Json::String(op) if op == CONS_OP => Ok(Primitive::Cons),
+            Json::String(op) if op == FIRST_OP => Ok(Primitive::First),
+            Json::String(op) if op == REST_OP => Ok(Primitive::Rest),
+            Json::String(op) if op == IS_EMPTY_OP => Ok(Primitive::IsEmpty),
+            Json::String(op) if op == LIST_OP => Ok(Primitive::List),
+            Json::String(op) => Err(format!("{}: unknown primitive: {}", path, op)),
+            other => Err(format!("{}: expected a primitive string but found {}", path, other.kind())),
+        }
+    }
+}
+
+impl DecodableNode for Fun {
+    fn decode_node<'a>(json: &'a Json, path: &str) -> Result<(Fun, Inputs<'a>), String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["type", "param", "annotation", "body"])?;
+        let param: String = fields.name("param")?;
+        let annotation: Option<Annotation> = fields.annotation()?.map(Annotation::from);
+        let inputs: Inputs = vec![fields.input("body")?];
+        let param: Box<Id> = Box::new(Id{ val: param, meta: Meta::default() });
+        Ok((Fun{ param, annotation, body: hole(), meta: Meta::default() }, inputs))
+    }
+}
+
+impl DecodableNode for App {
+    fn decode_node<'a>(json: &'a Json, path: &str) -> Result<(App, Inputs<'a>), String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["type", "function", "argument"])?;
+        let inputs: Inputs = vec![fields.input("function")?, fields.input("argument")?];
+        Ok((App{ fun: hole(), arg: hole(), meta: Meta::default() }, inputs))
+    }
+}
+
+impl Decodable for Str {
+    fn decode(json: &Json, path: &str) -> Result<Str, String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["type", "value"])?;
+        match fields.get("value")? {
+            Json::String(val) => Ok(Str{ val: val.clone(), meta: Meta::default() }),
+            other => Err(format!("{}.value: expected a string but found {}", path, other.kind())),
+        }
+    }
+}
+
+impl Decodable for Empty {
+    fn decode(json: &Json, path: &str) -> Result<Empty, String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["type"])?;
+        Ok(Empty{ meta: Meta::default() })
+    }
+}
+
+impl DecodableNode for Record {
+    fn decode_node<'a>(json: &'a Json, path: &str) -> Result<(Record, Inputs<'a>), String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["type", "fields"])?;
+        let mut decoded: Vec<Field> = Vec::new();
+        let mut inputs: Inputs = Vec::new();
+        for (i, item) in fields.array("fields")?.iter().enumerate() {
+            let (field, value) = Field::decode_node(item, &format!("{}.fields[{}]", path, i))?;
+            if decoded.iter().any(|other| other.name.val == field.name.val) {
+                return Err(format!("{}.fields[{}].name: duplicate field: {}", path, i, field.name.val))
+            }
+            decoded.push(field);
+            inputs.extend(value);
+        }
+        Ok((Record{ fields: decoded, meta: Meta::default() }, inputs))
+    }
+}
+
+impl DecodableNode for Field {
+    fn decode_node<'a>(json: &'a Json, path: &str) -> Result<(Field, Inputs<'a>), String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["name", "value"])?;
+        let name: String = fields.name("name")?;
+        let inputs: Inputs = vec![fields.input("value")?];
+        Ok((Field{ name: Box::new(Id{ val: name, meta: Meta::default() }), value: hole(), meta: Meta::default() }, inputs))
+    }
+}
+
+impl DecodableNode for Get {
+    fn decode_node<'a>(json: &'a Json, path: &str) -> Result<(Get, Inputs<'a>), String> {
+        let fields: Fields = Fields::new(json, path)?;
+        fields.only(&["type", "record", "field"])?;
+        let field: String = fields.name("field")?;
+        let inputs: Inputs = vec![fields.input("record")?];
+        Ok((Get{ record: hole(), field: Box::new(Id{ val: field, meta: Meta::default() }), meta: Meta::default() }, inputs))
+    }
+}
+
+impl Decodable for Type {
+    fn decode(json: &Json, path: &str) -> Result<Type, String> {
+        decode_type(json, path, 0)
+    }
+}
+
+// decode_type decodes a type annotation found at the given depth of nesting.
+fn decode_type(json: &Json, path: &str, depth: usize) -> Result<Type, String> {
+    if depth >= MAX_TYPE_DEPTH {
+        return Err(format!("{}: type is nested more than {} levels deep", path, MAX_TYPE_DEPTH))
+    }
+    match json {
+        Json::String(name) if name == NUMBER_TYPE => Ok(Type::Number),
+        Json::String(name) if name == BOOLEAN_TYPE => Ok(Type::Boolean),
+        Json::String(name) if name == STRING_TYPE => Ok(Type::String),
+        Json::String(name) => Err(format!("{}: unknown type: {}", path, name)),
+        Json::Object(_) => {
+            let fields: Fields = Fields::new(json, path)?;
+            if fields.has("listof") {
+                fields.only(&["listof"])?;
+                let item: Type = decode_type(fields.get("listof")?, &format!("{}.listof", path), depth + 1)?;
+                return Ok(Type::List(Box::new(item)))
+            }
+            if fields.has("record") {
+                fields.only(&["record"])?;
+                let mut decoded: Vec<(String, Type)> = Vec::new();
+                for (i, item) in fields.array("record")?.iter().enumerate() {
+                    let path: String = format!("{}.record[{}]", path, i);
+                    let field: Fields = Fields::new(item, &path)?;
+                    field.only(&["name", "type"])?;
+                    let name: String = field.name("name")?;
+                    if decoded.iter().any(|(other, _)| *other == name) {
+                        return Err(format!("{}.name: duplicate field: {}", path, name))
+                    }
+                    let ty: Type = decode_type(field.get("type")?, &format!("{}.type", path), depth + 1)?;
+                    decoded.push((name, ty));
+                }
+                return Ok(Type::Record(decoded))
+            }
+            fields.only(&["from", "to"])?;
+            let from: Type = decode_type(fields.get("from")?, &format!("{}.from", path), depth + 1)?;
+            let to: Type = decode_type(fields.get("to")?, &format!("{}.to", path), depth + 1)?;
+            Ok(Type::Fun(Box::new(from), Box::new(to)))
+        },
+        other => Err(format!("{}: expected a type but found {}", path, other.kind())),
+    }
+}
+
+// Fields gives path-aware access to the fields of a JSON object.
+struct Fields<'a, 'p> {
+    fields: &'a [(String, Json)],
+    path: &'p str,
+}
+
+impl<'a, 'p> Fields<'a, 'p> {
+    fn new(json: &'a Json, path: &'p str) -> Result<Fields<'a, 'p>, String> {
+        match json {
+            Json::Object(fields) => Ok(Fields{ fields, path }),
+            other => Err(format!("{}: expected an object but found {}", path, other.kind())),
+        }
+    }
+
+    // get returns the value of the given required field.
+    fn get(&self, key: &str) -> Result<&'a Json, String> {
+        self.fields.iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, val)| val)
+            .ok_or_else(|| format!("{}: missing field: {}", self.path, key))
+    }
+
+    // input returns the value of the given required field, which is an input
+    // of the node being decoded, along with its path.
+    fn input(&self, key: &str) -> Result<(&'a Json, String), String> {
+        Ok((self.get(key)?, format!("{}.{}", self.path, key)))
+    }
+
+    // string returns the value of the given required string field.
+    fn string(&self, key: &str) -> Result<String, String> {
+        match self.get(key)? {
+            Json::String(val) => Ok(val.clone()),
+            other => Err(format!("{}.{}: expected a string but found {}", self.path, key, other.kind())),
+        }
+    }
+
+    // array returns the items of the given required array field.
+    fn array(&self, key: &str) -> Result<&'a Vec<Json>, String> {
+        match self.get(key)? {
+            Json::Array(items) => Ok(items),
+            other => Err(format!("{}.{}: expected an array but found {}", self.path, key, other.kind())),
+        }
+    }
+
+    // name returns the value of the given required identifier field.
+    fn name(&self, key: &str) -> Result<String, String> {
+        let name: String = self.string(key)?;
+        if name.is_empty() || !name.chars().all(char::is_alphabetic) || KEYWORDS.contains(&name.as_str()) {
+            return Err(format!("{}.{}: invalid identifier: {:?}", self.path, key, name))
+        }
+        Ok(name)
+    }
+
+    // has returns true if the object has the given field.
+    fn has(&self, key: &str) -> bool {
+        self.fields.iter().any(|(name, _)| name == key)
+    }
+
+    // annotation returns the value of the optional annotation field.
+    fn annotation(&self) -> Result<Option<Type>, String> {
+        if !self.has("annotation") {
+            return Ok(None)
+        }
+        Ok(Some(Type::decode(self.get("annotation")?, &format!("{}.annotation", self.path))?))
+    }
+
+    // only checks that the object has no fields other than the given ones, and
+    // no field more than once.
+    fn only(&self, allowed: &[&str]) -> Result<(), String> {
+        for (i, (key, _)) in self.fields.iter().enumerate() {
+            if !allowed.contains(&key.as_str()) {
+                return Err(format!("{}: unexpected field: {}", self.path, key))
+            }
+            if self.fields[..i].iter().any(|(other, _)| other == key) {
+                return Err(format!("{}: duplicate field: {}", self.path, key))
+            }
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// READING
+// ============================================================================
+
+// Reader parses JSON text into a Json value.
+struct Reader {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Reader {
+    // read_document reads a single JSON value surrounded by optional
+    // whitespace.
+    fn read_document(&mut self) -> Result<Json, String> {
+        let json: Json = self.read_value()?;
+        self.skip_whitespace();
+        if self.position < self.chars.len() {
+            return Err(self.error("unexpected text after JSON value"))
+        }
+        Ok(json)
+    }
+
+    // read_value reads a JSON value with an explicit stack of the objects and
+    // arrays that are still open rather than by recursion, so that its depth
+    // is bounded only by memory.
+    fn read_value(&mut self) -> Result<Json, String> {
+        enum Open {
+            // Object holds the fields read so far and the key of the field
+            // whose value is being read.
+            Object(Vec<(String, Json)>, String),
+            Array(Vec<Json>),
+        }
+        let mut open: Vec<Open> = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let mut value: Json = match self.peek() {
+                Some('{') => {
+                    self.position += 1;
+                    self.skip_whitespace();
+                    if self.peek() == Some('}') {
+                        self.position += 1;
+                        Json::Object(Vec::new())
+                    } else {
+                        open.push(Open::Object(Vec::new(), self.read_key()?));
+                        continue
+                    }
+                },
+                Some('[') => {
+                    self.position += 1;
+                    self.skip_whitespace();
+                    if self.peek() == Some(']') {
+                        self.position += 1;
+                        Json::Array(Vec::new())
+                    } else {
+                        open.push(Open::Array(Vec::new()));
+                        continue
+                    }
+                },
+                Some('"') => Json::String(self.read_string()?),
+                Some('t') => self.read_keyword("true", Json::Bool(true))?,
+                Some('f') => self.read_keyword("false", Json::Bool(false))?,
+                Some('n') => self.read_keyword("null", Json::Null)?,
+                Some(c) if c == '-' || c.is_ascii_digit() => self.read_number()?,
+                Some(c) => return Err(self.error(&format!("unexpected character: {}", c))),
+                None => return Err(self.error("unexpected end of input")),
+            };
+            // Add the value to the innermost open container, closing each
+            // container that it completes.
+            loop {
+                self.skip_whitespace();
+                match open.pop() {
+                    None => return Ok(value),
+                    Some(Open::Object(mut fields, key)) => {
+                        fields.push((key, value));
+                        match self.next() {
+                            Some(',') => {
+                                let key: String = self.read_key()?;
+                                open.push(Open::Object(fields, key));
+                                break
+                            },
+                            Some('}') => value = Json::Object(fields),
+                            _ => return Err(self.error("expected ',' or '}'")),
+                        }
+                    },
+                    Some(Open::Array(mut items)) => {
+                        items.push(value);
+                        match self.next() {
+                            Some(',') => {
+                                open.push(Open::Array(items));
+                                break
+                            },
+                            Some(']') => value = Json::Array(items),
+                            _ => return Err(self.error("expected ',' or ']'")),
+                        }
+                    },
+                }
+            }
+        }
+    }
+
+    // read_key reads the name of an object field and the colon after it.
+    fn read_key(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        if self.peek() != Some('"') {
+            return Err(self.error("expected a field name"))
+        }
+        let key: String = self.read_string()?;
+        self.skip_whitespace();
+        self.expect(':')?;
+        Ok(key)
+    }
+
+    fn read_string(&mut self) -> Result<String, String> {
+        self.position += 1;
+        let mut text: String = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(text),
+                Some('\\') => match self.next() {
+                    Some('"') => text.push('"'),
+                    Some('\\') => text.push('\\'),
+                    Some('/') => text.push('/'),
+                    Some('b') => text.push('\u{8}'),
+                    Some('f') => text.push('\u{c}'),
+                    Some('n') => text.push('\n'),
+                    Some('r') => text.push('\r'),
+                    Some('t') => text.push('\t'),
+                    Some('u') => text.push(self.read_unicode_escape()?),
+                    _ => return Err(self.error("invalid escape sequence")),
+                },
+                Some(c) if (c as u32) < 0x20 => return Err(self.error("unescaped control character in string")),
+                Some(c) => text.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    // read_unicode_escape reads the digits of a \u escape, combining a
+    // surrogate pair into one character.
+    fn read_unicode_escape(&mut self) -> Result<char, String> {
+        let high: u32 = self.read_hex4()?;
+        if (0xd800..0xdc00).contains(&high) {
+            if self.next() != Some('\\') || self.next() != Some('u') {
+                return Err(self.error("expected a low surrogate"))
+            }
+            let low: u32 = self.read_hex4()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(self.error("invalid low surrogate"))
+            }
+            let code: u32 = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+            return char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+        }
+        char::from_u32(high).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn read_hex4(&mut self) -> Result<u32, String> {
+        let mut code: u32 = 0;
+        for _ in 0..4 {
+            let digit: u32 = self.next().and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.error("expected four hexadecimal digits"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn read_number(&mut self) -> Result<Json, String> {
+        let start: usize = self.position;
+        if self.peek() == Some('-') {
+            self.position += 1;
+        }
+        let digits: usize = self.skip_digits();
+        if digits == 0 || (digits > 1 && self.chars[self.position - digits] == '0') {
+            return Err(self.error("invalid number"))
+        }
+        if self.peek() == Some('.') {
+            self.position += 1;
+            if self.skip_digits() == 0 {
+                return Err(self.error("invalid number"))
+            }
+        }
+        if let Some('e') | Some('E') = self.peek() {
+            self.position += 1;
+            if let Some('+') | Some('-') = self.peek() {
+                self.position += 1;
+            }
+            if self.skip_digits() == 0 {
+                return Err(self.error("invalid number"))
+            }
+        }
+        Ok(Json::Number(self.chars[start..self.position].iter().collect()))
+    }
+
+    fn read_keyword(&mut self, keyword: &str, json: Json) -> Result<Json, String> {
+        for expected in keyword.chars() {
+            if self.next() != Some(expected) {
+                return Err(self.error(&format!("expected {}", keyword)))
+            }
+        }
+        Ok(json)
+    }
+
+    fn skip_digits(&mut self) -> usize {
+        let start: usize = self.position;
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_digit() {
+                break
+            }
+            self.position += 1;
+        }
+        self.position - start
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ') | Some('\t') | Some('\n') | Some('\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        if self.next() != Some(expected) {
+            return Err(self.error(&format!("expected '{}'", expected)))
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let next: Option<char> = self.peek();
+        self.position += 1;
+        next
+    }
+
+    // error returns the given message prefixed with the line and column of the
+    // current position.
+    fn error(&self, msg: &str) -> String {
+        let consumed: &[char] = &self.chars[..self.position.min(self.chars.len())];
+        let line: usize = consumed.iter().filter(|c| **c == '\n').count() + 1;
+        let column: usize = consumed.iter().rev().take_while(|c| **c != '\n').count() + 1;
+        format!("{}:{}: {}", line, column, msg)
+    }
+}
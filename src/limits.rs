@@ -0,0 +1,98 @@
+use crate::Expr;
+use crate::cse::size;
+use std::time::Instant;
+
+// EvalLimits bounds the work that replace and calc may do on a tree, so that
+// untrusted programs cannot run for too long or use too much memory. Every
+// limit is optional, and the default imposes none.
+//
+// Substitution copies the bound expression into every use of an identifier, so
+// a small program can expand to an exponentially large tree:
+//
+//   (with ([a (+ 1 1)]) (with ([b (+ a a)]) (with ([c (+ b b)]) ... )))
+#[derive(Clone, Default)]
+pub struct EvalLimits {
+    // max_steps bounds the number of reductions: every identifier replaced by
+    // substitution, every arithmetic operation or comparison, every branch
+    // taken and every function applied.
+    pub max_steps: Option<usize>,
+    // max_depth bounds how deep in the tree replace and calc may visit a node.
+    pub max_depth: Option<usize>,
+    // max_size bounds the number of nodes in the tree during substitution,
+    // counting the nodes of the original tree and every node copied into it.
+    pub max_size: Option<usize>,
+    // deadline is the time by which evaluation must finish.
+    pub deadline: Option<Instant>,
+}
+
+// Budget tracks the work done during one evaluation against its limits.
+pub(crate) struct Budget {
+    limits: EvalLimits,
+    steps:  usize,
+    size:   usize,
+    ticks:  usize,
+}
+
+impl Budget {
+    // new returns a budget for evaluating the given tree within the given
+    // limits.
+    pub(crate) fn new(limits: &EvalLimits, ast: &Expr) -> Budget {
+        Budget{ limits: limits.clone(), steps: 0, size: size(ast), ticks: 0 }
+    }
+
+    // unlimited returns a budget that is never exceeded.
+    pub(crate) fn unlimited() -> Budget {
+        Budget{ limits: EvalLimits::default(), steps: 0, size: 0, ticks: 0 }
+    }
+
+    // visit records a visit to a node at the given depth below the root of the
+    // tree being evaluated.
+    pub(crate) fn visit(&mut self, depth: usize) -> Result<(), String> {
+        if let Some(max) = self.limits.max_depth.filter(|max| depth > *max) {
+            return Err(limit_exceeded(&format!("tree deeper than {}", max)))
+        }
+        // Reading the clock is comparatively slow, so it is only done every
+        // so many nodes.
+        self.ticks += 1;
+        if self.ticks.is_multiple_of(DEADLINE_INTERVAL) && self.limits.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(limit_exceeded("deadline passed"))
+        }
+        Ok(())
+    }
+
+    // step records a single reduction.
+    pub(crate) fn step(&mut self) -> Result<(), String> {
+        self.steps += 1;
+        match self.limits.max_steps {
+            Some(max) if self.steps > max => Err(limit_exceeded(&format!("more than {} steps", max))),
+            _ => Ok(()),
+        }
+    }
+
+    // grow records that the given number of nodes were copied into the tree.
+    pub(crate) fn grow(&mut self, nodes: usize) -> Result<(), String> {
+        self.size += nodes;
+        match self.limits.max_size {
+            Some(max) if self.size > max => Err(limit_exceeded(&format!("tree larger than {} nodes", max))),
+            _ => Ok(()),
+        }
+    }
+}
+
+// limit_exceeded returns the error reported when an evaluation limit is
+// exceeded.
+fn limit_exceeded(detail: &str) -> String {
+    format!("{}: {}", LIMIT_EXCEEDED, detail)
+}
+
+// is_limit_exceeded returns true if the given error was reported because an
+// evaluation limit was exceeded, rather than by the program itself.
+pub fn is_limit_exceeded(msg: &str) -> bool {
+    msg.starts_with(LIMIT_EXCEEDED)
+}
+
+// LIMIT_EXCEEDED starts every error reported by a Budget.
+pub(crate) const LIMIT_EXCEEDED: &str = "evaluation limit exceeded";
+
+// DEADLINE_INTERVAL is the number of nodes visited between checks of the clock.
+const DEADLINE_INTERVAL: usize = 256;
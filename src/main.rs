@@ -1,16 +1,62 @@
 mod ast;
+mod reader;
 mod parse;
 mod calc;
 mod subst;
 mod pretty_print;
+mod cse;
+mod limits;
+mod bytecode;
+mod vm;
+mod codegen_c;
+mod codegen_wat;
+mod unparse;
+mod format;
+mod dot;
+mod json;
+mod program;
+mod step;
+mod debug;
+mod cli;
+mod arena;
+mod generate;
+mod fuzz;
+mod value;
+mod typecheck;
+mod infer;
 
-use ast::{Expr, Number, Binary, Operator, With, Binding, Id};
-use parse::parse;
-use calc::calc;
+use ast::{Expr, Number, Binary, Operator, With, Binding, Id, Meta, MAX_TYPE_DEPTH};
+use parse::{parse, parse_within, parse_with_recovery};
+use calc::{calc, calc_with_limits, eval};
+use crate::limits::{EvalLimits, is_limit_exceeded};
 use crate::subst::Substitutable;
 use crate::pretty_print::pretty_print;
+use crate::cse::cse;
+use crate::bytecode::{compile, disassemble, Chunk};
+use crate::vm::run;
+use crate::codegen_c::to_c;
+use crate::codegen_wat::{to_wat, validate};
+use crate::unparse::to_source;
+use crate::format::format;
+use crate::dot::{to_dot, DotOptions};
+use crate::json::{to_json, from_json};
+use crate::program::{parse_program, parse_program_with_recovery, evaluate, Program};
+use crate::step::{trace, Trace, MAX_TRACE_DEPTH};
+use crate::debug::debug;
+use crate::arena::{Arena, Tree, calc_arena, heap_size};
+use crate::generate::{random_expr, GenOptions, Rng};
+use crate::fuzz::{fuzz, FuzzReport};
+use crate::typecheck::check;
+use crate::infer::{infer, Inference};
+use std::process::Command;
+use std::time::{Duration, Instant};
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args))
+    }
+
     println!("RUNNING RUDIMENTARY INTERPRETER TESTS");
 
     test_expr("253354", "253354");
@@ -31,6 +77,229 @@ fn main() {
     test_expr("(+ 1 2 3)", "error");
     test_expr("()", "error");
     test_expr("(+ 1 2", "error");
+    test_expr("(+ 1 2]", "error");
+    test_expr("(with [x 1] x)", "error");
+    test_expr("(+ 1 2) 3", "error");
+
+    test_cse("(+ (* (+ x 1) (+ x 1)) (* (+ x 1) (+ x 1)))");
+    test_cse("(with ([x 3]) (* (+ (* x 2) 1) (- (+ (* x 2) 1) (+ (* x 2) 1))))");
+    test_cse("(with ([x (+ 1 2)]) (with ([y (* x 2)]) (+ (+ (* x y) 1) (+ (* x y) 1))))");
+
+    test_bytecode("(with ([x (- 23 7)]) (+ (/ x 2) (* 3 4)))");
+    test_bytecode("(with ([x 1]) (+ (with ([x (* x 2)]) x) x))");
+    test_bytecode("(with ([x (/ 1 (- 1 1))]) (* 2 3))");
+    test_bytecode("(with ([x (/ 1 (- 1 1))]) (+ y x))");
+    test_bytecode("(* 2147483647 2)");
+
+    test_c("(with ([x (- 23 7)]) (+ (/ x 2) (* 3 4)))", &[]);
+    test_c("(with ([x 1]) (+ (with ([x (* x 2)]) x) x))", &[]);
+    test_c("(with ([z (* x y)]) (- (* z z) (/ x y)))", &[("x", 17), ("y", 5)]);
+    test_c("(+ (/ 7 (- x x)) y)", &[("x", 3), ("y", 4)]);
+    test_c("(* (* x x) x)", &[("x", 2000)]);
+    test_c("(with ([int 3]) (+ int return))", &[("return", 4)]);
+
+    test_wat("(with ([x (- 23 7)]) (+ (/ x 2) (* 3 4)))");
+    test_wat("(with ([z (* x y)]) (- (* z z) (/ x y)))");
+    test_wat("7");
+    test_wat_invalid("(module (func $eval (export \"eval\") (result i32) i32.const 1 i64.const 2 i32.add))");
+    test_wat_invalid("(module (func $eval (export \"eval\") (result i32) local.get $x))");
+    test_wat_invalid("(module (func $main (result i32) i32.const 1))");
+
+    test_to_source(" (     with    (  [  x    (       -     23   7  ) ])    \
+    ( +   (  /    x 2)    ( * 3     4) ))");
+    test_to_source("(with ([x 1]) (+ (with ([x (* x 2)]) x) x))");
+    test_round_trip(1000);
+    test_scoped(1000);
+    test_fuzz(1, 2000);
+    test_fuzz(42, 2000);
+
+    test_format(" (     with    (  [  x    (       -     23   7  ) ])    \
+    ( +   (  /    x 2)    ( * 3     4) ))", 80);
+    test_format(" (     with    (  [  x    (       -     23   7  ) ])    \
+    ( +   (  /    x 2)    ( * 3     4) ))", 20);
+    test_format("(with ([x (* (+ alpha beta) (- alpha beta))]) (with ([y (/ x 2)]) (+ (* x y) (- x y))))", 30);
+
+    test_expr("; total\n(+ 1 #| one #| nested |# |# 2) ; end", "3");
+    test_expr("(+ 1 #;(* 2 3) 2)", "3");
+    test_expr("(+ 1 #| open 2)", "error");
+    test_comments("; Area of a rectangle.\n(with ([w 3] ; width\n) (* w #| height |# 4))",
+                  &["; Area of a rectangle.", "; width", "#| height |#"], 80);
+    test_comments("(with (#| the binding |# [x (+ 1 2)]) ; x is three\n  (+ x #;(* x x) x))",
+                  &["#| the binding |#", "; x is three", "#;(* x x)"], 80);
+    test_comments("(with ([x (- 23 7)]) ; bind x\n  (+ (/ x 2) ; half\n     (* 3 4)))", &["; bind x", "; half"], 20);
+    test_comments("(+ ; sum of\n   1 2)", &["; sum of"], 80);
+    test_comments("(fun (x) #| b |# (+ x 1))", &["#| b |#"], 80);
+    test_comment_placement("(fun (x) #| b |# (+ x 1))");
+    test_comments("(fun ([x : ; c\nnumber]) x)", &["; c"], 80);
+    test_comment_placement("(fun ([x : ; c\nnumber]) x)");
+    test_comment_placement("(fun ([f : (number #|a|# -> number)]) f)");
+    test_comment_placement("(with ([x : #| t |# number 1]) x)");
+    test_comment_placement("(fun ([l : (listof #| t |# number)]) l)");
+    test_comment_placement("(fun ([r : (record #| c |#)]) r)");
+    test_comment_placement("(fun ([r : (record #| k |# [a : ; n\nnumber] [b #| c |# : string])]) r)");
+    test_comments("(list ; only\n)", &["; only"], 80);
+    test_comment_placement("(list ; only\n)");
+    test_comment_placement("(list #| c |#)");
+    test_comment_placement("(list #;1)");
+    test_comments("(record ; only\n)", &["; only"], 80);
+    test_comment_placement("(record ; only\n)");
+    test_comment_placement("(record #| c |#)");
+    test_comment_placement("(record #;[x 1])");
+
+    test_program("(define x (- 23 7))\n(define y (* x 2))\n(+ x y)\n(with ([x 1]) (+ x y))", "x = 16, y = 32, 48, 33");
+    test_program("(define x 1) (define x (+ x 1)) x", "x = 1, x = 2, 2");
+    test_program("(define x (/ 1 (- 1 1)))\n(+ x 1)\n7 ; still runs", "error, error, 7");
+
+    test_recovery("(+ (% 1 2) (with ([x 0]) (* x (- 1))))", &["1:4", "1:22", "1:31"]);
+    test_recovery("(with ([x (+ 1 2)]) (+ x [y]))", &["1:26"]);
+    test_recovery("(+ 1 (* 2 3)", &["1:13"]);
+    test_recovery("(with ([x 1]) (+ x 2))", &[]);
+    test_program_recovery("(define 1 2)\n(define x (+ 1 2 3))\n(+ x y)\n(* 2 3)", &["1:9", "2:11"]);
+    test_program("; nothing here", "");
+    test_program("(define x 1) (+ (define y 2) x)", "error");
+    test_program("(define define 1)", "error");
+    test_format_program("(define x (- 23 7))\n(define #| d |# y (* x 2))\n\n(define big (with ([z (* (+ x y) (- x y))]) (+ z (* z (+ z 1)))))\n; last\n(+ x y)", 40);
+
+    test_trace("(with ([x (- 23 7)]) (+ (/ x 2) (* 3 4)))");
+    test_trace("(with ([x 1]) (+ (with ([x (* x 2)]) x) x))");
+    test_trace("(with ([x (/ 1 (- 1 1))]) (+ 1 2))");
+    test_trace("(with ([x y]) (with ([y 1]) (+ x y)))");
+    test_trace("(+ (* 2 3) (/ y 2))");
+    test_deep_trace(50_000);
+
+    test_debug("(with ([x (- 23 7)])\n  (+ (/ x 2) (* 3 4)))", "step\nstep\nenv\nnext\nstep\nstep\nenv\nfinish\ncontinue\n");
+    test_debug("(with ([x (- 23 7)])\n  (+ (/ x 2) (* 3 4)))", "break 2:14\nbreak y\nbreak x\nbreakpoints\ndelete 2\ncontinue\nwhere\ncontinue\ncontinue\n");
+    test_debug("(with ([x (/ 1 (- 1 1))]) (+ x 1))", "break x\nc\ne\nn\n");
+    test_debug("(+ 1 2)", "bogus\nbreak 1:\nquit\n");
+
+    let blowup: String = exponential_expr(24);
+    let limits: EvalLimits = EvalLimits{ max_size: Some(100_000), ..EvalLimits::default() };
+    test_limits("(with ([x (- 23 7)]) (+ (/ x 2) (* 3 4)))", &limits, "20");
+    test_limits(&exponential_expr(4), &EvalLimits{ max_steps: Some(20), ..EvalLimits::default() }, "limit exceeded");
+    test_limits(&exponential_expr(4), &EvalLimits{ max_steps: Some(100), ..EvalLimits::default() }, "16");
+    test_limits(&blowup, &limits, "limit exceeded");
+    test_limits(&blowup, &EvalLimits{ max_depth: Some(10), ..EvalLimits::default() }, "limit exceeded");
+    let deadline: Option<Instant> = Some(Instant::now() + Duration::from_millis(50));
+    test_limits(&blowup, &EvalLimits{ deadline, ..EvalLimits::default() }, "limit exceeded");
+    test_limits("(with ([x (/ 1 (- 1 1))]) (+ x 1))", &limits, "error");
+    let uses: String = format!("(define a (list 1 2 3 4 5 6 7 8 9 10))\n(list{})\n(first a)", " a".repeat(20_000));
+    test_program_limits(&uses, &limits, "a = (list 1 2 3 4 5 6 7 8 9 10), limit exceeded, 1");
+
+    test_deep(100_000);
+    test_deep_subst(200_000);
+    test_deep_typed(100_000);
+    test_deep_infer(100_000);
+    test_deep_list(100_000, 20_000);
+    test_deep_record(100_000, 20_000);
+    test_deep_parse(1_000_000, None);
+    test_deep_parse(1_000, Some(100));
+    test_deep_datum_comment(200_000, None);
+    test_deep_datum_comment(1_000, Some(100));
+    test_deep_annotation(20_000, "(listof ", ")");
+    test_deep_annotation(20_000, "(", " -> number)");
+    test_deep_annotation(99, "(listof ", ")");
+
+    test_arena("(with ([x (- 23 7)]) (+ (/ x 2) (* 3 4)))", "20");
+    test_arena("(with ([x 1]) (+ (with ([x (* x 2)]) x) x))", "3");
+    test_arena("(with ([x y]) (with ([y 5]) x))", "error");
+    test_arena("(with ([x y]) (+ 1 (* 2 y)))", "error");
+    test_arena(&exponential_expr(16), "65536");
+    test_arena_memory(10_000);
+    test_shared("(+ (* (+ x 1) (+ x 1)) (* (+ x 1) (+ x 1)))", "error", 5);
+    test_shared("(with ([x 3]) (+ (* (+ x 1) (+ x 1)) (* (+ x 1) (+ x 1))))", "32", 7);
+    test_shared(&blowup, "16777216", 75);
+
+    test_dot("(with ([x (- 23 7)]) (+ (/ x 2) (* 3 4)))", false);
+    test_dot("(with ([x 1]) (+ (with ([x (* x 2)]) x) x))", true);
+
+    test_json("(with ([x (- 23 7)]) (+ (/ x 2) (* 3 4)))");
+    test_json("gvtct");
+    test_json_error(r#"{"type": "Binary", "op": "+", "left": {"type": "Number", "value": 1}}"#);
+    test_json_error(r#"{"type": "With", "binding": {"identifier": "x", "value": {"type": "Id", "name": "with"}}, "body": {"type": "Id", "name": "x"}}"#);
+    test_json_error(r#"{"type": "Binary", "op": "%", "left": {"type": "Number", "value": 1}, "right": {"type": "Number", "value": 2}}"#);
+    test_json_error(r#"{"type": "Number", "value": 2147483648}"#);
+    test_json_error("{\"type\": \"Number\",\n \"value\": 1,}");
+    test_json_round_trip(1000);
+    test_deep_json(20_000);
+    test_json_error(&format!("{}{}", "[".repeat(100_000), "]".repeat(100_000)));
+    test_json_error(&format!(r#"{{"type": "Fun", "param": "x", "annotation": {}"number"{}, "body": {{"type": "Id", "name": "x"}}}}"#, r#"{"listof": "#.repeat(1000), "}".repeat(1000)));
+
+    test_eval("(if (< 1 2) 10 20)", "10");
+    test_eval("(with ([double (fun (x) (* x 2))]) (double 21))", "42");
+    test_eval("((fun ([x : number]) (= x 3)) 3)", "true");
+    test_eval("(with ([y 1]) (with ([f (fun (x) y)]) (with ([y 2]) (f 5))))", "1");
+    test_eval("(with ([x y]) (fun (y) (+ x y)))", "(fun (tmpa) (+ y tmpa))");
+    test_eval("(+ 1 (< 1 2))", "error");
+    test_eval("(if 1 2 3)", "error");
+    test_eval("(5 1)", "error");
+    test_expr("(fun (x) x)", "error");
+    test_cse("(fun ([x : number]) (+ (* (+ x 1) (+ x 1)) (* (+ x 1) (+ x 1))))");
+    test_format("(with ([clamp : (number -> number) (fun ([x : number]) (if (< x 1) 1 (if (> x 9) 9 x)))]) (clamp 12))", 60);
+    test_json("(with ([f : (number -> boolean) (fun ([x : number]) (< x 1))]) (if (f 1) true false))");
+    test_program("(define inc (fun (x) (+ x 1)))\n(inc 41)\n(define yes (= 1 1))", "inc = (fun (x) (+ x 1)), 42, yes = true");
+
+    test_typecheck("(with ([x : number 5]) (if (< x 3) x (* x 2)))", "number");
+    test_typecheck("(fun ([x : number]) (> x 1))", "(number -> boolean)");
+    test_typecheck("(with ([twice (fun ([f : (number -> number)]) (fun ([x : number]) (f (f x))))]) \
+    ((twice (fun ([n : number]) (+ n 3))) 10))", "number");
+    test_typecheck("(+ 1 (< 1 2))", "1:6");
+    test_typecheck("(with ([x : boolean 5]) x)", "1:21");
+    test_typecheck("(if (< 1 2) 1 false)", "1:15");
+    test_typecheck("(fun (x) x)", "1:7");
+    test_typecheck("((< 1 2) 3)", "1:2");
+    test_typecheck("(with ([x 1]) y)", "1:15");
+
+    test_infer("(with ([id (fun (x) x)]) (if (id true) (id 1) 2))", "number");
+    test_infer("(fun (f) (fun (g) (fun (x) (f (g x)))))", "(('a -> 'b) -> (('c -> 'a) -> ('c -> 'b)))");
+    test_infer("(with ([k (fun (x) (fun (y) x))]) k)", "('a -> ('b -> 'a))");
+    test_infer("(fun (x) (with ([y x]) (+ y 1)))", "(number -> number)");
+    test_infer("(fun (f) (if (f 1) (f true) false))", "1:23");
+    test_infer("(fun (x) (x x))", "1:11");
+    test_infer("(with ([n : number true]) n)", "1:20");
+
+    test_eval(r#"(string-append "total: " (number->string (* 6 7)))"#, r#""total: 42""#);
+    test_eval(r#"(string-length "tab\there")"#, "8");
+    test_eval(r#"(substring "hello world" 6 11)"#, r#""world""#);
+    test_eval(r#"(with ([s "say \"hi\"\n"]) s)"#, r#""say \"hi\"\n""#);
+    test_eval(r#"(+ 1 (string->number "41"))"#, "42");
+    test_eval(r#"(+ 1 "2")"#, "error");
+    test_eval(r#"(string->number "forty")"#, "error");
+    test_eval(r#"(substring "abc" 2 5)"#, "error");
+    test_eval(r#"(string-length "a\qb")"#, "error");
+    test_eval(r#"(string-length "open)"#, "error");
+    test_json(r#"(with ([label : string "a \"b\"\n"]) (string-append label "c"))"#);
+    test_typecheck(r#"(fun ([s : string]) (string-length s))"#, "(string -> number)");
+    test_typecheck(r#"(substring "abc" 1 "2")"#, "1:20");
+    test_infer(r#"(fun (s) (string-append s (number->string (string-length s))))"#, "(string -> string)");
+
+    test_eval("(cons 1 (list 2 3))", "(list 1 2 3)");
+    test_eval("(rest (rest (list 1 2)))", "empty");
+    test_eval("(with ([xs (list 4 5 6)]) (+ (first xs) (first (rest xs))))", "9");
+    test_eval("(with ([xs (list 1 2)]) (with ([f (fun (y) (cons y xs))]) (f 3)))", "(list 3 1 2)");
+    test_eval("(with ([len (fun (self) (fun (l) (if (empty? l) 1 (+ 1 ((self self) (rest l))))))]) ((len len) (list 7 8 9)))", "4");
+    test_eval(r#"(list "a" (list true) (fun (x) x))"#, r#"(list "a" (list true) (fun (x) x))"#);
+    test_eval("(first empty)", "error");
+    test_eval("(cons 1 2)", "error");
+    test_json("(with ([xs : (listof number) empty]) (if (empty? xs) (list) (cons 1 xs)))");
+    test_typecheck("(fun ([l : (listof number)]) (cons (first l) (rest l)))", "((listof number) -> (listof number))");
+    test_typecheck("(first empty)", "1:8");
+    test_typecheck("(cons 1 (list true))", "1:9");
+    test_infer("(fun (l) (if (empty? l) empty (rest l)))", "((listof 'a) -> (listof 'a))");
+    test_infer("(list 1 true)", "1:9");
+
+    test_eval("(record [x 1] [y (+ 1 2)])", "(record [x 1] [y 3])");
+    test_eval("(get (record [x 1] [y 2]) y)", "2");
+    test_eval("(with ([p (record [x 4] [y 5])]) (* (get p x) (get p y)))", "20");
+    test_eval("(get (get (record [inner (record [z 6])]) inner) z)", "6");
+    test_eval("(get (record [x 1]) y)", "error");
+    test_eval("(get 1 x)", "error");
+    test_format("(get (record [alpha (+ 100 200)] [beta 2]) alpha)", 20);
+    test_json("(with ([p : (record [x : number] [ys : (listof string)]) (record [x 1] [ys empty])]) (get p x))");
+    test_typecheck("(fun ([p : (record [x : number] [y : boolean])]) (if (get p y) (get p x) 1))", "((record [x : number] [y : boolean]) -> number)");
+    test_typecheck("(get (record [x 1]) y)", "1:21");
+    test_typecheck("(with ([p : (record [n : number] [l : (listof number)]) (record [n 1] [l empty])]) (get p l))", "(listof number)");
+    test_infer("(fun (n) (record [n n] [s (number->string n)]))", "(number -> (record [n : number] [s : string]))");
+    test_infer("(fun (p) (get p x))", "1:15");
     println!("{}", "=".repeat(80));
 }
 
@@ -60,3 +329,874 @@ fn test_expr(string_rep: &str, expected: &str) {
     }
     println!("Expected: {}", expected)
 }
+
+// test_eval prints the value of the given expression, which may be of any
+// type.
+fn test_eval(string_rep: &str, expected: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let ast: Expr;
+    match parse(string_rep.to_string()) {
+        Ok(expr) => ast = expr,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            println!("Expected: {}", expected);
+            return
+        }
+    }
+    pretty_print(&ast);
+    print!("Test Eval: ");
+    match eval(&ast) {
+        Ok(value) => println!("{}", value),
+        Err(msg) => println!("Error: {}", msg),
+    }
+    println!("Expected: {}", expected)
+}
+
+// test_typecheck prints the type of the given expression, or the type error in
+// it, which is expected at the given location.
+fn test_typecheck(string_rep: &str, expected: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    print!("Test Type Check: ");
+    let result: Result<String, String> = parse(string_rep.to_string()).and_then(|ast| check(&ast)).map(|ty| ty.to_string());
+    let actual: &str = match &result {
+        Ok(ty) => {
+            println!("{}", ty);
+            ty
+        },
+        Err(msg) => {
+            println!("Error: {}", msg);
+            msg.split(": ").next().unwrap()
+        },
+    };
+    println!("Expected: {}", expected);
+    println!("Matches Expected: {}", actual == expected)
+}
+
+// test_infer prints the inferred type of the given expression and of every
+// identifier bound in it, or the type error, which is expected at the given
+// location.
+fn test_infer(string_rep: &str, expected: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    print!("Test Infer: ");
+    let result: Result<Inference, String> = parse(string_rep.to_string()).and_then(|ast| infer(&ast));
+    let actual: String = match &result {
+        Ok(inference) => {
+            println!("{}", inference.ty);
+            for (id, ty) in &inference.bindings {
+                println!("  {} : {}", id.val, ty);
+            }
+            inference.ty.to_string()
+        },
+        Err(msg) => {
+            println!("Error: {}", msg);
+            msg.split(": ").next().unwrap().to_string()
+        },
+    };
+    println!("Expected: {}", expected);
+    println!("Matches Expected: {}", actual == expected)
+}
+
+fn test_cse(string_rep: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let ast: Expr;
+    match parse(string_rep.to_string()) {
+        Ok(expr) => ast = expr,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            return
+        }
+    }
+    println!("Test CSE:");
+    let eliminated: Expr = cse(&ast);
+    pretty_print(&eliminated);
+    println!("Test CSE Substituted: {}", eliminated.clone().replace() == ast.clone().replace());
+    println!("Test CSE Calc: {}", calc(&eliminated) == calc(&ast));
+}
+
+fn test_bytecode(string_rep: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let ast: Expr;
+    match parse(string_rep.to_string()) {
+        Ok(expr) => ast = expr,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            return
+        }
+    }
+    println!("Test Compile:");
+    let chunk: Chunk = compile(&ast);
+    println!("{}", disassemble(&chunk));
+    let result: Result<i32, String> = run(&chunk);
+    match &result {
+        Ok(val) => println!("Test VM: {}", val),
+        Err(msg) => println!("Test VM: Error: {}", msg),
+    }
+    println!("Matches Calc: {}", result == calc(&ast));
+}
+
+fn test_c(string_rep: &str, args: &[(&str, i32)]) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let mut ast: Expr;
+    match parse(string_rep.to_string()) {
+        Ok(expr) => ast = expr,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            return
+        }
+    }
+    println!("Test C:");
+    let source: String = to_c(&ast, "formula");
+    println!("{}", source);
+
+    // Free identifiers are parameters in order of first appearance, which is
+    // the order the arguments are given in.
+    let call_args: Vec<String> = args.iter().map(|(_, val)| val.to_string()).collect();
+    let driver: String = format!(concat!(
+        "{}\n#include <stdio.h>\n",
+        "int main(void) {{\n",
+        "    int32_t value;\n",
+        "    int status = formula({}&value);\n",
+        "    if (status == RINTERP_OK) printf(\"%d\", value);\n",
+        "    else printf(\"Error: %s\", rinterp_status_message(status));\n",
+        "    return 0;\n",
+        "}}\n",
+    ), source, call_args.iter().map(|arg| format!("{}, ", arg)).collect::<String>());
+
+    let dir: std::path::PathBuf = std::env::temp_dir().join(format!("rinterp-c-{}", std::process::id()));
+    let compiled: Result<String, String> = std::fs::create_dir_all(&dir)
+        .and_then(|_| std::fs::write(dir.join("formula.c"), &driver))
+        .map_err(|err| err.to_string())
+        .and_then(|_| {
+            let status = Command::new("cc")
+                .args(["-std=c99", "-Wall", "-Werror", "-Wno-unused-function", "-o"])
+                .arg(dir.join("formula")).arg(dir.join("formula.c"))
+                .status().map_err(|err| err.to_string())?;
+            if !status.success() {
+                return Err("cc failed".to_string())
+            }
+            let output = Command::new(dir.join("formula")).output().map_err(|err| err.to_string())?;
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        });
+    let _ = std::fs::remove_dir_all(&dir);
+    let actual: String;
+    match compiled {
+        Ok(output) => actual = output,
+        Err(msg) => {
+            println!("Test C Skipped: {}", msg);
+            return
+        }
+    }
+
+    for (name, val) in args.iter().rev() {
+        let identifier: Box<Id> = Box::new(Id{ val: name.to_string(), meta: Meta::default() });
+        let binding: Binding = Binding{ identifier, annotation: None, replace: Number{ val: *val, meta: Meta::default() }.into(), meta: Meta::default() };
+        ast = With{ binding, input: ast, meta: Meta::default() }.into();
+    }
+    let expected: String = match calc(&ast) {
+        Ok(val) => val.to_string(),
+        Err(msg) => format!("Error: {}", msg),
+    };
+    println!("Test C Run: {}", actual);
+    println!("Matches Calc: {}", actual == expected);
+}
+
+fn test_wat(string_rep: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let ast: Expr;
+    match parse(string_rep.to_string()) {
+        Ok(expr) => ast = expr,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            return
+        }
+    }
+    println!("Test WAT:");
+    let wat: String = to_wat(&ast);
+    println!("{}", wat);
+    match validate(&wat) {
+        Ok(()) => println!("Test Validate: valid"),
+        Err(msg) => println!("Test Validate: Error: {}", msg),
+    }
+    println!("Expected: valid")
+}
+
+fn test_wat_invalid(wat: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Module: {}\n", wat);
+    match validate(wat) {
+        Ok(()) => println!("Test Validate: valid"),
+        Err(msg) => println!("Test Validate: Error: {}", msg),
+    }
+    println!("Expected: error")
+}
+
+fn test_to_source(string_rep: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let ast: Expr;
+    match parse(string_rep.to_string()) {
+        Ok(expr) => ast = expr,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            return
+        }
+    }
+    let source: String = to_source(&ast);
+    println!("Test To Source: {}", source);
+    println!("Round Trips: {}", parse(source).ok() == Some(ast));
+}
+
+// test_round_trip checks that parse(to_source(e)) == e for the given number of
+// randomly generated expressions.
+fn test_round_trip(cases: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Round Trip Property:\n");
+    let mut rng: Rng = Rng::new(0x2545_f491_4f6c_dd1d);
+    let mut passed: usize = 0;
+    for _ in 0..cases {
+        let ast: Expr = random_expr(&mut rng, &GenOptions::default());
+        let source: String = to_source(&ast);
+        if parse(source.clone()).ok() == Some(ast) {
+            passed += 1
+        } else {
+            println!("Failed: {}", source)
+        }
+    }
+    println!("Test Round Trip: {}/{} passed", passed, cases);
+    println!("Expected: {}/{} passed", cases, cases)
+}
+
+// test_scoped checks that random expressions generated without ill-scoped
+// identifiers never fail on an unbound identifier.
+fn test_scoped(cases: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Scoped Generation:\n");
+    let mut rng: Rng = Rng::new(7);
+    let options: GenOptions = GenOptions{ ill_scoped: false, binding_percent: 70, ..GenOptions::default() };
+    let limits: EvalLimits = EvalLimits{ max_size: Some(100_000), ..EvalLimits::default() };
+    let mut unbound: usize = 0;
+    for _ in 0..cases {
+        let ast: Expr = random_expr(&mut rng, &options);
+        if let Err(msg) = calc_with_limits(&ast, &limits) {
+            if msg.contains("failed to replace identifier") {
+                println!("Unbound: {}", to_source(&ast));
+                unbound += 1
+            }
+        }
+    }
+    println!("Test Scoped: {} of {} unbound", unbound, cases);
+    println!("Expected: 0 of {} unbound", cases)
+}
+
+// test_fuzz runs the fuzz harness for the given seed and number of cases and
+// prints any broken invariants.
+fn test_fuzz(seed: u64, cases: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Fuzz: seed {}, {} programs\n", seed, cases);
+    let report: FuzzReport = fuzz(seed, cases, &GenOptions::default());
+    for failure in &report.failures {
+        println!("Failed: {}", failure);
+    }
+    println!("Test Fuzz: {} sources, {} parsed, {} evaluated, {} failures",
+             report.cases, report.parsed, report.evaluated, report.failures.len());
+    println!("Expected: 0 failures")
+}
+
+fn test_format(string_rep: &str, width: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let formatted: String;
+    match format(string_rep, width) {
+        Ok(source) => formatted = source,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            return
+        }
+    }
+    println!("Test Format (width {}):", width);
+    println!("{}", formatted);
+    println!("Same Tree: {}", parse(formatted.clone()).ok() == parse(string_rep.to_string()).ok());
+    println!("Idempotent: {}", format(&formatted, width) == Ok(formatted.clone()));
+}
+
+// test_comments checks that the given comments in the given program survive
+// both to_source and format.
+fn test_comments(string_rep: &str, comments: &[&str], width: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let ast: Expr;
+    match parse(string_rep.to_string()) {
+        Ok(expr) => ast = expr,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            return
+        }
+    }
+    let source: String = to_source(&ast);
+    let formatted: String = format(string_rep, width).unwrap_or_default();
+    println!("Test To Source:\n{}\n", source);
+    println!("Test Format (width {}):\n{}", width, formatted);
+    let kept = |text: &str| comments.iter().all(|comment| text.contains(comment));
+    println!("Comments Kept: {}", kept(&source) && kept(&formatted));
+    println!("Round Trips: {}", parse(source.clone()).ok() == Some(ast) && to_source(&parse(source.clone()).unwrap()) == source);
+    println!("Idempotent: {}", format(&formatted, width) == Ok(formatted.clone()));
+    println!("Expected: true, true, true")
+}
+
+// test_comment_placement checks that printing the given expression leaves
+// every comment where it was written.
+fn test_comment_placement(string_rep: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let source: String = parse(string_rep.to_string()).map(|ast| to_source(&ast)).unwrap_or_else(|msg| msg);
+    println!("Test Comment Placement:\n{}\n", source);
+    println!("Same Text: {}", source == string_rep);
+    println!("Expected: true")
+}
+
+fn test_program(source: &str, expected: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Program: {}\n", source);
+    match parse_program(source) {
+        Ok(program) => {
+            println!("Test Program:");
+            for (form, outcome) in program.forms.iter().zip(evaluate(&program, &EvalLimits::default())) {
+                match outcome {
+                    Ok(outcome) => println!("{}: {}", form.span(), outcome),
+                    Err(msg) => println!("{}: error: {}", form.span(), msg),
+                }
+            }
+        },
+        Err(msg) => println!("Error: {}", msg),
+    }
+    println!("Expected: {}", expected)
+}
+
+// test_format_program checks that formatting the given program keeps each of
+// its forms and their outcomes.
+fn test_format_program(source: &str, width: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Program: {}\n", source);
+    let formatted: String = match format(source, width) {
+        Ok(formatted) => formatted,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            return
+        },
+    };
+    println!("Test Format Program (width {}):", width);
+    println!("{}", formatted);
+    println!("Same Outcomes: {}", outcomes(&formatted) == outcomes(source));
+    println!("Idempotent: {}", format(&formatted, width) == Ok(formatted.clone()));
+}
+
+// outcomes returns the outcome of each form of the given program as text.
+fn outcomes(source: &str) -> Result<Vec<String>, String> {
+    let program: Program = parse_program(source)?;
+    Ok(evaluate(&program, &EvalLimits::default()).into_iter()
+        .map(|outcome| match outcome {
+            Ok(outcome) => outcome.to_string(),
+            Err(msg) => format!("error: {}", msg),
+        })
+        .collect())
+}
+
+// test_recovery checks that parsing the given expression with error recovery
+// reports errors at the given locations, keeps the rest of the tree and prints
+// the source unchanged.
+fn test_recovery(string_rep: &str, expected: &[&str]) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let (ast, errors): (Expr, Vec<String>) = parse_with_recovery(string_rep);
+    println!("Test Recovery:");
+    for msg in &errors {
+        println!("{}", msg);
+    }
+    println!("Expected: errors at [{}]", expected.join(", "));
+    let locations: Vec<&str> = errors.iter().map(|msg| msg.split(": ").next().unwrap()).collect();
+    println!("Matches Expected: {}", locations == expected);
+    println!("Recovered Tree:");
+    pretty_print(&ast);
+    println!("Source Unchanged: {}", to_source(&ast) == string_rep);
+    println!("Test Recovery Calc: {:?}", calc(&ast));
+}
+
+// test_program_recovery checks that parsing the given program with error
+// recovery reports errors at the given locations and still evaluates the
+// forms that parsed.
+fn test_program_recovery(source: &str, expected: &[&str]) {
+    println!("{}", "=".repeat(80));
+    println!("Program: {}\n", source);
+    let (program, errors): (Program, Vec<String>) = parse_program_with_recovery(source);
+    println!("Test Program Recovery:");
+    for msg in &errors {
+        println!("{}", msg);
+    }
+    let locations: Vec<&str> = errors.iter().map(|msg| msg.split(": ").next().unwrap()).collect();
+    println!("Expected: errors at [{}]", expected.join(", "));
+    println!("Matches Expected: {}", locations == expected);
+    for (form, outcome) in program.forms.iter().zip(evaluate(&program, &EvalLimits::default())) {
+        match outcome {
+            Ok(outcome) => println!("{}: {}", form.span(), outcome),
+            Err(msg) => println!("{}: error: {}", form.span(), msg),
+        }
+    }
+}
+
+fn test_trace(string_rep: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let ast: Expr;
+    match parse(string_rep.to_string()) {
+        Ok(expr) => ast = expr,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            return
+        }
+    }
+    let steps: Trace = trace(&ast);
+    println!("Test Trace:");
+    println!("    {}", to_source(&steps.start));
+    for step in &steps.steps {
+        println!("--> {}    [{}]", to_source(&step.expr), step.reduction);
+    }
+    let result: Result<i32, String> = match (&steps.error, steps.steps.last().map(|step| &step.expr).unwrap_or(&steps.start)) {
+        (Some(msg), _) => Err(msg.clone()),
+        (None, Expr::Number(number)) => Ok(number.val),
+        (None, _) => Err("trace ended before a value".to_string()),
+    };
+    match &result {
+        Ok(val) => println!("Test Trace Value: {}", val),
+        Err(msg) => println!("Test Trace Value: Error: {}", msg),
+    }
+    println!("Matches Calc: {}", result == calc(&ast))
+}
+
+// test_deep_trace checks that tracing (+ 1 (+ 1 ... (+ 1 1))) nested to the
+// given depth, which is more than MAX_TRACE_DEPTH, stops with an error at the
+// first node past it: the left input of the addition at that depth.
+fn test_deep_trace(depth: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Deep Trace: {} nested additions\n", depth);
+    let source: String = format!("{}1{}", "(+ 1 ".repeat(depth), ")".repeat(depth));
+    let steps: Trace = trace(&parse(source).unwrap());
+    println!("Test Deep Trace: {} steps, error: {:?}", steps.steps.len(), steps.error);
+    let column: usize = 5 * MAX_TRACE_DEPTH - 1;
+    println!("Expected: 0 steps, error: Some(\"1:{}: expression is nested more than {} levels deep to trace\")", column, MAX_TRACE_DEPTH)
+}
+
+fn test_debug(string_rep: &str, commands: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}", string_rep);
+    println!("Commands: {}\n", commands.trim_end().replace('\n', "; "));
+    let ast: Expr;
+    match parse(string_rep.to_string()) {
+        Ok(expr) => ast = expr,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            return
+        }
+    }
+    println!("Test Debug:");
+    let mut output: Vec<u8> = Vec::new();
+    let result: Result<i32, String> = debug(&ast, &mut commands.as_bytes(), &mut output);
+    print!("{}", String::from_utf8_lossy(&output));
+    match &result {
+        Ok(val) => println!("Result: {}", val),
+        Err(msg) => println!("Result: Error: {}", msg),
+    }
+    println!("Matches Calc: {}", result == calc(&ast) || result == Err(debug::DEBUGGER_QUIT.to_string()))
+}
+
+fn test_limits(string_rep: &str, limits: &EvalLimits, expected: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", if string_rep.len() > 80 { &string_rep[..80] } else { string_rep });
+    let ast: Expr;
+    match parse(string_rep.to_string()) {
+        Ok(expr) => ast = expr,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            return
+        }
+    }
+    match calc_with_limits(&ast, limits) {
+        Ok(val) => println!("Test Limits: {}", val),
+        Err(msg) => {
+            println!("Test Limits: Error: {}", msg);
+            println!("Limit Exceeded: {}", is_limit_exceeded(&msg))
+        },
+    }
+    println!("Expected: {}", expected)
+}
+
+// test_program_limits checks that evaluating the given program within the
+// given limits charges the definitions bound in each form to its limits.
+fn test_program_limits(source: &str, limits: &EvalLimits, expected: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Program: {}\n", if source.len() > 80 { &source[..80] } else { source });
+    let program: Program = parse_program(source).unwrap();
+    println!("Test Program Limits:");
+    for (form, outcome) in program.forms.iter().zip(evaluate(&program, limits)) {
+        match outcome {
+            Ok(outcome) => println!("{}: {}", form.span(), outcome),
+            Err(msg) => println!("{}: error: {}, limit exceeded: {}", form.span(), msg, is_limit_exceeded(&msg)),
+        }
+    }
+    println!("Expected: {}", expected)
+}
+
+// test_deep checks that a tree of the given depth can be evaluated, substituted,
+// printed, cloned and dropped without overflowing the stack. The tree is built
+// directly, as (with ([x 1]) (+ x (+ x ... (+ x 1)))).
+fn test_deep(depth: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Deep Expression: {} nested additions\n", depth);
+    let mut ast: Expr = Number{ val: 1, meta: Meta::default() }.into();
+    for _ in 0..depth {
+        let left: Expr = Id{ val: "x".to_string(), meta: Meta::default() }.into();
+        ast = Binary{ op: Operator::Add, left, right: ast, meta: Meta::default() }.into();
+    }
+    let identifier: Box<Id> = Box::new(Id{ val: "x".to_string(), meta: Meta::default() });
+    let binding: Binding = Binding{ identifier, annotation: None, replace: Number{ val: 1, meta: Meta::default() }.into(), meta: Meta::default() };
+    ast = With{ binding, input: ast, meta: Meta::default() }.into();
+
+    println!("Test Deep Calc: {:?}", calc(&ast));
+    println!("Expected: Ok({})", depth + 1);
+    let source: String = to_source(&ast.clone().replace());
+    println!("Test Deep To Source: {}...", &source[..40]);
+    println!("Source Length: {}", source.len());
+    println!("Expected: {}", 6 * depth + 1);
+    let dot: String = to_dot(&ast, &DotOptions{ clusters: true });
+    println!("Test Deep DOT Edges: {}", dot.matches(" -> ").count());
+    println!("Expected: {}", 2 * depth + 4);
+    println!("Test Deep Type Check: {:?}", check(&ast).map(|ty| ty.to_string()));
+    println!("Expected: Ok(\"number\")");
+    println!("Test Deep Infer: {:?}", infer(&ast).map(|inference| inference.ty.to_string()));
+    println!("Expected: Ok(\"number\")")
+}
+
+// test_deep_subst checks that a binding nested to the given depth can be
+// substituted into a function, which checks whether the bound expression would
+// be captured by its parameter, without overflowing the stack.
+fn test_deep_subst(depth: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Deep Binding: {} nested additions and lists\n", depth);
+    let sum: String = format!("{}1{}", "(+ 1 ".repeat(depth), ")".repeat(depth));
+    let source: String = format!("(with ([x {}]) ((fun (y) 1) 2))", sum);
+    println!("Test Deep Subst: {:?}", parse(source).and_then(|ast| calc(&ast.replace())));
+    println!("Expected: Ok(1)");
+    let list: String = format!("{}1{}", "(list ".repeat(depth), ")".repeat(depth));
+    let source: String = format!("(with ([x {}]) ((fun (y) x) 2))", list);
+    let value: Result<usize, String> = parse(source).and_then(|ast| eval(&ast)).map(|value| value.to_string().len());
+    println!("Test Deep Subst List: {:?}", value);
+    println!("Expected: Ok({})", list.len())
+}
+
+// test_deep_typed checks that a typed program nested to the given depth, as
+// (if (< x 2) (cons x (if (< x 2) (cons x ... empty) empty)) empty), can be
+// type checked without overflowing the stack.
+fn test_deep_typed(depth: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Deep Typed Expression: {} nested conditionals\n", depth);
+    let source: String = format!("(with ([x 1]) {}empty{})", "(if (< x 2) (cons x ".repeat(depth), ") empty)".repeat(depth));
+    let ast: Expr = parse(source).unwrap();
+    println!("Test Deep Type Check: {:?}", check(&ast).map(|ty| ty.to_string()));
+    println!("Expected: Ok(\"(listof number)\")");
+    println!("Test Deep Infer: {:?}", infer(&ast).map(|inference| inference.ty.to_string()));
+    println!("Expected: Ok(\"(listof number)\")")
+}
+
+// test_deep_infer checks that the type of (fun (x) (fun (x) ... x)), with
+// functions nested to the given depth, can be inferred and printed even though
+// the type is nested as deeply as the expression.
+fn test_deep_infer(depth: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Deep Inferred Function: {} nested functions\n", depth);
+    let source: String = format!("{}x{}", "(fun (x) ".repeat(depth), ")".repeat(depth));
+    let ast: Expr = parse(source).unwrap();
+    match infer(&ast) {
+        Ok(inference) => {
+            let ty: String = inference.ty.to_string();
+            let inner: &str = ty.trim_end_matches(')');
+            println!("Test Deep Infer: {}...{}", &ty[..20], &inner[inner.len() - 17..]);
+            println!("Closing Parentheses: {}, Bindings: {}", ty.len() - inner.len(), inference.bindings.len());
+        },
+        Err(msg) => println!("Test Deep Infer: Error: {}", msg),
+    }
+    println!("Expected: ('a -> ('b -> ('c ->...('d3846 -> 'd3846");
+    println!("Closing Parentheses: {}, Bindings: {}", depth, depth)
+}
+
+// test_deep_list checks that (list (list ... 1)), with lists nested to the
+// given depth, can be evaluated, printed, compared and dropped, and that
+// (first (first ... 1)) over a list nested to the second depth reaches the 1.
+fn test_deep_list(depth: usize, first_depth: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Deep List: {} nested lists\n", depth);
+    let source: String = format!("{}1{}", "(list ".repeat(depth), ")".repeat(depth));
+    let value: Result<String, String> = parse(source.clone()).and_then(|ast| eval(&ast)).map(|value| value.to_string());
+    println!("Test Deep List: {:?}", value.as_ref().map(|text| (&text[..20], text.len())));
+    println!("Expected: Ok((\"(list (list (list (l\", {}))", source.len());
+    let twice: Result<bool, String> = parse(source).and_then(|ast| Ok(eval(&ast)? == eval(&ast)?));
+    println!("Test Deep List Equal: {:?}", twice);
+    println!("Expected: Ok(true)");
+    let nested: String = format!("{}1{}", "(list ".repeat(first_depth), ")".repeat(first_depth));
+    let firsts: String = format!("{}{}{}", "(first ".repeat(first_depth), nested, ")".repeat(first_depth));
+    println!("Test Deep First: {:?}", parse(firsts).and_then(|ast| calc(&ast)));
+    println!("Expected: Ok(1)")
+}
+
+// test_deep_record checks that (record [a (record [a ... 1])]), with records
+// nested to the given depth, can be evaluated, printed, compared and dropped,
+// and that (get (get ... a) a) over a record nested to the second depth
+// reaches the 1.
+fn test_deep_record(depth: usize, get_depth: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Deep Record: {} nested records\n", depth);
+    let source: String = format!("{}1{}", "(record [a ".repeat(depth), "])".repeat(depth));
+    let value: Result<String, String> = parse(source.clone()).and_then(|ast| eval(&ast)).map(|value| value.to_string());
+    println!("Test Deep Record: {:?}", value.as_ref().map(|text| (&text[..20], text.len())));
+    println!("Expected: Ok((\"(record [a (record [\", {}))", source.len());
+    let twice: Result<bool, String> = parse(source).and_then(|ast| Ok(eval(&ast)? == eval(&ast)?));
+    println!("Test Deep Record Equal: {:?}", twice);
+    println!("Expected: Ok(true)");
+    let nested: String = format!("{}1{}", "(record [a ".repeat(get_depth), "])".repeat(get_depth));
+    let gets: String = format!("{}{}{}", "(get ".repeat(get_depth), nested, " a)".repeat(get_depth));
+    println!("Test Deep Get: {:?}", parse(gets).and_then(|ast| calc(&ast)));
+    println!("Expected: Ok(1)")
+}
+
+// test_arena checks that evaluating the given expression in an Arena agrees
+// with calc, and that the tree survives a trip through the arena.
+fn test_arena(string_rep: &str, expected: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let ast: Expr;
+    match parse(string_rep.to_string()) {
+        Ok(expr) => ast = expr,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            return
+        }
+    }
+    let result: Result<i32, String> = calc_arena(&ast, &EvalLimits::default());
+    match &result {
+        Ok(val) => println!("Test Arena: {}", val),
+        Err(msg) => println!("Test Arena: Error: {}", msg),
+    }
+    println!("Expected: {}", expected);
+    println!("Matches Calc: {}", result == calc(&ast));
+    let mut arena: Arena = Arena::new();
+    let root: Tree = arena.lower(&ast);
+    println!("Arena Round Trip: {}", arena.to_expr(root) == ast);
+}
+
+// test_arena_memory compares the memory used by a parsed program of the given
+// number of nested additions, (with ([x 1]) (+ x (+ x ... (+ x 1)))), as a
+// boxed tree and in an Arena.
+fn test_arena_memory(depth: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Arena Memory: {} nested additions\n", depth);
+    let source: String = format!("(with ([x 1]) {}1{})", "(+ x ".repeat(depth), ")".repeat(depth));
+    let ast: Expr = parse(source).unwrap();
+    let mut arena: Arena = Arena::new();
+    arena.lower(&ast);
+    let boxed: usize = heap_size(&ast);
+    let indexed: usize = arena.heap_size();
+    println!("Boxed Tree: {} bytes", boxed);
+    println!("Arena: {} bytes for {} nodes", indexed, arena.len());
+    println!("Arena Smaller: {}", indexed < boxed);
+    println!("Expected: true")
+}
+
+// test_shared checks that a hash-consed Arena stores the given expression in
+// the given number of nodes, and that it evaluates the expression within the
+// limits that substitution exceeds on exponential_expr(24).
+fn test_shared(string_rep: &str, expected: &str, nodes: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let ast: Expr = parse(string_rep.to_string()).unwrap();
+    let mut arena: Arena = Arena::hash_consed();
+    arena.lower(&ast);
+    println!("Shared Nodes: {}", arena.len());
+    println!("Expected: {}", nodes);
+    let limits: EvalLimits = EvalLimits{ max_size: Some(100_000), ..EvalLimits::default() };
+    match calc_arena(&ast, &limits) {
+        Ok(val) => println!("Test Shared: {}", val),
+        Err(msg) => println!("Test Shared: Error: {}", msg),
+    }
+    println!("Expected: {}", expected)
+}
+
+// test_deep_parse checks that source text nested to the given depth, as
+// (+ 1 (+ 1 ... (+ 1 1))), can be parsed without overflowing the stack, and
+// that parse_within rejects it if it is nested deeper than max_depth.
+fn test_deep_parse(depth: usize, max_depth: Option<usize>) {
+    println!("{}", "=".repeat(80));
+    println!("Deep Source: {} nested additions\n", depth);
+    let source: String = format!("{}1{}", "(+ 1 ".repeat(depth), ")".repeat(depth));
+    let result: Result<Expr, String> = match max_depth {
+        Some(max_depth) => parse_within(&source, max_depth),
+        None => parse(source),
+    };
+    match result {
+        Ok(ast) => {
+            println!("Test Deep Parse: Ok");
+            println!("Test Deep Parse Calc: {:?}", calc(&ast));
+            println!("Expected: Ok({})", depth + 1)
+        },
+        Err(msg) => {
+            println!("Test Deep Parse: Error: {}", msg);
+            println!("Expected: Error: 1:{}: expression is nested more than {} levels deep",
+                     5 * max_depth.unwrap_or(0) + 1, max_depth.unwrap_or(0))
+        },
+    }
+}
+
+// test_deep_datum_comment checks that datum comments nested to the given
+// depth, as (+ 1 #;(#;(... #;()))) 2), or one after the other, as
+// #; #; ... 1 1 ... 2, can be read without overflowing the stack, and that
+// parse_within counts the lists inside a datum comment toward max_depth.
+fn test_deep_datum_comment(depth: usize, max_depth: Option<usize>) {
+    println!("{}", "=".repeat(80));
+    println!("Deep Datum Comments: {} nested datum comments\n", depth);
+    let nested: String = format!("(+ 1 {}{} 2)", "#;(".repeat(depth), ")".repeat(depth));
+    let result: Result<Expr, String> = match max_depth {
+        Some(max_depth) => parse_within(&nested, max_depth),
+        None => parse(nested),
+    };
+    match max_depth {
+        None => {
+            println!("Test Deep Datum Comment: {:?}", result.and_then(|ast| calc(&ast)));
+            println!("Expected: Ok(3)")
+        },
+        Some(max_depth) => {
+            println!("Test Deep Datum Comment: {:?}", result.map(|_| ()));
+            println!("Expected: Err(\"1:{}: expression is nested more than {} levels deep\")", 3 * max_depth + 5, max_depth)
+        },
+    }
+    let repeated: String = format!("{}{}2", "#; ".repeat(depth), "1 ".repeat(depth));
+    println!("Test Repeated Datum Comments: {:?}", parse(repeated).and_then(|ast| calc(&ast)));
+    println!("Expected: Ok(2)")
+}
+
+// test_deep_annotation checks that a function whose parameter's type is the
+// given prefix and suffix nested to the given depth around number is parsed if
+// it is nested at most MAX_TYPE_DEPTH levels deep, and rejected with an error
+// rather than overflowing the stack otherwise.
+fn test_deep_annotation(depth: usize, prefix: &str, suffix: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Deep Annotation: {} nested {}...{}\n", depth, prefix, suffix);
+    let head: &str = "(fun ([x : ";
+    let source: String = format!("{}{}number{}]) x)", head, prefix.repeat(depth), suffix.repeat(depth));
+    match parse(source) {
+        Ok(ast) => println!("Test Deep Annotation: {:?}", check(&ast).map(|ty| ty.to_string().len())),
+        Err(msg) => println!("Test Deep Annotation: Error: {}", msg),
+    }
+    if depth < MAX_TYPE_DEPTH {
+        println!("Expected: Ok({})", 2 * (prefix.len() + suffix.len()) * depth + 18)
+    } else {
+        println!("Expected: Error: 1:{}: type is nested more than {} levels deep", head.len() + prefix.len() * MAX_TYPE_DEPTH + 1, MAX_TYPE_DEPTH)
+    }
+}
+
+// exponential_expr returns a program of the given number (at most 25) of nested
+// Withs, each of which doubles the size of the tree after substitution.
+fn exponential_expr(depth: usize) -> String {
+    let name = |i: usize| ((b'a' + i as u8) as char).to_string();
+    let mut source: String = "(with ([a 1]) ".to_string();
+    for i in 1..=depth {
+        source.push_str(&format!("(with ([{} (+ {} {})]) ", name(i), name(i - 1), name(i - 1)));
+    }
+    source.push_str(&format!("{}{}", name(depth), ")".repeat(depth + 1)));
+    source
+}
+
+fn test_dot(string_rep: &str, clusters: bool) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let ast: Expr;
+    match parse(string_rep.to_string()) {
+        Ok(expr) => ast = expr,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            return
+        }
+    }
+    let options: DotOptions = DotOptions{ clusters };
+    println!("Test DOT:");
+    println!("{}", to_dot(&ast, &options));
+    println!("Test DOT Subst:");
+    println!("{}", to_dot(&ast.replace(), &options));
+}
+
+fn test_json(string_rep: &str) {
+    println!("{}", "=".repeat(80));
+    println!("Expression: {}\n", string_rep);
+    let ast: Expr;
+    match parse(string_rep.to_string()) {
+        Ok(expr) => ast = expr,
+        Err(msg) => {
+            println!("Error: {}", msg);
+            return
+        }
+    }
+    let json: String = to_json(&ast);
+    println!("Test JSON: {}", json);
+    match from_json(&json) {
+        Ok(decoded) => println!("Round Trips: {}", decoded == ast),
+        Err(msg) => println!("Error: {}", msg),
+    }
+}
+
+fn test_json_error(json: &str) {
+    println!("{}", "=".repeat(80));
+    println!("JSON: {}\n", if json.len() > 200 { &json[..200] } else { json });
+    match from_json(json) {
+        Ok(ast) => println!("Test Decode: {}", to_source(&ast)),
+        Err(msg) => println!("Test Decode: Error: {}", msg),
+    }
+    println!("Expected: error")
+}
+
+// test_json_round_trip checks that decoding the JSON encoding of a parsed
+// expression yields the same tree for the given number of random expressions.
+fn test_json_round_trip(cases: usize) {
+    println!("{}", "=".repeat(80));
+    println!("JSON Round Trip Property:\n");
+    let mut rng: Rng = Rng::new(0x9e37_79b9_7f4a_7c15);
+    let mut passed: usize = 0;
+    for _ in 0..cases {
+        let source: String = to_source(&random_expr(&mut rng, &GenOptions::default()));
+        let ast: Expr = parse(source.clone()).unwrap();
+        if from_json(&to_json(&ast)).ok() == Some(ast) {
+            passed += 1
+        } else {
+            println!("Failed: {}", source)
+        }
+    }
+    println!("Test JSON Round Trip: {}/{} passed", passed, cases);
+    println!("Expected: {}/{} passed", cases, cases)
+}
+
+// test_deep_json checks that JSON for (+ 1 (+ 1 ... (+ 1 1))) nested to the
+// given depth is read and decoded without overflowing the stack.
+fn test_deep_json(depth: usize) {
+    println!("{}", "=".repeat(80));
+    println!("Deep JSON: {} nested additions\n", depth);
+    let one: &str = r#"{"type":"Number","value":1}"#;
+    let open: String = format!(r#"{{"type":"Binary","op":"+","left":{},"right":"#, one);
+    let source: String = format!("{}{}{}", open.repeat(depth), one, "}".repeat(depth));
+    match from_json(&source) {
+        Ok(ast) => println!("Test Deep JSON Calc: {:?}", calc(&ast)),
+        Err(msg) => println!("Test Deep JSON: Error: {}", msg),
+    }
+    println!("Expected: Ok({})", depth + 1)
+}
@@ -1,96 +1,232 @@
 // ============================================================================
 // GRAMMAR:
-// WAE = Number
-//     | (+ WAE WAE)
-//     | (- WAE WAE)
-//     | (* WAE WAE)
-//     | (/ WAE WAE)
-//     | (With ([x WAE]) WAE)
-//     | x
+// WAE  = Number
+//      | true
+//      | false
+//      | String
+//      | empty
+//      | (+ WAE WAE)
+//      | (- WAE WAE)
+//      | (* WAE WAE)
+//      | (/ WAE WAE)
+//      | (< WAE WAE)
+//      | (= WAE WAE)
+//      | (> WAE WAE)
+//      | (string-append WAE WAE)
+//      | (string-length WAE)
+//      | (substring WAE WAE WAE)
+//      | (number->string WAE)
+//      | (string->number WAE)
+//      | (cons WAE WAE)
+//      | (first WAE)
+//      | (rest WAE)
+//      | (empty? WAE)
+//      | (list WAE ...)
+//      | (record [x WAE] ...)
+//      | (get WAE x)
+//      | (if WAE WAE WAE)
+//      | (With ([x WAE]) WAE)
+//      | (With ([x : Type WAE]) WAE)
+//      | (fun (x) WAE)
+//      | (fun ([x : Type]) WAE)
+//      | (WAE WAE)
+//      | x
+// Type = number
+//      | boolean
+//      | string
+//      | (listof Type)
+//      | (record [x : Type] ...)
+//      | (Type -> Type)
 // ============================================================================
 
-use crate::{Expr, Number, Binary, Operator, With, Binding, Id};
-use std::num::ParseIntError;
-use std::str::Chars;
+use crate::{Expr, Number, Binary, Operator, With, Binding, Id, Meta};
+use crate::ast::{Error, Bool, If, Call, Primitive, Fun, App, Type, Annotation, TypeMeta, Str, Empty, Record, Field, Get, MAX_TYPE_DEPTH};
+use crate::reader::{read, read_within, Comment, Datum, DatumKind, Span, Trivia};
 
 // parse returns an abstract syntax tree that represents the expression provided
 // by the given string.
 pub fn parse(rep: String) -> Result<Expr, String> {
-    Expr::parse(rep)
+    Expr::parse(&read(&rep)?)
+}
+
+// parse_within is parse, failing if the expression is nested more than
+// max_depth parentheses or brackets deep. Nesting is otherwise bounded only by
+// memory, so this guards against exhausting it with hostile input.
+pub fn parse_within(rep: &str, max_depth: usize) -> Result<Expr, String> {
+    Expr::parse(&read_within(rep, Some(max_depth))?)
+}
+
+// parse_with_recovery returns a tree for the given string along with every
+// parse error in it. A parenthesized expression, number or identifier that is
+// malformed is replaced by an Error node and parsing carries on with the rest
+// of the tree, so the tree is as complete as possible. An input whose
+// parentheses do not match cannot be split into expressions at all, and is
+// returned as a single Error node.
+pub fn parse_with_recovery(rep: &str) -> (Expr, Vec<String>) {
+    let mut errors: Vec<String> = Vec::new();
+    let ast: Expr = match read(rep) {
+        Ok(datum) => parse_recovering(&datum, rep, &mut errors),
+        Err(msg) => {
+            errors.push(msg.clone());
+            unreadable(rep, msg)
+        },
+    };
+    (ast, errors)
 }
 
 // A type that implements Parsable is able to construct an instance of itself
-// given a string representation.
-pub(crate) trait Parsable {
-    type Representation;
-    type Parsed;
-    
-    // parse parses the given input String and returns the expression tree it
+// from the datum that represents it. Reading datums (matching parentheses and
+// splitting atoms) is left to reader.rs, so a new form only needs a Form
+// variant and an entry in parse_paren_expr.
+pub(crate) trait Parsable: Sized {
+    // parse interprets the given datum and returns the expression tree it
     // represents.
-    fn parse(input: Self::Representation) -> Result<Self::Parsed, String>;
+    fn parse(datum: &Datum) -> Result<Self, String>;
 }
 
 impl Parsable for Expr {
-    type Representation = String;
-    type Parsed = Expr;
+    fn parse(datum: &Datum) -> Result<Expr, String> {
+        build(datum, None)
+    }
+}
 
-    fn parse(rep: String) -> Result<Expr, String> {
-        if rep.is_empty() {
-            return Err("expected a non-empty input".to_string())
-        }
-        let first_char: char;
-        let parse_str: String = rep.trim().to_string();
-        match parse_str.chars().next() {
-            Some(c) => first_char = c,
-            None => return Err(format!("unable to parse expression: {}", rep)),
-        }
-        if first_char.is_ascii_digit() {
-            return Number::parse(parse_str);
+// parse_recovering returns the tree for the given datum, read from the given
+// source text, in which every malformed expression is an Error node. The parse
+// errors are added to the given list.
+pub(crate) fn parse_recovering(datum: &Datum, source: &str, errors: &mut Vec<String>) -> Expr {
+    let mut recovery: Recovery = Recovery{ source, errors };
+    build(datum, Some(&mut recovery)).expect("errors are recovered from")
+}
+
+// Recovery holds what a parser that recovers from errors needs to build Error
+// nodes.
+struct Recovery<'a> {
+    source: &'a str,
+    errors: &'a mut Vec<String>,
+}
+
+impl<'a> Recovery<'a> {
+    fn error(&mut self, datum: &Datum, message: String) -> Expr {
+        recover_error(datum, self.source, message, self.errors)
+    }
+}
+
+// recover_error records the given parse error and returns the Error node that
+// replaces the given datum, read from the given source text.
+pub(crate) fn recover_error(datum: &Datum, source: &str, message: String, errors: &mut Vec<String>) -> Expr {
+    errors.push(message.clone());
+    let source: String = source[datum.span.start..datum.span.end].to_string();
+    Error{ message, source, meta: Meta::of(datum) }.into()
+}
+
+// build returns the tree for the given datum. It fails at the first parse
+// error unless recovery is given, in which case the malformed expression is
+// replaced by an Error node instead.
+//
+// The tree is built with an explicit work stack rather than by recursion, so
+// that its depth is bounded only by memory.
+fn build(datum: &Datum, mut recovery: Option<&mut Recovery>) -> Result<Expr, String> {
+    enum Task<'a> {
+        Parse(&'a Datum),
+        // Build pops the given number of inputs of the form, which were
+        // pushed in order, and pushes the node.
+        Build(Form<'a>, usize),
+    }
+    let mut tasks: Vec<Task> = vec![Task::Parse(datum)];
+    let mut results: Vec<Expr> = Vec::new();
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Parse(datum) => match parse_node(datum) {
+                Ok(Parsed::Leaf(expr)) => results.push(expr),
+                Ok(Parsed::Form(form)) => {
+                    let inputs: Vec<&Datum> = form.inputs();
+                    tasks.push(Task::Build(form, inputs.len()));
+                    tasks.extend(inputs.into_iter().rev().map(Task::Parse))
+                },
+                Err(msg) => match &mut recovery {
+                    Some(recovery) => results.push(recovery.error(datum, msg)),
+                    None => return Err(msg),
+                },
+            },
+            Task::Build(form, count) => {
+                let inputs: Vec<Expr> = results.split_off(results.len() - count);
+                results.push(form.build(inputs))
+            },
         }
-        if first_char == OPEN_PAREN {
-            return parse_paren_expr(parse_str);
+    }
+    Ok(results.pop().unwrap())
+}
+
+// Parsed is a node whose datum has been checked: either a finished leaf or a
+// form whose inputs are still to be parsed.
+enum Parsed<'a> {
+    Leaf(Expr),
+    Form(Form<'a>),
+}
+
+// parse_node checks the given datum and returns the node it represents.
+fn parse_node(datum: &Datum) -> Result<Parsed<'_>, String> {
+    match &datum.kind {
+        DatumKind::Number(_) => Ok(Parsed::Leaf(Number::parse(datum)?.into())),
+        DatumKind::Symbol(name) if name == TRUE || name == FALSE => Ok(Parsed::Leaf(Bool::parse(datum)?.into())),
+        DatumKind::Symbol(name) if name == EMPTY => Ok(Parsed::Leaf(Empty::parse(datum)?.into())),
+        DatumKind::Symbol(_) => Ok(Parsed::Leaf(Id::parse(datum)?.into())),
+        DatumKind::String(_) => Ok(Parsed::Leaf(Str::parse(datum)?.into())),
+        DatumKind::List(items) => Ok(Parsed::Form(parse_paren_expr(datum, items)?)),
+        DatumKind::Bracket(_) => Err(format!("{}: unexpected brackets: {}", datum.span, datum)),
+    }
+}
+
+// unreadable returns the Error node for source text that could not be read,
+// which spans all of it.
+pub(crate) fn unreadable(source: &str, message: String) -> Expr {
+    let span: Span = Span{ start: 0, end: source.len(), line: 1, column: 1 };
+    Error{ message, source: source.to_string(), meta: Meta{ span, ..Meta::default() } }.into()
+}
+
+impl Parsable for Number {
+    fn parse(datum: &Datum) -> Result<Number, String> {
+        match datum.kind {
+            DatumKind::Number(val) if val > 0 => Ok(Number{ val, meta: Meta::of(datum) }),
+            _ => Err(format!("{}: expected a natural number", datum.span)),
         }
-        if first_char.is_alphabetic() {
-            return Ok(Id::parse(parse_str)?.into());
+    }
+}
+
+impl Parsable for Bool {
+    fn parse(datum: &Datum) -> Result<Bool, String> {
+        match &datum.kind {
+            DatumKind::Symbol(name) if name == TRUE => Ok(Bool{ val: true, meta: Meta::of(datum) }),
+            DatumKind::Symbol(name) if name == FALSE => Ok(Bool{ val: false, meta: Meta::of(datum) }),
+            _ => Err(format!("{}: expected a boolean", datum.span)),
         }
-        Err(format!("unexpected symbol: {}", first_char))
     }
 }
 
-impl Parsable for Number {
-    type Representation = String;
-    type Parsed = Expr;
-    
-    fn parse(input: String) -> Result<Expr, String> {
-        let result_val: Result<i32, ParseIntError> = input.parse::<i32>();
-        return match result_val {
-            Ok(val) if val <= 0 => Err("expected a natural number".to_string()),
-            Ok(val) => Ok(Number{ val }.into()),
-            Err(_) => Err("expected a natural number".to_string())
+impl Parsable for Str {
+    fn parse(datum: &Datum) -> Result<Str, String> {
+        match &datum.kind {
+            DatumKind::String(val) => Ok(Str{ val: val.clone(), meta: Meta::of(datum) }),
+            _ => Err(format!("{}: expected a string", datum.span)),
         }
     }
 }
 
-impl Parsable for Binary {
-    type Representation = Vec<String>;
-    type Parsed = Expr;
-    
-    fn parse(tokens: Vec<String>) -> Result<Expr, String> {
-        if tokens.len() != 3 {
-            return Err("expected an operator type and two inputs for binary expression".to_string())
+impl Parsable for Empty {
+    fn parse(datum: &Datum) -> Result<Empty, String> {
+        match &datum.kind {
+            DatumKind::Symbol(name) if name == EMPTY => Ok(Empty{ meta: Meta::of(datum) }),
+            _ => Err(format!("{}: expected the empty list", datum.span)),
         }
-        let op: Operator = Operator::parse(tokens[0].clone())?;
-        let left: Expr = Expr::parse(tokens[1].clone())?;
-        let right: Expr = Expr::parse(tokens[2].clone())?;
-        Ok(Binary { op, left, right }.into())
     }
 }
 
 impl Parsable for Operator {
-    type Representation = String;
-    type Parsed = Operator;
-    
-    fn parse(input: String) -> Result<Operator, String> {
+    fn parse(datum: &Datum) -> Result<Operator, String> {
+        let input: &str = match &datum.kind {
+            DatumKind::Symbol(name) => name,
+            _ => return Err(format!("{}: unexpected operator: {}", datum.span, datum)),
+        };
         if input.eq(ADD_OP) {
             return Ok(Operator::Add)
         }
@@ -103,153 +239,457 @@ impl Parsable for Operator {
         if input.eq(DIV_OP) {
             return Ok(Operator::Div)
         }
-        Err(format!("unexpected operator: {}", input))
+        Err(format!("{}: unexpected operator: {}", datum.span, input))
     }
 }
 
-impl Parsable for With {
-    type Representation = Vec<String>;
-    type Parsed = Expr;
-    
-    fn parse(tokens: Vec<String>) -> Result<Expr, String> {
-        if tokens.len() != 3 {
-            return Err("expected 'with' symbol, binding, and input for With expression".to_string())
+impl Parsable for Id {
+    fn parse(datum: &Datum) -> Result<Id, String> {
+        let parse_str: &str = match &datum.kind {
+            DatumKind::Symbol(name) => name,
+            _ => return Err(format!("{}: expected an identifier: {}", datum.span, datum)),
+        };
+        if KEYWORDS.contains(&parse_str) {
+            return Err(format!("{}: identifier cannot be '{}'", datum.span, parse_str))
+        }
+        if !parse_str.starts_with(char::is_alphabetic) {
+            return Err(format!("{}: unexpected symbol: {}", datum.span, parse_str))
         }
-        if !tokens[0].eq(WITH_OP) {
-            return Err("expected 'with'".to_string())
+        for ch in parse_str.chars() {
+            if !ch.is_alphabetic() {
+                return Err(format!("{}: expected an alphabetic identifier", datum.span))
+            }
         }
-        let binding: Binding = Binding::parse(tokens[1].clone())?;
-        let input: Expr = Expr::parse(tokens[2].clone())?;
-        Ok(With { binding, input }.into())
+        Ok(Id { val: parse_str.to_string(), meta: Meta::of(datum) })
     }
 }
 
-impl Parsable for Binding {
-    type Representation = String;
-    type Parsed = Binding;
-    
-    fn parse(input: String) -> Result<Binding, String> {
-        let mut stripped: String;
-        match strip_ends(input.trim().to_string(), OPEN_PAREN, CLOSE_PAREN) {
-            Some(s) => stripped = s,
-            None => return Err("expected With binding to be wrapped in parentheses".to_string())
-        }
-        match strip_ends(stripped.trim().to_string(), OPEN_BRACE, CLOSE_BRACE) {
-            Some(s) => stripped = s,
-            None => return Err("expected With binding to be wrapped in brackets".to_string())
+impl Parsable for Primitive {
+    fn parse(datum: &Datum) -> Result<Primitive, String> {
+        match &datum.kind {
+            DatumKind::Symbol(name) if name == LESS_OP => Ok(Primitive::Less),
+            DatumKind::Symbol(name) if name == EQUAL_OP => Ok(Primitive::Equal),
+            DatumKind::Symbol(name) if name == GREATER_OP => Ok(Primitive::Greater),
+            DatumKind::Symbol(name) if name == STRING_APPEND_OP => Ok(Primitive::StringAppend),
+            DatumKind::Symbol(name) if name == STRING_LENGTH_OP => Ok(Primitive::StringLength),
+            DatumKind::Symbol(name) if name == SUBSTRING_OP => Ok(Primitive::Substring),
+            DatumKind::Symbol(name) if name == NUMBER_TO_STRING_OP => Ok(Primitive::NumberToString),
+            DatumKind::Symbol(name) if name == STRING_TO_NUMBER_OP => Ok(Primitive::StringToNumber),
+            DatumKind::Symbol(name) if name == CONS_OP => Ok(Primitive::Cons),
+            DatumKind::Symbol(name) if name == FIRST_OP => Ok(Primitive::First),
+            DatumKind::Symbol(name) if name == REST_OP => Ok(Primitive::Rest),
+            DatumKind::Symbol(name) if name == IS_EMPTY_OP => Ok(Primitive::IsEmpty),
+            DatumKind::Symbol(name) if name == LIST_OP => Ok(Primitive::List),
+            _ => Err(format!("{}: unexpected primitive: {}", datum.span, datum)),
         }
-        if stripped.is_empty() {
-            return Err("expected a binding expression for With clause".to_string())
-        }
-        let tokens: Vec<String> = split_tokens(stripped);
-        if tokens.len() != 2 {
-            return Err("expected an identifier and bound expression for With clause".to_string())
-        }
-        let identifier: Box<Id> = Box::new(Id::parse(tokens[0].clone())?);
-        let replace: Expr = Expr::parse(tokens[1].clone())?;
-        Ok(Binding{ identifier, replace })
     }
 }
 
-impl Parsable for Id {
-    type Representation = String;
-    type Parsed = Id;
-    
-    fn parse(input: String) -> Result<Id, String> {
-        let parse_str: &str = input.trim();
-        if parse_str.eq(WITH_OP) {
-            return Err("identifier cannot be 'with'".to_string())
-        }
-        for ch in parse_str.chars() {
-            if !ch.is_alphabetic() {
-                return Err("expected an alphabetic identifier".to_string())
-            }
-        }
-        Ok(Id { val: parse_str.to_string() })
+impl Parsable for Annotation {
+    fn parse(datum: &Datum) -> Result<Annotation, String> {
+        parse_type(datum, 0)
     }
 }
 
-// parse_paren_expr returns the Binary or With expression represented by the 
-// given parenthesized string.
-fn parse_paren_expr(parse_string: String) -> Result<Expr, String> {
-    let stripped: String;
-    match strip_ends(parse_string, OPEN_PAREN, CLOSE_PAREN) {
-        Some(s) => stripped = s,
-        None => return Err("expected opening and closing parentheses".to_string())
-    }
-    let exprs: Vec<String> = split_tokens(stripped);
-    if exprs.len() == 0 {
-        return Err("expected an expression within the parentheses".to_string())
-    }
-    return match &exprs[0][..] {
-        ADD_OP | SUB_OP | MUL_OP | DIV_OP => Binary::parse(exprs),
-        WITH_OP => With::parse(exprs),
-        s @ _ => Err(format!("unexpected parenthesized expression: {}", s)),
+// parse_type parses a type annotation found at the given depth of nesting.
+fn parse_type(datum: &Datum, depth: usize) -> Result<Annotation, String> {
+    if depth >= MAX_TYPE_DEPTH {
+        return Err(format!("{}: type is nested more than {} levels deep", datum.span, MAX_TYPE_DEPTH))
     }
+    let mut meta: TypeMeta = TypeMeta{ trivia: datum.trivia.clone(), inputs: Vec::new() };
+    let ty: Type = match &datum.kind {
+        DatumKind::Symbol(name) if name == NUMBER_TYPE => Type::Number,
+        DatumKind::Symbol(name) if name == BOOLEAN_TYPE => Type::Boolean,
+        DatumKind::Symbol(name) if name == STRING_TYPE => Type::String,
+        DatumKind::List(items) if items.len() == 3 && is_symbol(&items[1], ARROW) => {
+            let from: Annotation = parse_type(&items[0], depth + 1)?;
+            let mut to: Annotation = parse_type(&items[2], depth + 1)?;
+            // The arrow carries no metadata, so comments around it lead the
+            // type after it.
+            prepend_comments(&mut to.meta.trivia, &items[1].trivia);
+            meta.inputs = vec![from.meta, to.meta];
+            Type::Fun(Box::new(from.ty), Box::new(to.ty))
+        },
+        DatumKind::List(items) if items.len() == 2 && is_symbol(&items[0], LISTOF_TYPE) => {
+            let mut item: Annotation = parse_type(&items[1], depth + 1)?;
+            prepend_comments(&mut item.meta.trivia, &items[0].trivia);
+            meta.inputs = vec![item.meta];
+            Type::List(Box::new(item.ty))
+        },
+        DatumKind::List(items) if !items.is_empty() && is_symbol(&items[0], RECORD_OP) => {
+            let mut fields: Vec<(String, Type)> = Vec::new();
+            for field in &items[1..] {
+                let bracket: &[Datum] = match &field.kind {
+                    DatumKind::Bracket(bracket) if bracket.len() == 3 && is_symbol(&bracket[1], COLON) => bracket,
+                    _ => return Err(format!("{}: expected a field name, ':' and a type for record field type", field.span)),
+                };
+                let mut name: Id = Id::parse(&bracket[0])?;
+                if fields.iter().any(|(other, _)| *other == name.val) {
+                    return Err(format!("{}: duplicate field: {}", name.meta.span, name.val))
+                }
+                let mut ty: Annotation = parse_type(&bracket[2], depth + 1)?;
+                attach_colon(&mut name.meta.trivia, &bracket[1], &mut ty.meta.trivia);
+                let name_meta: TypeMeta = TypeMeta{ trivia: name.meta.trivia, inputs: Vec::new() };
+                meta.inputs.push(TypeMeta{ trivia: field.trivia.clone(), inputs: vec![name_meta, ty.meta] });
+                fields.push((name.val, ty.ty));
+            }
+            // The keyword carries no metadata, so its comments lead the
+            // first field, or stay inside the type if it has no fields.
+            match meta.inputs.first_mut() {
+                Some(first) => prepend_comments(&mut first.trivia, &items[0].trivia),
+                None => keep_inside(&mut meta.trivia, &items[0].trivia),
+            }
+            Type::Record(fields)
+        },
+        _ => return Err(format!("{}: expected a type: {}", datum.span, datum)),
+    };
+    Ok(Annotation{ ty, meta })
+}
+
+// Form is a parenthesized expression whose inputs have not been parsed yet.
+enum Form<'a> {
+    Binary(Operator, &'a Datum),
+    With(Box<Id>, Option<Annotation>, &'a Datum),
+    If(&'a Datum),
+    Call(Primitive, &'a Datum),
+    Fun(Box<Id>, Option<Annotation>, &'a Datum),
+    App(&'a Datum),
+    Record(Vec<Id>, &'a Datum),
+    Get(Box<Id>, &'a Datum),
 }
 
-// split_tokens splits the given input string into a series of parenthesized and
-// literal expression tokens.
-fn split_tokens(input: String) -> Vec<String> {
-    let mut tokens: Vec<String> = Vec::new();
+impl<'a> Form<'a> {
+    // inputs returns the datums of the inputs of this form, in source order.
+    fn inputs(&self) -> Vec<&'a Datum> {
+        match self {
+            Form::Binary(_, datum) | Form::If(datum) | Form::Call(_, datum) => list_items(datum)[1..].iter().collect(),
+            Form::With(_, _, datum) => {
+                let items: &[Datum] = list_items(datum);
+                let binding: &[Datum] = list_items(&list_items(&items[1])[0]);
+                vec![binding.last().unwrap(), &items[2]]
+            },
+            Form::Fun(_, _, datum) => vec![&list_items(datum)[2]],
+            Form::App(datum) => list_items(datum).iter().collect(),
+            Form::Record(_, datum) => list_items(datum)[1..].iter().map(|field| &list_items(field)[1]).collect(),
+            Form::Get(_, datum) => vec![&list_items(datum)[1]],
+        }
+    }
 
-    let mut is_paren_expr: bool = false;
-    let mut paren_count: i32 = 0;
-    let mut token: String = "".to_string();
+    // build returns the node for this form, given its parsed inputs.
+    fn build(self, inputs: Vec<Expr>) -> Expr {
+        let mut inputs = inputs.into_iter();
+        let mut next = || inputs.next().unwrap();
+        match self {
+            Form::Binary(op, datum) => {
+                let items: &[Datum] = list_items(datum);
+                let mut left: Expr = next();
+                // Operators carry no metadata, so comments around one lead the
+                // left input instead.
+                prepend_comments(&mut left.meta_mut().trivia, &items[0].trivia);
+                Binary{ op, left, right: next(), meta: Meta::of(datum) }.into()
+            },
+            Form::With(identifier, annotation, datum) => {
+                let items: &[Datum] = list_items(datum);
+                let paren: &Datum = &items[1];
+                // The parentheses and brackets around a binding form a single
+                // node, so their comments are merged, outermost first.
+                let mut meta: Meta = Meta::of(&list_items(paren)[0]);
+                meta.trivia.leading.splice(0..0, paren.trivia.leading.iter().cloned());
+                meta.trivia.trailing.extend(paren.trivia.trailing.iter().cloned());
+                prepend_comments(&mut meta.trivia, &items[0].trivia);
+                let replace: Expr = next();
+                let binding: Binding = Binding{ identifier, annotation, replace, meta };
+                With{ binding, input: next(), meta: Meta::of(datum) }.into()
+            },
+            Form::If(datum) => {
+                let mut condition: Expr = next();
+                prepend_comments(&mut condition.meta_mut().trivia, &list_items(datum)[0].trivia);
+                If{ condition, then: next(), otherwise: next(), meta: Meta::of(datum) }.into()
+            },
+            Form::Call(prim, datum) => {
+                let mut args: Vec<Expr> = inputs.collect();
+                let mut meta: Meta = Meta::of(datum);
+                // The keyword's comments lead the first argument, or stay
+                // inside the call if it has none.
+                match args.first_mut() {
+                    Some(first) => prepend_comments(&mut first.meta_mut().trivia, &list_items(datum)[0].trivia),
+                    None => keep_inside(&mut meta.trivia, &list_items(datum)[0].trivia),
+                }
+                Call{ prim, args, meta }.into()
+            },
+            Form::Fun(mut param, annotation, datum) => {
+                // The keyword and the delimiters around the parameter carry no
+                // metadata of their own, so comments before the parameter lead
+                // it and comments after its closing delimiters lead the body.
+                let items: &[Datum] = list_items(datum);
+                let paren: &Datum = &items[1];
+                let mut before: Vec<Comment> = items[0].trivia.leading.clone();
+                before.extend(items[0].trivia.trailing.iter().cloned());
+                before.extend(paren.trivia.leading.iter().cloned());
+                let mut after: Vec<Comment> = Vec::new();
+                if annotation.is_some() {
+                    let brackets: &Trivia = &list_items(paren)[0].trivia;
+                    before.extend(brackets.leading.iter().cloned());
+                    after.extend(brackets.trailing.iter().cloned());
+                }
+                after.extend(paren.trivia.trailing.iter().cloned());
+                param.meta.trivia.leading.splice(0..0, before);
+                let mut body: Expr = next();
+                body.meta_mut().trivia.leading.splice(0..0, after);
+                Fun{ param, annotation, body, meta: Meta::of(datum) }.into()
+            },
+            Form::App(datum) => App{ fun: next(), arg: next(), meta: Meta::of(datum) }.into(),
+            Form::Record(names, datum) => {
+                // A field is the bracket around its name and value, so it
+                // keeps the comments on the brackets.
+                let items: &[Datum] = list_items(datum);
+                let mut fields: Vec<Field> = names.into_iter().zip(&items[1..]).zip(inputs)
+                    .map(|((name, bracket), value)| Field{ name: Box::new(name), value, meta: Meta::of(bracket) })
+                    .collect();
+                let mut meta: Meta = Meta::of(datum);
+                // The keyword's comments lead the first field, or stay inside
+                // the record if it has none.
+                match fields.first_mut() {
+                    Some(first) => prepend_comments(&mut first.meta.trivia, &items[0].trivia),
+                    None => keep_inside(&mut meta.trivia, &items[0].trivia),
+                }
+                Record{ fields, meta }.into()
+            },
+            Form::Get(field, datum) => {
+                let mut record: Expr = next();
+                prepend_comments(&mut record.meta_mut().trivia, &list_items(datum)[0].trivia);
+                Get{ record, field, meta: Meta::of(datum) }.into()
+            },
+        }
+    }
+}
 
-    let mut chars: Chars = input.trim().chars();
-    while let Some(curr_char) = chars.next() {
-        if !is_paren_expr && curr_char.is_ascii_whitespace() {
-            if !token.is_empty() {
-                tokens.push(token);
-                token = String::new()
+// parse_paren_expr checks the expression represented by the given
+// parenthesized datum, chosen by the symbol at its head, and returns it as a
+// Form whose inputs are still to be parsed. A list whose head is an
+// identifier or another list is a function application.
+fn parse_paren_expr<'a>(datum: &'a Datum, items: &'a [Datum]) -> Result<Form<'a>, String> {
+    let head: &str = match items.first().map(|item| &item.kind) {
+        Some(DatumKind::Symbol(name)) => name,
+        Some(DatumKind::List(_)) => return parse_app(datum, items),
+        Some(_) => return Err(format!("{}: unexpected parenthesized expression: {}", datum.span, datum)),
+        None => return Err(format!("{}: expected an expression within the parentheses", datum.span)),
+    };
+    match head {
+        ADD_OP | SUB_OP | MUL_OP | DIV_OP => {
+            if items.len() != 3 {
+                return Err(format!("{}: expected an operator type and two inputs for binary expression", datum.span))
             }
-            if paren_count != 0 || is_paren_expr {
-                return Vec::new()
+            Ok(Form::Binary(Operator::parse(&items[0])?, datum))
+        },
+        LESS_OP | EQUAL_OP | GREATER_OP | STRING_APPEND_OP | STRING_LENGTH_OP | SUBSTRING_OP
+        | NUMBER_TO_STRING_OP | STRING_TO_NUMBER_OP | CONS_OP | FIRST_OP | REST_OP | IS_EMPTY_OP | LIST_OP => {
+            let prim: Primitive = Primitive::parse(&items[0])?;
+            if let Some(arity) = arity(prim) {
+                if items.len() != arity + 1 {
+                    return Err(format!("{}: expected {} inputs for '{}'", datum.span, arity, head))
+                }
             }
-            continue
-        }
-        if curr_char.eq(&OPEN_PAREN) {
-            paren_count += 1;
-            if !is_paren_expr {
-                is_paren_expr = true
+            Ok(Form::Call(prim, datum))
+        },
+        WITH_OP => {
+            if items.len() != 3 {
+                return Err(format!("{}: expected 'with' symbol, binding, and input for With expression", datum.span))
+            }
+            let (identifier, annotation) = parse_binding_identifier(&items[1])?;
+            Ok(Form::With(identifier, annotation, datum))
+        },
+        IF_OP => {
+            if items.len() != 4 {
+                return Err(format!("{}: expected 'if' symbol, condition, and two branches for If expression", datum.span))
+            }
+            Ok(Form::If(datum))
+        },
+        FUN_OP => {
+            if items.len() != 3 {
+                return Err(format!("{}: expected 'fun' symbol, parameter, and body for function", datum.span))
             }
-        } else if curr_char.eq(&CLOSE_PAREN) {
-            paren_count -= 1;
-            if paren_count == 0 {
-                is_paren_expr = false
+            let (param, annotation) = parse_param(&items[1])?;
+            Ok(Form::Fun(param, annotation, datum))
+        },
+        RECORD_OP => Ok(Form::Record(parse_fields(&items[1..])?, datum)),
+        GET_OP => {
+            if items.len() != 3 {
+                return Err(format!("{}: expected 'get' symbol, record, and field name for field access", datum.span))
             }
+            Ok(Form::Get(Box::new(Id::parse(&items[2])?), datum))
+        },
+        DEFINE_OP => Err(format!("{}: definitions are only allowed at the top level of a program", datum.span)),
+        s if s.starts_with(char::is_alphabetic) => parse_app(datum, items),
+        s => Err(format!("{}: unexpected parenthesized expression: {}", datum.span, s)),
+    }
+}
+
+// parse_app checks the shape of the given function application.
+fn parse_app<'a>(datum: &'a Datum, items: &'a [Datum]) -> Result<Form<'a>, String> {
+    if items.len() != 2 {
+        return Err(format!("{}: expected a function and a single argument for application", datum.span))
+    }
+    Ok(Form::App(datum))
+}
+
+// arity returns the number of inputs the given primitive takes, or None if it
+// takes any number of them.
+pub(crate) fn arity(prim: Primitive) -> Option<usize> {
+    match prim {
+        Primitive::Less | Primitive::Equal | Primitive::Greater | Primitive::StringAppend | Primitive::Cons => Some(2),
+        Primitive::StringLength | Primitive::NumberToString | Primitive::StringToNumber => Some(1),
+        Primitive::First | Primitive::Rest | Primitive::IsEmpty => Some(1),
+        Primitive::Substring => Some(3),
+        Primitive::List => None,
+    }
+}
+
+// parse_fields checks the shape of the given record fields, each [x WAE], and
+// returns their names, which must be distinct.
+fn parse_fields(fields: &[Datum]) -> Result<Vec<Id>, String> {
+    let mut names: Vec<Id> = Vec::new();
+    for field in fields {
+        let items: &[Datum] = match &field.kind {
+            DatumKind::Bracket(items) if items.len() == 2 => items,
+            _ => return Err(format!("{}: expected a field name and value in brackets for record field", field.span)),
+        };
+        let name: Id = Id::parse(&items[0])?;
+        if names.iter().any(|other| other.val == name.val) {
+            return Err(format!("{}: duplicate field: {}", items[0].span, name.val))
         }
-        token.push(curr_char)
+        names.push(name);
     }
+    Ok(names)
+}
 
-    if !token.is_empty() {
-        tokens.push(token);
+// parse_binding_identifier checks the shape of the given With binding and
+// returns the identifier it binds, along with its type annotation if it has
+// one.
+fn parse_binding_identifier(datum: &Datum) -> Result<(Box<Id>, Option<Annotation>), String> {
+    let bracket: &Datum = match &datum.kind {
+        DatumKind::List(items) if items.len() == 1 => &items[0],
+        DatumKind::List(_) => return Err(format!("{}: expected a single binding for With clause", datum.span)),
+        _ => return Err(format!("{}: expected With binding to be wrapped in parentheses", datum.span)),
+    };
+    let items: &[Datum] = match &bracket.kind {
+        DatumKind::Bracket(items) => items,
+        _ => return Err(format!("{}: expected With binding to be wrapped in brackets", bracket.span)),
+    };
+    if items.is_empty() {
+        return Err(format!("{}: expected a binding expression for With clause", bracket.span))
+    }
+    if items.len() == 4 && is_symbol(&items[1], COLON) {
+        return parse_annotated(items)
     }
-    tokens
+    if items.len() != 2 {
+        return Err(format!("{}: expected an identifier and bound expression for With clause", bracket.span))
+    }
+    Ok((Box::new(Id::parse(&items[0])?), None))
 }
 
-// strip_ends strips the given prefix and suffix from the given string.
-// Returns None if the prefix or suffix do not exist.
-fn strip_ends(input: String, prefix: char, suffix: char) -> Option<String> {
-    let stripped: String;
-    match input.strip_prefix(prefix) {
-        Some(s) => stripped = s.to_string(),
-        None => return None,
+// parse_param checks the shape of the given function parameter, either (x) or
+// ([x : Type]), and returns the parameter along with its type annotation if it
+// has one.
+fn parse_param(datum: &Datum) -> Result<(Box<Id>, Option<Annotation>), String> {
+    let param: &Datum = match &datum.kind {
+        DatumKind::List(items) if items.len() == 1 => &items[0],
+        _ => return Err(format!("{}: expected a single parameter in parentheses for function", datum.span)),
+    };
+    match &param.kind {
+        DatumKind::Bracket(items) if items.len() == 3 && is_symbol(&items[1], COLON) => parse_annotated(items),
+        DatumKind::Bracket(_) => Err(format!("{}: expected an identifier, ':' and a type for annotated parameter", param.span)),
+        _ => Ok((Box::new(Id::parse(param)?), None)),
     }
-    return match stripped.strip_suffix(suffix) {
-        Some(s) => Some(s.to_string()),
-        None => return None,
+}
+
+// parse_annotated returns the identifier and type annotation at the start of
+// the given bracket items, which are known to start with an identifier, ':'
+// and a type.
+fn parse_annotated(items: &[Datum]) -> Result<(Box<Id>, Option<Annotation>), String> {
+    let mut identifier: Box<Id> = Box::new(Id::parse(&items[0])?);
+    let mut annotation: Annotation = Annotation::parse(&items[2])?;
+    attach_colon(&mut identifier.meta.trivia, &items[1], &mut annotation.meta.trivia);
+    Ok((identifier, Some(annotation)))
+}
+
+// attach_colon moves the comments around a ':' onto the name before it and
+// the type after it, since the colon carries no metadata of its own.
+fn attach_colon(name: &mut Trivia, colon: &Datum, ty: &mut Trivia) {
+    name.trailing.extend(colon.trivia.leading.iter().cloned());
+    ty.leading.splice(0..0, colon.trivia.trailing.iter().cloned());
+}
+
+// is_symbol returns true if the given datum is the given symbol.
+fn is_symbol(datum: &Datum, symbol: &str) -> bool {
+    matches!(&datum.kind, DatumKind::Symbol(name) if name == symbol)
+}
+
+// list_items returns the items of the given datum, which parse_paren_expr has
+// already checked is a list or brackets.
+fn list_items(datum: &Datum) -> &[Datum] {
+    match &datum.kind {
+        DatumKind::List(items) | DatumKind::Bracket(items) => items,
+        _ => &[],
     }
 }
 
+// prepend_comments makes all of the comments in the given trivia lead a node,
+// ahead of the comments that already lead it.
+pub(crate) fn prepend_comments(trivia: &mut Trivia, comments: &Trivia) {
+    let moved = comments.leading.iter().chain(comments.trailing.iter()).cloned();
+    trivia.leading.splice(0..0, moved);
+}
+
+// keep_inside makes all of the comments in the given trivia inner comments of
+// a node that has no inputs for them to lead.
+fn keep_inside(trivia: &mut Trivia, comments: &Trivia) {
+    trivia.inner.extend(comments.leading.iter().chain(comments.trailing.iter()).cloned());
+}
+
 // Constants for use in parsing expressions.
-const OPEN_PAREN:  char = '(';
-const CLOSE_PAREN: char = ')';
-const OPEN_BRACE:  char = '[';
-const CLOSE_BRACE: char = ']';
-const ADD_OP: &str = "+";
-const SUB_OP: &str = "-";
-const MUL_OP: &str = "*";
-const DIV_OP: &str = "/";
-const WITH_OP: &str = "with";
+pub(crate) const OPEN_PAREN:  char = '(';
+pub(crate) const CLOSE_PAREN: char = ')';
+pub(crate) const OPEN_BRACE:  char = '[';
+pub(crate) const CLOSE_BRACE: char = ']';
+pub(crate) const ADD_OP: &str = "+";
+pub(crate) const SUB_OP: &str = "-";
+pub(crate) const MUL_OP: &str = "*";
+pub(crate) const DIV_OP: &str = "/";
+pub(crate) const WITH_OP: &str = "with";
+pub(crate) const DEFINE_OP: &str = "define";
+pub(crate) const LESS_OP: &str = "<";
+pub(crate) const EQUAL_OP: &str = "=";
+pub(crate) const GREATER_OP: &str = ">";
+pub(crate) const STRING_APPEND_OP: &str = "string-append";
+pub(crate) const STRING_LENGTH_OP: &str = "string-length";
+pub(crate) const SUBSTRING_OP: &str = "substring";
+pub(crate) const NUMBER_TO_STRING_OP: &str = "number->string";
+pub(crate) const STRING_TO_NUMBER_OP: &str = "string->number";
+pub(crate) const CONS_OP: &str = "cons";
+pub(crate) const FIRST_OP: &str = "first";
+pub(crate) const REST_OP: &str = "rest";
+pub(crate) const IS_EMPTY_OP: &str = "empty?";
+pub(crate) const LIST_OP: &str = "list";
+pub(crate) const RECORD_OP: &str = "record";
+pub(crate) const GET_OP: &str = "get";
+pub(crate) const IF_OP: &str = "if";
+pub(crate) const FUN_OP: &str = "fun";
+pub(crate) const TRUE: &str = "true";
+pub(crate) const FALSE: &str = "false";
+pub(crate) const EMPTY: &str = "empty";
+pub(crate) const COLON: &str = ":";
+pub(crate) const ARROW: &str = "->";
+pub(crate) const NUMBER_TYPE: &str = "number";
+pub(crate) const BOOLEAN_TYPE: &str = "boolean";
+pub(crate) const STRING_TYPE: &str = "string";
+pub(crate) const LISTOF_TYPE: &str = "listof";
+
+// KEYWORDS are the symbols that cannot be used as identifiers.
+pub(crate) const KEYWORDS: [&str; 14] = [
+    WITH_OP, DEFINE_OP, IF_OP, FUN_OP, TRUE, FALSE, EMPTY, SUBSTRING_OP, CONS_OP, FIRST_OP, REST_OP, LIST_OP,
+    RECORD_OP, GET_OP,
+];
+
+// INVALID_EXPRESSION is reported by the code generators for an Error node.
+pub(crate) const INVALID_EXPRESSION: &str = "invalid expression";
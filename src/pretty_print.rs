@@ -1,15 +1,21 @@
-use crate::ast::{Expr, Number, Binary, Operator, With, Binding, Id};
+use crate::ast::{Expr, Number, Binary, Operator, With, Binding, Id, Error, Bool, If, Call, Primitive, Fun, App, Str, Empty, Record, Field, Get};
+use crate::reader::quote;
 use std::fmt::{Display, Formatter};
 use std::fmt;
 
 pub fn pretty_print(expr: &Expr) {
-    fn pretty_print<T: Printable + ?Sized>(expr: &Box<T>, prefix: String, child_prefix: String) {
+    // Nodes waiting to be printed, with the prefix of their own line and of
+    // their children's lines. An explicit stack keeps deep trees from
+    // overflowing the call stack, and children are borrowed rather than
+    // copied.
+    let mut stack: Vec<(&dyn Printable, String, String)> = vec![(expr, "".to_string(), "".to_string())];
+    while let Some((expr, prefix, child_prefix)) = stack.pop() {
         println!("{}{}", prefix, expr);
 
         if expr.child_count() > 0 {
             let last_child = expr.child_count() - 1;
 
-            for (i, child) in expr.children().iter().enumerate() {
+            for (i, child) in expr.children().into_iter().enumerate().rev() {
                 let new_prefix: String;
                 let new_child_prefix: String;
                 if i == last_child {
@@ -19,25 +25,33 @@ pub fn pretty_print(expr: &Expr) {
                     new_prefix = format!("{}{}", child_prefix, "├── ");
                     new_child_prefix = format!("{}{}", child_prefix, "│   ");
                 }
-                pretty_print(child, new_prefix, new_child_prefix);
+                stack.push((child, new_prefix, new_child_prefix));
             }
         }
     }
-
-    pretty_print(&Box::new(expr.clone()), "".to_string(), "".to_string());
     println!()
 }
 
-trait Printable: Display {
+pub(crate) trait Printable: Display {
     fn child_count(&self) -> usize { 0 }
-    fn children(&self) -> Vec<Box<dyn Printable>> { Vec::new() }
+    fn children(&self) -> Vec<&dyn Printable> { Vec::new() }
+    // edge_labels names the role of each child, in the same order as children.
+    fn edge_labels(&self) -> Vec<&'static str> { Vec::new() }
     fn name(&self) -> String;
+    // detail returns the value carried by a node beyond its kind, if any.
+    fn detail(&self) -> Option<String> { None }
+    // scope returns the identifier bound within this node's subtree, if any.
+    fn scope(&self) -> Option<String> { None }
 }
 
 impl Printable for Number {
     fn name(&self) -> String {
         "Number".to_string()
     }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.val.to_string())
+    }
 }
 
 impl Display for Number {
@@ -48,13 +62,20 @@ impl Display for Number {
 
 impl Printable for Binary {
     fn child_count(&self) -> usize { 2 }
-    fn children(&self) -> Vec<Box<dyn Printable>> {
-        vec!(Box::new(self.left.clone()), Box::new(self.right.clone()))
+    fn children(&self) -> Vec<&dyn Printable> {
+        vec!(&self.left, &self.right)
+    }
+    fn edge_labels(&self) -> Vec<&'static str> {
+        vec!("left", "right")
     }
 
     fn name(&self) -> String {
         "Binary".to_string()
     }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.op.name())
+    }
 }
 
 impl Display for Binary {
@@ -65,7 +86,7 @@ impl Display for Binary {
 
 impl Printable for Operator {
     fn name(&self) -> String {
-        return match self {
+        match self {
             Operator::Add => "Add".to_string(),
             Operator::Sub => "Subtract".to_string(),
             Operator::Mul => "Multiply".to_string(),
@@ -82,13 +103,20 @@ impl Display for Operator {
 
 impl Printable for With {
     fn child_count(&self) -> usize { 2 }
-    fn children(&self) -> Vec<Box<dyn Printable>> {
-        vec!(Box::new(self.binding.clone()), Box::new(self.input.clone()))
+    fn children(&self) -> Vec<&dyn Printable> {
+        vec!(&self.binding, &self.input)
+    }
+    fn edge_labels(&self) -> Vec<&'static str> {
+        vec!("binding", "body")
     }
 
     fn name(&self) -> String {
         "With".to_string()
     }
+
+    fn scope(&self) -> Option<String> {
+        Some(self.binding.identifier.val.clone())
+    }
 }
 
 impl Display for With {
@@ -99,18 +127,28 @@ impl Display for With {
 
 impl Printable for Binding {
     fn child_count(&self) -> usize { 2 }
-    fn children(&self) -> Vec<Box<dyn Printable>> {
-        vec!(self.identifier.clone(), Box::new(self.replace.clone()))
+    fn children(&self) -> Vec<&dyn Printable> {
+        vec!(self.identifier.as_ref(), &self.replace)
+    }
+    fn edge_labels(&self) -> Vec<&'static str> {
+        vec!("identifier", "value")
     }
 
     fn name(&self) -> String {
         "Binding".to_string()
     }
+
+    fn detail(&self) -> Option<String> {
+        self.annotation.as_ref().map(|annotation| annotation.ty.to_string())
+    }
 }
 
 impl Display for Binding {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.name())
+        match self.detail() {
+            Some(ty) => write!(f, "{}: {}", self.name(), ty),
+            None => write!(f, "{}", self.name()),
+        }
     }
 }
 
@@ -118,6 +156,10 @@ impl Printable for Id {
     fn name(&self) -> String {
         "Id".to_string()
     }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.val.clone())
+    }
 }
 
 impl Display for Id {
@@ -126,42 +168,384 @@ impl Display for Id {
     }
 }
 
+impl Printable for Error {
+    fn name(&self) -> String {
+        "Error".to_string()
+    }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.message.clone())
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name(), self.message)
+    }
+}
+
+impl Printable for Bool {
+    fn name(&self) -> String {
+        "Bool".to_string()
+    }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.val.to_string())
+    }
+}
+
+impl Display for Bool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name(), self.val)
+    }
+}
+
+impl Printable for If {
+    fn child_count(&self) -> usize { 3 }
+    fn children(&self) -> Vec<&dyn Printable> {
+        vec!(&self.condition, &self.then, &self.otherwise)
+    }
+    fn edge_labels(&self) -> Vec<&'static str> {
+        vec!("condition", "then", "else")
+    }
+
+    fn name(&self) -> String {
+        "If".to_string()
+    }
+}
+
+impl Display for If {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Printable for Call {
+    fn child_count(&self) -> usize { self.args.len() }
+    fn children(&self) -> Vec<&dyn Printable> {
+        self.args.iter().map(|arg| arg as &dyn Printable).collect()
+    }
+    fn edge_labels(&self) -> Vec<&'static str> {
+        vec!["argument"; self.args.len()]
+    }
+
+    fn name(&self) -> String {
+        "Call".to_string()
+    }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.prim.name())
+    }
+}
+
+impl Display for Call {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.prim.name())
+    }
+}
+
+impl Printable for Primitive {
+    fn name(&self) -> String {
+        match self {
+            Primitive::Less => "Less".to_string(),
+            Primitive::Equal => "Equal".to_string(),
+            Primitive::Greater => "Greater".to_string(),
+            Primitive::StringAppend => "StringAppend".to_string(),
+            Primitive::StringLength => "StringLength".to_string(),
+            Primitive::Substring => "Substring".to_string(),
+            Primitive::NumberToString => "NumberToString".to_string(),
+            Primitive::StringToNumber => "StringToNumber".to_string(),
+            Primitive::Cons => "Cons".to_string(),
+            Primitive::First => "First".to_string(),
+            Primitive::Rest => "Rest".to_string(),
+            Primitive::IsEmpty => "IsEmpty".to_string(),
+            Primitive::List => "List".to_string(),
+        }
+    }
+}
+
+impl Display for Primitive {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Printable for Fun {
+    fn child_count(&self) -> usize { 2 }
+    fn children(&self) -> Vec<&dyn Printable> {
+        vec!(self.param.as_ref(), &self.body)
+    }
+    fn edge_labels(&self) -> Vec<&'static str> {
+        vec!("parameter", "body")
+    }
+
+    fn name(&self) -> String {
+        "Fun".to_string()
+    }
+
+    fn detail(&self) -> Option<String> {
+        self.annotation.as_ref().map(|annotation| annotation.ty.to_string())
+    }
+
+    fn scope(&self) -> Option<String> {
+        Some(self.param.val.clone())
+    }
+}
+
+impl Display for Fun {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.detail() {
+            Some(ty) => write!(f, "{}: {}", self.name(), ty),
+            None => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+impl Printable for App {
+    fn child_count(&self) -> usize { 2 }
+    fn children(&self) -> Vec<&dyn Printable> {
+        vec!(&self.fun, &self.arg)
+    }
+    fn edge_labels(&self) -> Vec<&'static str> {
+        vec!("function", "argument")
+    }
+
+    fn name(&self) -> String {
+        "App".to_string()
+    }
+}
+
+impl Display for App {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Printable for Str {
+    fn name(&self) -> String {
+        "Str".to_string()
+    }
+
+    fn detail(&self) -> Option<String> {
+        Some(quote(&self.val))
+    }
+}
+
+impl Display for Str {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name(), quote(&self.val))
+    }
+}
+
+impl Printable for Empty {
+    fn name(&self) -> String {
+        "Empty".to_string()
+    }
+}
+
+impl Display for Empty {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Printable for Record {
+    fn child_count(&self) -> usize { self.fields.len() }
+    fn children(&self) -> Vec<&dyn Printable> {
+        self.fields.iter().map(|field| field as &dyn Printable).collect()
+    }
+    fn edge_labels(&self) -> Vec<&'static str> {
+        vec!["field"; self.fields.len()]
+    }
+
+    fn name(&self) -> String {
+        "Record".to_string()
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Printable for Field {
+    fn child_count(&self) -> usize { 1 }
+    fn children(&self) -> Vec<&dyn Printable> {
+        vec!(&self.value)
+    }
+    fn edge_labels(&self) -> Vec<&'static str> {
+        vec!("value")
+    }
+
+    fn name(&self) -> String {
+        "Field".to_string()
+    }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.name.val.clone())
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name(), self.name.val)
+    }
+}
+
+impl Printable for Get {
+    fn child_count(&self) -> usize { 2 }
+    fn children(&self) -> Vec<&dyn Printable> {
+        vec!(&self.record, self.field.as_ref())
+    }
+    fn edge_labels(&self) -> Vec<&'static str> {
+        vec!("record", "field")
+    }
+
+    fn name(&self) -> String {
+        "Get".to_string()
+    }
+}
+
+impl Display for Get {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
 impl Printable for Expr {
     fn child_count(&self) -> usize {
-        return match self {
+        match self {
             Expr::Number(expr) => expr.child_count(),
             Expr::Binary(expr) => expr.child_count(),
             Expr::With(expr) => expr.child_count(),
             Expr::Id(expr) => expr.child_count(),
+            Expr::Error(expr) => expr.child_count(),
+            Expr::Bool(expr) => expr.child_count(),
+            Expr::If(expr) => expr.child_count(),
+            Expr::Call(expr) => expr.child_count(),
+            Expr::Fun(expr) => expr.child_count(),
+            Expr::App(expr) => expr.child_count(),
+            Expr::Str(expr) => expr.child_count(),
+            Expr::Empty(expr) => expr.child_count(),
+            Expr::Record(expr) => expr.child_count(),
+            Expr::Get(expr) => expr.child_count(),
         }
     }
 
-    fn children(&self) -> Vec<Box<dyn Printable>> {
-        return match self {
+    fn children(&self) -> Vec<&dyn Printable> {
+        match self {
             Expr::Number(expr) => expr.children(),
             Expr::Binary(expr) => expr.children(),
             Expr::With(expr) => expr.children(),
             Expr::Id(expr) => expr.children(),
+            Expr::Error(expr) => expr.children(),
+            Expr::Bool(expr) => expr.children(),
+            Expr::If(expr) => expr.children(),
+            Expr::Call(expr) => expr.children(),
+            Expr::Fun(expr) => expr.children(),
+            Expr::App(expr) => expr.children(),
+            Expr::Str(expr) => expr.children(),
+            Expr::Empty(expr) => expr.children(),
+            Expr::Record(expr) => expr.children(),
+            Expr::Get(expr) => expr.children(),
+        }
+    }
+
+    fn edge_labels(&self) -> Vec<&'static str> {
+        match self {
+            Expr::Number(expr) => expr.edge_labels(),
+            Expr::Binary(expr) => expr.edge_labels(),
+            Expr::With(expr) => expr.edge_labels(),
+            Expr::Id(expr) => expr.edge_labels(),
+            Expr::Error(expr) => expr.edge_labels(),
+            Expr::Bool(expr) => expr.edge_labels(),
+            Expr::If(expr) => expr.edge_labels(),
+            Expr::Call(expr) => expr.edge_labels(),
+            Expr::Fun(expr) => expr.edge_labels(),
+            Expr::App(expr) => expr.edge_labels(),
+            Expr::Str(expr) => expr.edge_labels(),
+            Expr::Empty(expr) => expr.edge_labels(),
+            Expr::Record(expr) => expr.edge_labels(),
+            Expr::Get(expr) => expr.edge_labels(),
         }
     }
 
     fn name(&self) -> String {
-        return match self {
+        match self {
             Expr::Number(expr) => expr.name(),
             Expr::Binary(expr) => expr.name(),
             Expr::With(expr) => expr.name(),
             Expr::Id(expr) => expr.name(),
+            Expr::Error(expr) => expr.name(),
+            Expr::Bool(expr) => expr.name(),
+            Expr::If(expr) => expr.name(),
+            Expr::Call(expr) => expr.name(),
+            Expr::Fun(expr) => expr.name(),
+            Expr::App(expr) => expr.name(),
+            Expr::Str(expr) => expr.name(),
+            Expr::Empty(expr) => expr.name(),
+            Expr::Record(expr) => expr.name(),
+            Expr::Get(expr) => expr.name(),
+        }
+    }
+
+    fn detail(&self) -> Option<String> {
+        match self {
+            Expr::Number(expr) => expr.detail(),
+            Expr::Binary(expr) => expr.detail(),
+            Expr::With(expr) => expr.detail(),
+            Expr::Id(expr) => expr.detail(),
+            Expr::Error(expr) => expr.detail(),
+            Expr::Bool(expr) => expr.detail(),
+            Expr::If(expr) => expr.detail(),
+            Expr::Call(expr) => expr.detail(),
+            Expr::Fun(expr) => expr.detail(),
+            Expr::App(expr) => expr.detail(),
+            Expr::Str(expr) => expr.detail(),
+            Expr::Empty(expr) => expr.detail(),
+            Expr::Record(expr) => expr.detail(),
+            Expr::Get(expr) => expr.detail(),
+        }
+    }
+
+    fn scope(&self) -> Option<String> {
+        match self {
+            Expr::Number(expr) => expr.scope(),
+            Expr::Binary(expr) => expr.scope(),
+            Expr::With(expr) => expr.scope(),
+            Expr::Id(expr) => expr.scope(),
+            Expr::Error(expr) => expr.scope(),
+            Expr::Bool(expr) => expr.scope(),
+            Expr::If(expr) => expr.scope(),
+            Expr::Call(expr) => expr.scope(),
+            Expr::Fun(expr) => expr.scope(),
+            Expr::App(expr) => expr.scope(),
+            Expr::Str(expr) => expr.scope(),
+            Expr::Empty(expr) => expr.scope(),
+            Expr::Record(expr) => expr.scope(),
+            Expr::Get(expr) => expr.scope(),
         }
     }
 }
 
 impl Display for Expr {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        return match self {
+        match self {
             Expr::Number(expr) => expr.fmt(f),
             Expr::Binary(expr) => expr.fmt(f),
             Expr::With(expr) => expr.fmt(f),
             Expr::Id(expr) => expr.fmt(f),
+            Expr::Error(expr) => expr.fmt(f),
+            Expr::Bool(expr) => expr.fmt(f),
+            Expr::If(expr) => expr.fmt(f),
+            Expr::Call(expr) => expr.fmt(f),
+            Expr::Fun(expr) => expr.fmt(f),
+            Expr::App(expr) => expr.fmt(f),
+            Expr::Str(expr) => expr.fmt(f),
+            Expr::Empty(expr) => expr.fmt(f),
+            Expr::Record(expr) => expr.fmt(f),
+            Expr::Get(expr) => expr.fmt(f),
         }
     }
 }
@@ -0,0 +1,196 @@
+use crate::{Expr, Id, Meta, Binding};
+use crate::calc::eval_within;
+use crate::limits::{Budget, EvalLimits};
+use crate::parse::{Parsable, parse_recovering, prepend_comments, recover_error, unreadable, DEFINE_OP};
+use crate::reader::{read_all, Datum, DatumKind, Span};
+use crate::subst::Substitutable;
+use crate::value::Value;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+// ============================================================================
+// PROGRAM:
+// Program = Form*
+// Form    = (define x WAE)
+//         | WAE
+// ============================================================================
+
+// Program is a sequence of top-level forms read from a single source file.
+pub struct Program {
+    pub(crate) forms: Vec<Form>,
+}
+
+// Form is a top-level form of a program: either a definition or an expression
+// whose value is reported.
+pub enum Form {
+    Define(Define),
+    Expr(Expr),
+}
+
+// Define binds a name to the value of an expression for the rest of the
+// program.
+pub struct Define {
+    pub(crate) name:  Box<Id>,
+    pub(crate) value: Expr,
+    pub(crate) meta:  Meta,
+}
+
+// parse_program returns the program made of the forms in the given source
+// text. A program with no forms is valid.
+pub fn parse_program(source: &str) -> Result<Program, String> {
+    let mut forms: Vec<Form> = Vec::new();
+    for datum in read_all(source)? {
+        forms.push(Form::parse(&datum)?);
+    }
+    Ok(Program{ forms })
+}
+
+// parse_program_with_recovery returns the program made of the forms in the
+// given source text along with every parse error in it, as parse_with_recovery
+// does for a single expression. A definition whose name is malformed becomes
+// an expression form holding an Error node.
+pub fn parse_program_with_recovery(source: &str) -> (Program, Vec<String>) {
+    let mut errors: Vec<String> = Vec::new();
+    let datums: Vec<Datum> = match read_all(source) {
+        Ok(datums) => datums,
+        Err(msg) => {
+            errors.push(msg.clone());
+            return (Program{ forms: vec![Form::Expr(unreadable(source, msg))] }, errors)
+        },
+    };
+    let mut forms: Vec<Form> = Vec::new();
+    for datum in &datums {
+        let form: Form = match &datum.kind {
+            DatumKind::List(items) if is_define(items) => match parse_define_name(datum) {
+                Ok(name) => {
+                    let value: Expr = parse_recovering(&items[2], source, &mut errors);
+                    Form::Define(Define{ name, value, meta: Meta::of(datum) })
+                },
+                Err(msg) => Form::Expr(recover_error(datum, source, msg, &mut errors)),
+            },
+            _ => Form::Expr(parse_recovering(datum, source, &mut errors)),
+        };
+        forms.push(form);
+    }
+    (Program{ forms }, errors)
+}
+
+impl Parsable for Form {
+    fn parse(datum: &Datum) -> Result<Form, String> {
+        match &datum.kind {
+            DatumKind::List(items) if is_define(items) => Ok(Form::Define(Define::parse(datum)?)),
+            _ => Ok(Form::Expr(Expr::parse(datum)?)),
+        }
+    }
+}
+
+impl Parsable for Define {
+    fn parse(datum: &Datum) -> Result<Define, String> {
+        let name: Box<Id> = parse_define_name(datum)?;
+        let value: Expr = Expr::parse(&list_items(datum)[2])?;
+        Ok(Define{ name, value, meta: Meta::of(datum) })
+    }
+}
+
+// parse_define_name checks the shape of the given definition and returns the
+// name it defines. Comments around the 'define' keyword lead the name.
+fn parse_define_name(datum: &Datum) -> Result<Box<Id>, String> {
+    let items: &[Datum] = match &datum.kind {
+        DatumKind::List(items) if items.len() == 3 => items,
+        _ => return Err(format!("{}: expected 'define' symbol, identifier, and expression for definition", datum.span)),
+    };
+    let mut name: Id = Id::parse(&items[1])?;
+    prepend_comments(&mut name.meta.trivia, &items[0].trivia);
+    Ok(Box::new(name))
+}
+
+// list_items returns the items of the given datum, which parse_define_name has
+// already checked is a list.
+fn list_items(datum: &Datum) -> &[Datum] {
+    match &datum.kind {
+        DatumKind::List(items) => items,
+        _ => &[],
+    }
+}
+
+// is_define returns true if the given list items start with the 'define'
+// symbol.
+fn is_define(items: &[Datum]) -> bool {
+    match items.first().map(|item| &item.kind) {
+        Some(DatumKind::Symbol(name)) => name == DEFINE_OP,
+        _ => false,
+    }
+}
+
+// Outcome is the result of evaluating one form of a program.
+pub enum Outcome {
+    // Defined reports the value bound by a definition.
+    Defined(String, Value),
+    // Value reports the value of an expression.
+    Value(Value),
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Defined(name, val) => write!(f, "{} = {}", name, val),
+            Outcome::Value(val) => write!(f, "{}", val),
+        }
+    }
+}
+
+// evaluate evaluates the forms of the given program in order, each within the
+// given limits, and returns the outcome of each. Every form sees the
+// definitions that precede it, with later definitions of a name shadowing
+// earlier ones. A definition that fails to evaluate reports its error and
+// leaves its name unbound from then on.
+pub fn evaluate(program: &Program, limits: &EvalLimits) -> Vec<Result<Outcome, String>> {
+    let mut defined: Vec<Binding> = Vec::new();
+    let mut outcomes: Vec<Result<Outcome, String>> = Vec::new();
+    for form in &program.forms {
+        let outcome: Result<Outcome, String> = match form {
+            Form::Define(define) => {
+                let result: Result<Value, String> = eval_form(&define.value, &defined, limits);
+                defined.retain(|binding| binding.identifier.val != define.name.val);
+                if let Ok(value) = &result {
+                    let replace: Expr = value.to_expr();
+                    defined.push(Binding{ identifier: define.name.clone(), annotation: None, replace, meta: define.meta.clone() });
+                }
+                result.map(|value| Outcome::Defined(define.name.val.clone(), value))
+            },
+            Form::Expr(expr) => eval_form(expr, &defined, limits).map(Outcome::Value),
+        };
+        outcomes.push(outcome);
+    }
+    outcomes
+}
+
+// eval_form evaluates the given expression, with the given definitions bound
+// in it, within the given limits. Binding the definitions copies their values
+// into the expression, so it is charged to the same budget as evaluation.
+fn eval_form(expr: &Expr, defined: &[Binding], limits: &EvalLimits) -> Result<Value, String> {
+    let mut budget: Budget = Budget::new(limits, expr);
+    let bound: Expr = bind(expr, defined, &mut budget)?;
+    eval_within(bound, &mut budget)
+}
+
+// bind substitutes the values of the given definitions into the given
+// expression, charging the work done to the given budget. A With in the
+// expression that rebinds a defined name shadows the definition.
+fn bind(expr: &Expr, defined: &[Binding], budget: &mut Budget) -> Result<Expr, String> {
+    let mut bound: Expr = expr.clone();
+    for binding in defined {
+        bound = bound.subst_within(binding, budget)?;
+    }
+    Ok(bound)
+}
+
+impl Form {
+    // span returns the location of this form in the source text.
+    pub(crate) fn span(&self) -> Span {
+        match self {
+            Form::Define(define) => define.meta.span,
+            Form::Expr(expr) => expr.meta().span,
+        }
+    }
+}
@@ -0,0 +1,582 @@
+use crate::parse::{OPEN_PAREN, CLOSE_PAREN, OPEN_BRACE, CLOSE_BRACE};
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+// Span is the location of a piece of source text: the byte offsets of its
+// start and end, and the line and column (both starting at 1) of its start.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Span {
+    pub start:  usize,
+    pub end:    usize,
+    pub line:   usize,
+    pub column: usize,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// Comment is a comment in the source text. Its text is kept exactly as
+// written, including its delimiters, so that it can be re-emitted.
+#[derive(Clone)]
+pub struct Comment {
+    pub kind: CommentKind,
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum CommentKind {
+    // Line runs from ';' to the end of the line.
+    Line,
+    // Block is delimited by '#|' and '|#', and may be nested.
+    Block,
+    // Datum is '#;' followed by a datum that is ignored.
+    Datum,
+}
+
+// Trivia holds the comments attached to a datum or node. A comment that starts
+// on the line where the previous datum in the same list ends, or that is the
+// last thing in a list, trails that datum. Any other comment leads the datum
+// that follows it. Comments in an empty list trail the list itself.
+//
+// The reader never fills inner. It holds the comments that the parser finds
+// inside a node with no inputs to attach them to, such as (list ; none).
+#[derive(Clone, Default)]
+pub struct Trivia {
+    pub leading:  Vec<Comment>,
+    pub trailing: Vec<Comment>,
+    pub inner:    Vec<Comment>,
+}
+
+// Datum is a node of the S-expression syntax that WAE programs are written in.
+// The reader only knows about parentheses, brackets, atoms and comments; the
+// forms of the language are recognised from datums by the AST builder in
+// parse.rs.
+//
+// Like Expr, Datum implements Drop and Display with an explicit work stack, so
+// that it can be nested far deeper than the call stack.
+pub struct Datum {
+    pub kind:   DatumKind,
+    pub span:   Span,
+    pub trivia: Trivia,
+}
+
+pub enum DatumKind {
+    // List is a parenthesized sequence: (a b c)
+    List(Vec<Datum>),
+    // Bracket is a bracketed sequence: [a b c]
+    Bracket(Vec<Datum>),
+    // Number is an atom that starts with a digit.
+    Number(i32),
+    // String is a string literal, with its escape sequences replaced by the
+    // characters they stand for.
+    String(String),
+    // Symbol is any other atom.
+    Symbol(String),
+}
+
+// read returns the single datum in the given source text. Comments before it
+// lead it, and comments after it trail it.
+pub fn read(source: &str) -> Result<Datum, String> {
+    read_within(source, None)
+}
+
+// read_within is read, failing if lists are nested more than max_depth deep.
+pub fn read_within(source: &str, max_depth: Option<usize>) -> Result<Datum, String> {
+    let mut reader: Reader = Reader::new(source, max_depth);
+    let leading: Vec<Comment> = reader.read_trivia()?;
+    if reader.at_end() {
+        return Err("expected a non-empty input".to_string())
+    }
+    let mut datum: Datum = reader.read_datum()?;
+    datum.trivia.leading.splice(0..0, leading);
+    let trailing: Vec<Comment> = reader.read_trivia()?;
+    datum.trivia.trailing.extend(trailing);
+    if !reader.at_end() {
+        return Err(format!("{}: unexpected text after expression", reader.span_from(reader.position)))
+    }
+    Ok(datum)
+}
+
+// read_all returns every datum in the given source text, in order. Comments are
+// attached as they are within a list: a comment on the line where a datum ends
+// trails it, and any other comment leads the datum that follows it.
+pub fn read_all(source: &str) -> Result<Vec<Datum>, String> {
+    let mut reader: Reader = Reader::new(source, None);
+    let mut datums: Vec<Datum> = Vec::new();
+    loop {
+        let previous_line: usize = reader.line;
+        let mut comments: Vec<Comment> = reader.read_trivia()?;
+        if let Some(previous) = datums.last_mut() {
+            let same_line: usize = comments.iter()
+                .take_while(|comment| comment.span.line == previous_line)
+                .count();
+            previous.trivia.trailing.extend(comments.drain(..same_line));
+            if reader.at_end() {
+                previous.trivia.trailing.append(&mut comments);
+            }
+        }
+        if reader.at_end() {
+            return Ok(datums)
+        }
+        let mut datum: Datum = reader.read_datum()?;
+        datum.trivia.leading.splice(0..0, comments);
+        datums.push(datum);
+    }
+}
+
+// Reader reads datums from source text while tracking line and column.
+struct Reader<'a> {
+    source: &'a str,
+    position: usize,
+    line: usize,
+    column: usize,
+    // max_depth is the number of lists that may be open at once, if limited.
+    max_depth: Option<usize>,
+}
+
+// Open is a list that the reader has started but not yet closed, or a datum
+// comment whose datum it has not yet read.
+enum Open {
+    List(OpenList),
+    Comment(OpenComment),
+}
+
+impl Open {
+    // depth returns the number of lists open at this point, counting this one
+    // if it is a list.
+    fn depth(&self) -> usize {
+        match self {
+            Open::List(list) => list.depth,
+            Open::Comment(comment) => comment.depth,
+        }
+    }
+}
+
+// OpenList is a list that the reader has started but not yet closed.
+struct OpenList {
+    first: char,
+    close: char,
+    start: Span,
+    depth: usize,
+    items: Vec<Datum>,
+    trivia: Trivia,
+    // leading holds the comments that lead the next item.
+    leading: Vec<Comment>,
+    // comments holds the comments read since the last item, or the opening
+    // bracket, which ended on the given line.
+    comments: Vec<Comment>,
+    line: usize,
+}
+
+// OpenComment is a datum comment that starts at the given span, inside the
+// given number of lists.
+struct OpenComment {
+    start: Span,
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(source: &'a str, max_depth: Option<usize>) -> Reader<'a> {
+        Reader{ source, position: 0, line: 1, column: 1, max_depth }
+    }
+
+    // read_datum reads the datum starting at the current position, which must
+    // not be whitespace or the end of the input.
+    fn read_datum(&mut self) -> Result<Datum, String> {
+        self.read_open(Vec::new())
+    }
+
+    // read_open reads the datum starting at the current position, which must
+    // not be whitespace or the end of the input, and carries on until every
+    // one of the given lists and datum comments is closed. It returns the last
+    // datum it finishes. Lists and datum comments are read with this explicit
+    // stack of the ones that are still open rather than by recursion, so that
+    // nesting is bounded only by memory and max_depth.
+    fn read_open(&mut self, mut open: Vec<Open>) -> Result<Datum, String> {
+        loop {
+            let start: Span = self.span_from(self.position);
+            let first: char = self.peek().unwrap();
+            let mut done: Datum;
+            if first == OPEN_PAREN || first == OPEN_BRACE {
+                let depth: usize = open.last().map_or(0, Open::depth) + 1;
+                if self.max_depth.is_some_and(|max_depth| depth > max_depth) {
+                    return Err(format!("{}: expression is nested more than {} levels deep",
+                                       start, self.max_depth.unwrap()))
+                }
+                let close: char = if first == OPEN_PAREN { CLOSE_PAREN } else { CLOSE_BRACE };
+                self.advance();
+                open.push(Open::List(OpenList{
+                    first, close, start, depth,
+                    items: Vec::new(), trivia: Trivia::default(), leading: Vec::new(), comments: Vec::new(), line: self.line,
+                }));
+                match self.read_open_trivia(&mut open)? {
+                    Some(datum) => done = datum,
+                    None => continue,
+                }
+            } else if first == QUOTE {
+                done = self.read_string(start)?;
+            } else {
+                done = self.read_atom(start, first)?;
+            }
+            // Finishing a datum can close the lists around it, or end the
+            // datum comment it belongs to, one after the other.
+            loop {
+                match open.last_mut() {
+                    None => return Ok(done),
+                    Some(Open::List(list)) => {
+                        done.trivia.leading.splice(0..0, list.leading.drain(..));
+                        list.items.push(done);
+                        list.line = self.line;
+                    },
+                    Some(Open::Comment(comment)) => {
+                        let comment: Comment = self.comment(CommentKind::Datum, comment.start);
+                        open.pop();
+                        match open.last_mut() {
+                            None => return Ok(done),
+                            Some(Open::List(list)) => list.comments.push(comment),
+                            // A datum comment inside another is part of it.
+                            Some(Open::Comment(_)) => {},
+                        }
+                    },
+                }
+                match self.read_open_trivia(&mut open)? {
+                    Some(datum) => done = datum,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    // read_open_trivia reads the comments that follow the last item of the
+    // innermost open list, or its opening bracket, or that follow the '#;' of
+    // the innermost open datum comment. A datum comment among them is opened
+    // in turn, and its datum is left to read_open. It returns the list if it
+    // is closed.
+    fn read_open_trivia(&mut self, open: &mut Vec<Open>) -> Result<Option<Datum>, String> {
+        loop {
+            let datum_comment: Option<Span> = match open.last_mut().unwrap() {
+                Open::List(list) => self.read_comments(&mut list.comments)?,
+                // The comments inside a datum comment are part of it.
+                Open::Comment(_) => self.read_comments(&mut Vec::new())?,
+            };
+            match datum_comment {
+                Some(start) => {
+                    let depth: usize = open.last().unwrap().depth();
+                    open.push(Open::Comment(OpenComment{ start, depth }))
+                },
+                None => break,
+            }
+        }
+        let closed: Option<Datum> = match open.last_mut().unwrap() {
+            Open::List(list) => self.attach_list_trivia(list)?,
+            Open::Comment(comment) => {
+                if self.at_end() || self.peek() == Some(CLOSE_PAREN) || self.peek() == Some(CLOSE_BRACE) {
+                    return Err(format!("{}: expected a datum after '{}'", comment.start, DATUM_COMMENT))
+                }
+                None
+            },
+        };
+        if closed.is_some() {
+            open.pop();
+        }
+        Ok(closed)
+    }
+
+    // attach_list_trivia attaches the comments that follow the last item of
+    // the given list, or its opening bracket. It returns the list if it is
+    // closed, and otherwise keeps the comments that lead the next item.
+    fn attach_list_trivia(&mut self, list: &mut OpenList) -> Result<Option<Datum>, String> {
+        let mut comments: Vec<Comment> = std::mem::take(&mut list.comments);
+        let previous_line: usize = list.line;
+        if let Some(previous) = list.items.last_mut() {
+            let same_line: usize = comments.iter()
+                .take_while(|comment| comment.span.line == previous_line)
+                .count();
+            previous.trivia.trailing.extend(comments.drain(..same_line));
+        }
+        match self.peek() {
+            Some(c) if c == list.close => {
+                match list.items.last_mut() {
+                    Some(previous) => previous.trivia.trailing.extend(comments),
+                    None => list.trivia.trailing.extend(comments),
+                }
+                self.advance();
+                let span: Span = Span{ end: self.position, ..list.start };
+                let items: Vec<Datum> = std::mem::take(&mut list.items);
+                let kind: DatumKind = if list.first == OPEN_PAREN { DatumKind::List(items) } else { DatumKind::Bracket(items) };
+                Ok(Some(Datum{ kind, span, trivia: std::mem::take(&mut list.trivia) }))
+            },
+            Some(c) if c == CLOSE_PAREN || c == CLOSE_BRACE => {
+                Err(format!("{}: expected '{}' to close '{}' at {} but found '{}'",
+                            self.span_from(self.position), list.close, list.first, list.start, c))
+            },
+            Some(_) => {
+                list.leading = comments;
+                Ok(None)
+            },
+            None => Err(format!("{}: expected '{}' to close '{}' at {}",
+                                self.span_from(self.position), list.close, list.first, list.start)),
+        }
+    }
+
+    // read_atom reads the number or symbol that starts at the current
+    // position.
+    fn read_atom(&mut self, start: Span, first: char) -> Result<Datum, String> {
+        if first == CLOSE_PAREN || first == CLOSE_BRACE {
+            return Err(format!("{}: unexpected '{}'", start, first))
+        }
+
+        while let Some(c) = self.peek() {
+            if is_delimiter(c) {
+                break
+            }
+            self.advance();
+        }
+        let span: Span = Span{ end: self.position, ..start };
+        let text: &str = &self.source[span.start..span.end];
+        if first.is_ascii_digit() {
+            return match text.parse::<i32>() {
+                Ok(val) => Ok(Datum{ kind: DatumKind::Number(val), span, trivia: Trivia::default() }),
+                Err(_) => Err(format!("{}: invalid number: {}", span, text)),
+            }
+        }
+        Ok(Datum{ kind: DatumKind::Symbol(text.to_string()), span, trivia: Trivia::default() })
+    }
+
+    // read_string reads the string literal that starts at the current
+    // position. A backslash escapes the character after it, which must be one
+    // of those in ESCAPES.
+    fn read_string(&mut self, start: Span) -> Result<Datum, String> {
+        let mut val: String = String::new();
+        self.advance();
+        loop {
+            let escape: Span = self.span_from(self.position);
+            let c: char = match self.peek() {
+                Some(c) => c,
+                None => return Err(format!("{}: unterminated string", start)),
+            };
+            self.advance();
+            if c == QUOTE {
+                break
+            }
+            if c != BACKSLASH {
+                val.push(c);
+                continue
+            }
+            match self.peek().and_then(|c| ESCAPES.iter().find(|(name, _)| *name == c)) {
+                Some((_, replaced)) => val.push(*replaced),
+                None if self.at_end() => return Err(format!("{}: unterminated string", start)),
+                None => return Err(format!("{}: unknown escape sequence: {}{}", escape, BACKSLASH, self.peek().unwrap())),
+            }
+            self.advance();
+        }
+        let span: Span = Span{ end: self.position, ..start };
+        Ok(Datum{ kind: DatumKind::String(val), span, trivia: Trivia::default() })
+    }
+
+    // read_trivia skips whitespace and returns the comments found before the
+    // next datum or the end of the input.
+    fn read_trivia(&mut self) -> Result<Vec<Comment>, String> {
+        let mut comments: Vec<Comment> = Vec::new();
+        while let Some(start) = self.read_comments(&mut comments)? {
+            let mut open: Vec<Open> = vec![Open::Comment(OpenComment{ start, depth: 0 })];
+            self.read_open_trivia(&mut open)?;
+            self.read_open(open)?;
+            comments.push(self.comment(CommentKind::Datum, start));
+        }
+        Ok(comments)
+    }
+
+    // read_comments skips whitespace and adds the line and block comments
+    // found to the given list. It stops before the next datum or the end of
+    // the input and returns nothing, or after the '#;' of a datum comment and
+    // returns where the comment starts.
+    fn read_comments(&mut self, comments: &mut Vec<Comment>) -> Result<Option<Span>, String> {
+        loop {
+            while self.peek().is_some_and(char::is_whitespace) {
+                self.advance();
+            }
+            let start: Span = self.span_from(self.position);
+            let rest: &str = &self.source[self.position..];
+            let kind: CommentKind;
+            if rest.starts_with(LINE_COMMENT) {
+                kind = CommentKind::Line;
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.advance();
+                }
+            } else if rest.starts_with(BLOCK_COMMENT_OPEN) {
+                kind = CommentKind::Block;
+                self.skip_block_comment(start)?;
+            } else if rest.starts_with(DATUM_COMMENT) {
+                self.advance();
+                self.advance();
+                return Ok(Some(start))
+            } else {
+                return Ok(None)
+            }
+            comments.push(self.comment(kind, start));
+        }
+    }
+
+    // comment returns the comment of the given kind that starts at the given
+    // span and ends at the current position.
+    fn comment(&self, kind: CommentKind, start: Span) -> Comment {
+        let span: Span = Span{ end: self.position, ..start };
+        let text: String = self.source[span.start..span.end].to_string();
+        Comment{ kind, text, span }
+    }
+
+    // skip_block_comment skips a possibly nested block comment starting at the
+    // current position.
+    fn skip_block_comment(&mut self, start: Span) -> Result<(), String> {
+        let mut depth: usize = 0;
+        loop {
+            let rest: &str = &self.source[self.position..];
+            if rest.starts_with(BLOCK_COMMENT_OPEN) {
+                depth += 1;
+            } else if rest.starts_with(BLOCK_COMMENT_CLOSE) {
+                depth -= 1;
+            } else if rest.is_empty() {
+                return Err(format!("{}: unterminated block comment", start))
+            } else {
+                self.advance();
+                continue
+            }
+            self.advance();
+            self.advance();
+            if depth == 0 {
+                return Ok(())
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.position..].chars().next()
+    }
+
+    fn advance(&mut self) {
+        if let Some(c) = self.peek() {
+            self.position += c.len_utf8();
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.position >= self.source.len()
+    }
+
+    // span_from returns an empty span at the current line and column, starting
+    // at the given offset.
+    fn span_from(&self, start: usize) -> Span {
+        Span{ start, end: start, line: self.line, column: self.column }
+    }
+}
+
+// is_delimiter returns true if the given character ends an atom.
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || c == OPEN_PAREN || c == CLOSE_PAREN || c == OPEN_BRACE || c == CLOSE_BRACE
+        || c == QUOTE || LINE_COMMENT.starts_with(c)
+}
+
+// quote returns the string literal that reads as the given string, escaping
+// every character that has an escape sequence.
+pub(crate) fn quote(val: &str) -> String {
+    let mut literal: String = String::new();
+    literal.push(QUOTE);
+    for c in val.chars() {
+        match ESCAPES.iter().find(|(_, replaced)| *replaced == c) {
+            Some((name, _)) => {
+                literal.push(BACKSLASH);
+                literal.push(*name)
+            },
+            None => literal.push(c),
+        }
+    }
+    literal.push(QUOTE);
+    literal
+}
+
+// String literal delimiters, and the escape sequences that may appear in a
+// string literal: a backslash followed by the first character stands for the
+// second.
+pub(crate) const QUOTE:     char = '"';
+pub(crate) const BACKSLASH: char = '\\';
+const ESCAPES: [(char, char); 5] = [('"', '"'), ('\\', '\\'), ('n', '\n'), ('t', '\t'), ('r', '\r')];
+
+// Comment delimiters.
+pub(crate) const LINE_COMMENT:        &str = ";";
+pub(crate) const BLOCK_COMMENT_OPEN:  &str = "#|";
+pub(crate) const BLOCK_COMMENT_CLOSE: &str = "|#";
+pub(crate) const DATUM_COMMENT:       &str = "#;";
+
+impl Display for Datum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        enum Part<'a> {
+            Datum(&'a Datum),
+            Text(char),
+        }
+        let mut parts: Vec<Part> = vec![Part::Datum(self)];
+        while let Some(part) = parts.pop() {
+            let (open, items, close) = match part {
+                Part::Text(c) => {
+                    write!(f, "{}", c)?;
+                    continue
+                },
+                Part::Datum(datum) => match &datum.kind {
+                    DatumKind::List(items) => (OPEN_PAREN, items, CLOSE_PAREN),
+                    DatumKind::Bracket(items) => (OPEN_BRACE, items, CLOSE_BRACE),
+                    DatumKind::Number(val) => {
+                        write!(f, "{}", val)?;
+                        continue
+                    },
+                    DatumKind::Symbol(name) => {
+                        write!(f, "{}", name)?;
+                        continue
+                    },
+                    DatumKind::String(val) => {
+                        write!(f, "{}", quote(val))?;
+                        continue
+                    },
+                },
+            };
+            write!(f, "{}", open)?;
+            parts.push(Part::Text(close));
+            for (i, item) in items.iter().enumerate().rev() {
+                parts.push(Part::Datum(item));
+                if i > 0 {
+                    parts.push(Part::Text(' '));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Datum {
+    // drop moves the items of nested lists onto a stack, so that every datum
+    // is dropped with no items left in it.
+    fn drop(&mut self) {
+        let mut stack: Vec<Datum> = Vec::new();
+        take_items(self, &mut stack);
+        while let Some(mut datum) = stack.pop() {
+            take_items(&mut datum, &mut stack);
+        }
+    }
+}
+
+// take_items moves the items of the given datum, if it is a list, onto the
+// given stack.
+fn take_items(datum: &mut Datum, stack: &mut Vec<Datum>) {
+    if let DatumKind::List(items) | DatumKind::Bracket(items) = &mut datum.kind {
+        stack.append(items)
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{Expr, Number, Binary, Operator, With, Binding, Id};
+use crate::ast::Error;
+use crate::calc::{located, unbound_identifier, unsupported};
+use crate::cse::{FreshNames, mentions};
+use crate::unparse::Unparsable;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+// trace reduces the given abstract syntax tree one step at a time until it is
+// a Number or cannot be reduced further, and returns every intermediate tree.
+//
+// A step either substitutes the binding of the leftmost outermost With into
+// its input, or applies the leftmost Binary whose inputs are both Numbers.
+// Unlike With::subst, which also carries out every With it passes, substitution
+// stops at a With that rebinds the identifier and otherwise leaves nested Withs
+// in place for later steps. Bound expressions are substituted unevaluated, so
+// the trace ends with the same value or error as calc. Only the arithmetic
+// language is traced: any other expression is stuck, and so is one nested
+// more than MAX_TRACE_DEPTH deep.
+pub fn trace(ast: &Expr) -> Trace {
+    let mut names: FreshNames = FreshNames::new(ast);
+    let mut steps: Vec<Step> = Vec::new();
+    let mut current: Expr = ast.clone();
+    loop {
+        if let Err(msg) = check_depth(&current) {
+            return Trace{ start: ast.clone(), steps, error: Some(msg) }
+        }
+        match current.step(&mut names) {
+            Ok(Some((next, reduction))) => {
+                steps.push(Step{ reduction, expr: next.clone() });
+                current = next
+            },
+            Ok(None) => return Trace{ start: ast.clone(), steps, error: None },
+            Err(msg) => return Trace{ start: ast.clone(), steps, error: Some(msg) },
+        }
+    }
+}
+
+// MAX_TRACE_DEPTH is how deeply an expression may be nested for trace to step
+// it. Steps are found by recursion, and a trace keeps every intermediate tree,
+// so tracing is only practical for small expressions anyway.
+pub const MAX_TRACE_DEPTH: usize = 1000;
+
+// check_depth returns an error located at the first node of the given
+// expression that is nested more than MAX_TRACE_DEPTH deep, if there is one.
+fn check_depth(expr: &Expr) -> Result<(), String> {
+    let mut stack: Vec<(&Expr, usize)> = vec![(expr, 1)];
+    while let Some((expr, depth)) = stack.pop() {
+        if depth > MAX_TRACE_DEPTH {
+            let msg: String = format!("expression is nested more than {} levels deep to trace", MAX_TRACE_DEPTH);
+            return Err(located(expr.meta().span, &msg))
+        }
+        stack.extend(expr.inputs().into_iter().rev().map(|input| (input, depth + 1)));
+    }
+    Ok(())
+}
+
+// Trace is the sequence of reductions from an expression to its value.
+pub struct Trace {
+    pub start: Expr,
+    pub steps: Vec<Step>,
+    // error is set if the last expression is stuck rather than a Number.
+    pub error: Option<String>,
+}
+
+// Step is a single reduction and the expression it produced.
+pub struct Step {
+    pub reduction: Reduction,
+    pub expr:      Expr,
+}
+
+// Reduction describes what a step did.
+pub enum Reduction {
+    // Subst substituted the binding of a With for the named identifier.
+    Subst(String),
+    // Apply applied an operator to two numbers and produced the result.
+    Apply(Operator, i32, i32, i32),
+}
+
+impl Display for Reduction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Reduction::Subst(name) => write!(f, "substitute {}", name),
+            Reduction::Apply(op, left, right, result) => {
+                let mut text: String = String::new();
+                op.unparse(&mut text);
+                write!(f, "({} {} {}) => {}", text, left, right, result)
+            },
+        }
+    }
+}
+
+// A type that implements Steppable can perform a single reduction.
+pub(crate) trait Steppable {
+    // step performs the first reduction in the expression rooted at this node
+    // and returns the reduced expression, or None if this node is a Number. It
+    // returns an error if the expression is stuck. Fresh names are drawn from
+    // the given generator when a With has to be renamed to avoid capture.
+    fn step(&self, names: &mut FreshNames) -> Result<Option<(Expr, Reduction)>, String>;
+}
+
+impl Steppable for Expr {
+    fn step(&self, names: &mut FreshNames) -> Result<Option<(Expr, Reduction)>, String> {
+        match self {
+            Expr::Number(expr) => expr.step(names),
+            Expr::Binary(expr) => expr.step(names),
+            Expr::With(expr) => expr.step(names),
+            Expr::Id(expr) => expr.step(names),
+            Expr::Error(expr) => expr.step(names),
+            _ => Err(unsupported(self, "trace")),
+        }
+    }
+}
+
+impl Steppable for Number {
+    fn step(&self, _: &mut FreshNames) -> Result<Option<(Expr, Reduction)>, String> {
+        Ok(None)
+    }
+}
+
+impl Steppable for Binary {
+    fn step(&self, names: &mut FreshNames) -> Result<Option<(Expr, Reduction)>, String> {
+        if let Some((left, reduction)) = self.left.step(names)? {
+            let expr: Expr = Binary{ op: self.op, left, right: self.right.clone(), meta: self.meta.clone() }.into();
+            return Ok(Some((expr, reduction)))
+        }
+        if let Some((right, reduction)) = self.right.step(names)? {
+            let expr: Expr = Binary{ op: self.op, left: self.left.clone(), right, meta: self.meta.clone() }.into();
+            return Ok(Some((expr, reduction)))
+        }
+        let (left, right): (i32, i32) = match (&self.left, &self.right) {
+            (Expr::Number(left), Expr::Number(right)) => (left.val, right.val),
+            _ => unreachable!("inputs that cannot step are numbers"),
+        };
+        let val: i32 = self.op.apply(left, right)?;
+        let expr: Expr = Number{ val, meta: self.meta.clone() }.into();
+        Ok(Some((expr, Reduction::Apply(self.op, left, right, val))))
+    }
+}
+
+impl Steppable for With {
+    fn step(&self, names: &mut FreshNames) -> Result<Option<(Expr, Reduction)>, String> {
+        let expr: Expr = substitute(&self.input, &self.binding.identifier, &self.binding.replace, names);
+        Ok(Some((expr, Reduction::Subst(self.binding.identifier.val.clone()))))
+    }
+}
+
+impl Steppable for Id {
+    fn step(&self, _: &mut FreshNames) -> Result<Option<(Expr, Reduction)>, String> {
+        Err(unbound_identifier(self))
+    }
+}
+
+impl Steppable for Error {
+    fn step(&self, _: &mut FreshNames) -> Result<Option<(Expr, Reduction)>, String> {
+        Err(self.message.clone())
+    }
+}
+
+// substitute replaces the free occurrences of the given identifier in the given
+// expression with the given value. The value is only ever substituted from the
+// outermost With, so any identifier free in it is unbound; a nested With that
+// binds such an identifier is renamed so that it cannot capture it.
+fn substitute(expr: &Expr, id: &Id, value: &Expr, names: &mut FreshNames) -> Expr {
+    match expr {
+        Expr::Id(expr) if expr.val == id.val => value.clone(),
+        Expr::Binary(expr) => {
+            let left: Expr = substitute(&expr.left, id, value, names);
+            let right: Expr = substitute(&expr.right, id, value, names);
+            Binary{ op: expr.op, left, right, meta: expr.meta.clone() }.into()
+        },
+        Expr::With(expr) => {
+            let replace: Expr = substitute(&expr.binding.replace, id, value, names);
+            let mut identifier: Box<Id> = expr.binding.identifier.clone();
+            let mut input: Expr = expr.input.clone();
+            if identifier.val != id.val {
+                if mentions(value, &identifier) {
+                    let fresh: Id = Id{ val: names.fresh().val, meta: identifier.meta.clone() };
+                    input = substitute(&input, &identifier, &fresh.clone().into(), names);
+                    identifier = Box::new(fresh);
+                }
+                input = substitute(&input, id, value, names);
+            }
+            let binding: Binding = Binding{ identifier, annotation: expr.binding.annotation.clone(), replace, meta: expr.binding.meta.clone() };
+            With{ binding, input, meta: expr.meta.clone() }.into()
+        },
+        _ => expr.clone(),
+    }
+}
@@ -1,94 +1,205 @@
-use crate::{Expr, Binary, With, Binding, Id};
+use crate::{Expr, Binary, Operator, Binding, Id, Meta};
+use crate::cse::{FreshNames, mentions};
+use crate::limits::Budget;
+use std::rc::Rc;
 
 // A type that implements Substitutable can propagate or effect a With
 // replacement.
-pub(crate) trait Substitutable {
+pub(crate) trait Substitutable: Sized {
     type Substituted;
 
-    // subst is recursively called on the input of a With in order to enact the
-    // replacement described by the With.
-    fn subst(self, binding: &Binding) -> Self::Substituted;
-
     // replace traverses the ast until a With expression is found, and then
-    // calls subst.
-    fn replace(self) -> Self::Substituted;
-}
-
-impl Substitutable for Expr {
-    type Substituted = Expr;
-
-    fn subst(self, binding: &Binding) -> Expr {
-        return match self {
-            Expr::Binary(expr) => expr.subst(binding),
-            Expr::With(expr) => expr.subst(binding),
-            Expr::Id(expr) if expr.should_replace(binding) => binding.replace.clone().into(),
-            _ => self,
-        }
+    // calls subst_within.
+    fn replace(self) -> Self::Substituted {
+        self.replace_within(&mut Budget::unlimited()).expect("an unlimited budget is never exceeded")
     }
 
-    fn replace(self) -> Expr {
-        return match self {
-            Expr::Binary(expr) => expr.replace(),
-            Expr::With(expr) => expr.replace(),
-            _ => self,
-        }
-    }
+    // subst_within is called on the input of a With in order to enact the
+    // replacement described by the With, charging the work done to the given
+    // budget.
+    fn subst_within(self, binding: &Binding, budget: &mut Budget) -> Result<Self::Substituted, String>;
+
+    // replace_within is replace, charging the work done to the given budget.
+    fn replace_within(self, budget: &mut Budget) -> Result<Self::Substituted, String>;
 }
 
-impl Substitutable for Binary {
+impl Substitutable for Expr {
     type Substituted = Expr;
 
-    fn subst(self, binding: &Binding) -> Expr {
-        let new_left: Expr = self.left.subst(binding);
-        let new_right: Expr = self.right.subst(binding);
-        Binary{ op: self.op, left: new_left, right: new_right }.into()
+    fn subst_within(self, binding: &Binding, budget: &mut Budget) -> Result<Expr, String> {
+        let binding: Rc<Binding> = Rc::new(binding.clone());
+        Substituter{ tasks: vec![Task::Subst(self, binding, 0)], results: Vec::new(), budget }.run()
     }
 
-    fn replace(self) -> Expr {
-        let new_left: Expr = self.left.replace();
-        let new_right: Expr = self.right.replace();
-        Binary{ op: self.op, left: new_left, right: new_right }.into()
+    fn replace_within(self, budget: &mut Budget) -> Result<Expr, String> {
+        Substituter{ tasks: vec![Task::Replace(self, 0)], results: Vec::new(), budget }.run()
     }
 }
 
-impl Substitutable for With {
-    type Substituted = Expr;
-
-    fn subst(self, binding: &Binding) -> Expr {
-        // First effect substitution on this With's binding using the given
-        // binding:
-        //   (With ([x 1] (With ([y (* x 2)]) (<expr>)))
-        //   =>
-        //   ((With ([x 1] (With ([y (* 1 2)]) (<expr>)))
-        let subst_binding: &Binding = &self.binding.subst(binding);
-
-        // Effect the replacement described by this With's binding.
-        let first_replace: Expr = self.input.subst(subst_binding);
-
-        // Now propagate the previous With replacement into the input. Replace
-        // this With with its input.
-        first_replace.subst(binding)
-    }
+// Task is a unit of work for a Substituter. Tasks that build a node pop the
+// results of the tasks for its inputs, which were pushed in order.
+enum Task {
+    // Subst substitutes the binding into the expression at the given depth.
+    Subst(Expr, Rc<Binding>, usize),
+    // Replace carries out every With in the expression at the given depth.
+    Replace(Expr, usize),
+    // Binary pops the left and right inputs and pushes a Binary.
+    Binary(Operator, Meta),
+    // Rebuild pops one input for each input of the node, whose own inputs
+    // have been taken, puts them back and pushes the node.
+    Rebuild(Expr),
+    // Bind pops the bound expression of a With, which has been substituted or
+    // replaced, and substitutes the binding into the input of the With. If a
+    // binding is given, it is then substituted into the result.
+    Bind(Box<Id>, Meta, Expr, Option<Rc<Binding>>, usize),
+    // Then pops an expression and substitutes the binding into it.
+    Then(Rc<Binding>, usize),
+}
 
-    fn replace(self) -> Expr {
-        self.input.subst(&self.binding.replace())
-    }
+// Substituter carries out substitution with an explicit work stack rather than
+// by recursion, so that the depth of a tree is bounded only by memory.
+struct Substituter<'a> {
+    tasks:   Vec<Task>,
+    results: Vec<Expr>,
+    budget:  &'a mut Budget,
 }
 
-impl Substitutable for Binding {
-    type Substituted = Binding;
+impl<'a> Substituter<'a> {
+    fn run(mut self) -> Result<Expr, String> {
+        while let Some(task) = self.tasks.pop() {
+            match task {
+                Task::Subst(expr, binding, depth) => self.subst(expr, binding, depth)?,
+                Task::Replace(expr, depth) => self.replace(expr, depth)?,
+                Task::Binary(op, meta) => {
+                    let right: Expr = self.results.pop().unwrap();
+                    let left: Expr = self.results.pop().unwrap();
+                    self.results.push(Binary{ op, left, right, meta }.into())
+                },
+                Task::Rebuild(mut expr) => {
+                    let start: usize = self.results.len() - expr.inputs().len();
+                    expr.fill(self.results.drain(start..));
+                    self.results.push(expr)
+                },
+                Task::Bind(identifier, meta, input, then, depth) => {
+                    let replace: Expr = self.results.pop().unwrap();
+                    let binding: Rc<Binding> = Rc::new(Binding{ identifier, annotation: None, replace, meta });
+                    if let Some(then) = then {
+                        self.tasks.push(Task::Then(then, depth));
+                    }
+                    self.tasks.push(Task::Subst(input, binding, depth + 1))
+                },
+                Task::Then(binding, depth) => {
+                    let expr: Expr = self.results.pop().unwrap();
+                    self.tasks.push(Task::Subst(expr, binding, depth))
+                },
+            }
+        }
+        Ok(self.results.pop().unwrap())
+    }
 
-    fn subst(self, binding: &Binding) -> Binding {
-        Binding{ identifier: self.identifier, replace: self.replace.subst(binding) }.into()
+    fn subst(&mut self, mut expr: Expr, binding: Rc<Binding>, depth: usize) -> Result<(), String> {
+        self.budget.visit(depth)?;
+        let leaf: bool = expr.is_leaf();
+        match &mut expr {
+            Expr::Binary(node) => {
+                self.tasks.push(Task::Binary(node.op, std::mem::take(&mut node.meta)));
+                self.tasks.push(Task::Subst(node.right.take(), binding.clone(), depth + 1));
+                self.tasks.push(Task::Subst(node.left.take(), binding, depth + 1))
+            },
+            Expr::With(node) => {
+                // First effect the replacement described by this With's
+                // binding, then propagate the given binding into the result,
+                // which replaces this With:
+                //   (With ([x 1] (With ([y (* x 2)]) (+ x y)))
+                //   =>
+                //   (With ([x 1] (+ x (* x 2)))
+                //   =>
+                //   (+ 1 (* 1 2))
+                // The given binding reaches the copies of this With's bound
+                // expression through the result, so it must not also be
+                // substituted into the bound expression beforehand: if its
+                // value mentions its own identifier, that would substitute it
+                // twice.
+                let replace: Expr = node.binding.replace.take();
+                let meta: Meta = std::mem::take(&mut node.binding.meta);
+                self.tasks.push(Task::Bind(node.binding.identifier.clone(), meta, node.input.take(), Some(binding), depth));
+                self.tasks.push(Task::Replace(replace, depth + 1))
+            },
+            Expr::Id(node) if node.val == binding.identifier.val => {
+                self.budget.step()?;
+                let copy: Expr = copy(&binding.replace, self.budget, depth)?;
+                self.results.push(copy)
+            },
+            // A function whose parameter is the identifier shadows the binding.
+            Expr::Fun(node) if node.param.val == binding.identifier.val => self.results.push(expr),
+            Expr::Fun(node) => {
+                // A parameter that is free in the bound expression would
+                // capture it, so it is renamed first:
+                //   (With ([x y]) (fun (y) (+ x y)))
+                //   =>
+                //   (fun (tmpa) (+ y tmpa))
+                if mentions(&binding.replace, &node.param) {
+                    let mut names: FreshNames = FreshNames::new(&node.body);
+                    names.reserve(&binding.replace);
+                    names.reserve(&(*binding.identifier).clone().into());
+                    let fresh: Id = Id{ meta: node.param.meta.clone(), ..names.fresh() };
+                    let rename: Binding = Binding{ identifier: node.param.clone(), annotation: None, replace: fresh.clone().into(), meta: Meta::default() };
+                    node.body = node.body.take().subst_within(&rename, self.budget)?;
+                    *node.param = fresh;
+                }
+                let body: Expr = node.body.take();
+                self.tasks.push(Task::Rebuild(expr));
+                self.tasks.push(Task::Subst(body, binding, depth + 1))
+            },
+            _ if !leaf => {
+                let inputs: Vec<Expr> = expr.inputs_mut().into_iter().map(Expr::take).collect();
+                self.tasks.push(Task::Rebuild(expr));
+                for input in inputs.into_iter().rev() {
+                    self.tasks.push(Task::Subst(input, binding.clone(), depth + 1));
+                }
+            },
+            _ => self.results.push(expr),
+        }
+        Ok(())
     }
 
-    fn replace(self) -> Binding {
-        Binding{ identifier: self.identifier, replace: self.replace.replace() }.into()
+    fn replace(&mut self, mut expr: Expr, depth: usize) -> Result<(), String> {
+        self.budget.visit(depth)?;
+        let leaf: bool = expr.is_leaf();
+        match &mut expr {
+            Expr::Binary(node) => {
+                self.tasks.push(Task::Binary(node.op, std::mem::take(&mut node.meta)));
+                self.tasks.push(Task::Replace(node.right.take(), depth + 1));
+                self.tasks.push(Task::Replace(node.left.take(), depth + 1))
+            },
+            Expr::With(node) => {
+                let replace: Expr = node.binding.replace.take();
+                let meta: Meta = std::mem::take(&mut node.binding.meta);
+                self.tasks.push(Task::Bind(node.binding.identifier.clone(), meta, node.input.take(), None, depth));
+                self.tasks.push(Task::Replace(replace, depth + 1))
+            },
+            _ if !leaf => {
+                let inputs: Vec<Expr> = expr.inputs_mut().into_iter().map(Expr::take).collect();
+                self.tasks.push(Task::Rebuild(expr));
+                for input in inputs.into_iter().rev() {
+                    self.tasks.push(Task::Replace(input, depth + 1));
+                }
+            },
+            _ => self.results.push(expr),
+        }
+        Ok(())
     }
 }
 
-impl Id {
-    fn should_replace(&self, binding: &Binding) -> bool {
-        self.val == binding.identifier.val
+// copy returns a clone of the given expression, which replaces an identifier
+// at the given depth. Copies can be exponentially large, so every node is
+// charged to the given budget before the clone is made.
+fn copy(expr: &Expr, budget: &mut Budget, depth: usize) -> Result<Expr, String> {
+    let mut stack: Vec<(&Expr, usize)> = vec![(expr, depth)];
+    while let Some((expr, depth)) = stack.pop() {
+        budget.visit(depth)?;
+        budget.grow(1)?;
+        stack.extend(expr.inputs().into_iter().map(|input| (input, depth + 1)));
     }
-}
\ No newline at end of file
+    Ok(expr.clone())
+}
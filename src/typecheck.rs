@@ -0,0 +1,455 @@
+use crate::{Expr, Number, Binary, With, Id};
+use crate::ast::{Error, Bool, If, Call, Primitive, Fun, App, Type, Str, Empty, Record, Get};
+use crate::calc::located;
+use crate::program::{Program, Form};
+use crate::reader::Span;
+
+// check returns the type of the given abstract syntax tree in the typed
+// dialect, or the first type error in it. It runs before evaluation, so a tree
+// that checks cannot fail at run time with a type error:
+//
+//   number        is the type of integers and arithmetic
+//   boolean       is the type of true, false, comparisons
+//   string        is the type of string literals and the string primitives
+//   (A -> B)      is the type of a function from A to B
+//   (listof A)    is the type of a list whose elements all have type A
+//   (record [x : A] ...)
+//                 is the type of a record whose field x has type A, and so on
+//
+// Every function parameter must be annotated. An empty list has no element
+// type of its own, so it may only appear where a list type is expected: as the
+// rest of a cons, or as a bound expression or argument whose type is known. A
+// With binding may be annotated, in which case its bound expression must have
+// the given type; otherwise the identifier has the type of its bound
+// expression. Both branches of an If must
+// have the same type. Two record types are the same only if they have the same
+// fields in the same order. Errors are located at the expression whose type is
+// wrong.
+pub fn check(ast: &Expr) -> Result<Type, String> {
+    ast.check(&mut Vec::new())
+}
+
+// check_program checks the forms of the given program in order and returns the
+// type of each. Every form sees the types of the definitions that precede it,
+// as in evaluate, and a definition that fails to check leaves its name unbound
+// from then on.
+pub fn check_program(program: &Program) -> Vec<Result<Type, String>> {
+    let mut scope: Scope = Vec::new();
+    let mut types: Vec<Result<Type, String>> = Vec::new();
+    for form in &program.forms {
+        let result: Result<Type, String> = match form {
+            Form::Define(define) => {
+                let result: Result<Type, String> = define.value.check(&mut scope);
+                scope.retain(|(name, _)| *name != define.name.val);
+                if let Ok(ty) = &result {
+                    scope.push((&define.name.val, ty.clone()));
+                }
+                result
+            },
+            Form::Expr(expr) => expr.check(&mut scope),
+        };
+        types.push(result);
+    }
+    types
+}
+
+// Scope maps each identifier in scope to its type, innermost last.
+type Scope<'a> = Vec<(&'a str, Type)>;
+
+// A type that implements Checkable can be given a type.
+pub(crate) trait Checkable {
+    // check returns the type of the expression rooted at this node, in which
+    // the identifiers in the given scope have the given types. The scope is
+    // left as it was.
+    fn check<'a>(&'a self, scope: &mut Scope<'a>) -> Result<Type, String>;
+}
+
+impl Checkable for Expr {
+    // check walks the tree with an explicit work stack rather than by
+    // recursion, so that its depth is bounded only by memory.
+    fn check<'a>(&'a self, scope: &mut Scope<'a>) -> Result<Type, String> {
+        let depth: usize = scope.len();
+        let mut checker: Checker = Checker{ tasks: vec![Task::Check(self)], types: Vec::new(), scope: std::mem::take(scope) };
+        let result: Result<Type, String> = checker.run();
+        *scope = checker.scope;
+        scope.truncate(depth);
+        result
+    }
+}
+
+// Task is a piece of a type check still to be done.
+enum Task<'a> {
+    // Check pushes the type of the expression.
+    Check(&'a Expr),
+    // Expect checks that the expression has the given type, and pushes
+    // nothing.
+    Expect(Type, &'a Expr),
+    // ExpectSame checks that the expression has the type on top of the stack,
+    // which it leaves there.
+    ExpectSame(&'a Expr),
+    // Compare pops the type of the expression and checks that it is the given
+    // type.
+    Compare(Type, &'a Expr),
+    Push(Type),
+    // Bind pops the type of a bound expression and binds the identifier to it.
+    Bind(&'a str),
+    Declare(&'a str, Type),
+    Unbind,
+    // Finish pops the types of the inputs of the node and pushes its type.
+    Finish(&'a dyn CheckableNode),
+}
+
+// Checker holds a type check in progress: the tasks still to do, the types of
+// the expressions checked so far, and the identifiers in scope.
+pub(crate) struct Checker<'a> {
+    tasks: Vec<Task<'a>>,
+    types: Vec<Type>,
+    scope: Scope<'a>,
+}
+
+impl<'a> Checker<'a> {
+    // run does the tasks in order and returns the one type they leave.
+    fn run(&mut self) -> Result<Type, String> {
+        while let Some(task) = self.tasks.pop() {
+            match task {
+                Task::Check(expr) => expr.check_node(self)?,
+                Task::Expect(expected, expr) => self.expect(expected, expr),
+                Task::ExpectSame(expr) => {
+                    let expected: Type = self.types.last().unwrap().clone();
+                    self.expect(expected, expr)
+                },
+                Task::Compare(expected, expr) => {
+                    let ty: Type = self.types.pop().unwrap();
+                    if ty != expected {
+                        return Err(mismatch(&expected.to_string(), &ty, expr.meta().span))
+                    }
+                },
+                Task::Push(ty) => self.types.push(ty),
+                Task::Bind(name) => {
+                    let ty: Type = self.types.pop().unwrap();
+                    self.scope.push((name, ty))
+                },
+                Task::Declare(name, ty) => self.scope.push((name, ty)),
+                Task::Unbind => {
+                    self.scope.pop();
+                },
+                Task::Finish(expr) => expr.finish(self)?,
+            }
+        }
+        Ok(self.types.pop().unwrap())
+    }
+
+    // then schedules the given tasks to be done next, in order.
+    fn then(&mut self, tasks: Vec<Task<'a>>) {
+        self.tasks.extend(tasks.into_iter().rev())
+    }
+
+    // expect schedules a check that the given expression has the expected
+    // type. An empty list has every list type, and the fields of a record
+    // written where a record type is expected are checked against the expected
+    // field types.
+    fn expect(&mut self, expected: Type, expr: &'a Expr) {
+        if let Type::List(_) = expected {
+            if is_empty_list(expr) {
+                return
+            }
+        }
+        if let (Type::Record(types), Expr::Record(record)) = (&expected, expr) {
+            let names_match: bool = types.len() == record.fields.len()
+                && types.iter().zip(&record.fields).all(|((name, _), field)| *name == field.name.val);
+            if names_match {
+                let fields = types.iter().zip(&record.fields);
+                self.then(fields.map(|((_, ty), field)| Task::Expect(ty.clone(), &field.value)).collect());
+                return
+            }
+        }
+        self.then(vec![Task::Check(expr), Task::Compare(expected, expr)])
+    }
+}
+
+// A type that implements CheckableNode is a node that can be given a type
+// without recursing into its inputs.
+pub(crate) trait CheckableNode {
+    // check_node either pushes the type of this node, or schedules the tasks
+    // that check its inputs and then combine their types.
+    fn check_node<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String>;
+
+    // finish pops the types of the inputs of this node, which check_node
+    // scheduled, and pushes its type.
+    fn finish<'a>(&'a self, _: &mut Checker<'a>) -> Result<(), String> {
+        unreachable!("finish is only scheduled by nodes that implement it")
+    }
+}
+
+impl CheckableNode for Expr {
+    fn check_node<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        match self {
+            Expr::Number(expr) => expr.check_node(checker),
+            Expr::Binary(expr) => expr.check_node(checker),
+            Expr::With(expr) => expr.check_node(checker),
+            Expr::Id(expr) => expr.check_node(checker),
+            Expr::Error(expr) => expr.check_node(checker),
+            Expr::Bool(expr) => expr.check_node(checker),
+            Expr::If(expr) => expr.check_node(checker),
+            Expr::Call(expr) => expr.check_node(checker),
+            Expr::Fun(expr) => expr.check_node(checker),
+            Expr::App(expr) => expr.check_node(checker),
+            Expr::Str(expr) => expr.check_node(checker),
+            Expr::Empty(expr) => expr.check_node(checker),
+            Expr::Record(expr) => expr.check_node(checker),
+            Expr::Get(expr) => expr.check_node(checker),
+        }
+    }
+}
+
+impl CheckableNode for Number {
+    fn check_node<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        checker.types.push(Type::Number);
+        Ok(())
+    }
+}
+
+impl CheckableNode for Binary {
+    fn check_node<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        checker.then(vec![
+            Task::Expect(Type::Number, &self.left),
+            Task::Expect(Type::Number, &self.right),
+            Task::Push(Type::Number),
+        ]);
+        Ok(())
+    }
+}
+
+impl CheckableNode for With {
+    fn check_node<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        let name: &str = &self.binding.identifier.val;
+        let mut tasks: Vec<Task> = match &self.binding.annotation {
+            Some(annotation) => vec![
+                Task::Expect(annotation.ty.clone(), &self.binding.replace),
+                Task::Declare(name, annotation.ty.clone()),
+            ],
+            None => vec![Task::Check(&self.binding.replace), Task::Bind(name)],
+        };
+        tasks.extend(vec![Task::Check(&self.input), Task::Unbind]);
+        checker.then(tasks);
+        Ok(())
+    }
+}
+
+impl CheckableNode for Id {
+    fn check_node<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        match checker.scope.iter().rev().find(|(name, _)| *name == self.val) {
+            Some((_, ty)) => {
+                let ty: Type = ty.clone();
+                checker.types.push(ty);
+                Ok(())
+            },
+            None => Err(located(self.meta.span, &format!("unbound identifier: {}", self.val))),
+        }
+    }
+}
+
+impl CheckableNode for Error {
+    fn check_node<'a>(&'a self, _: &mut Checker<'a>) -> Result<(), String> {
+        Err(self.message.clone())
+    }
+}
+
+impl CheckableNode for Bool {
+    fn check_node<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        checker.types.push(Type::Boolean);
+        Ok(())
+    }
+}
+
+impl CheckableNode for If {
+    fn check_node<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        checker.then(vec![
+            Task::Expect(Type::Boolean, &self.condition),
+            Task::Check(&self.then),
+            Task::ExpectSame(&self.otherwise),
+        ]);
+        Ok(())
+    }
+}
+
+impl CheckableNode for Call {
+    fn check_node<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        match self.prim {
+            Primitive::Cons | Primitive::First | Primitive::Rest | Primitive::IsEmpty => {
+                checker.then(vec![Task::Check(&self.args[0]), Task::Finish(self)])
+            },
+            // The elements of a list must all have the type of the first.
+            Primitive::List => {
+                let first: &Expr = match self.args.first() {
+                    Some(first) => first,
+                    None => return Err(unknown_element(self.meta.span)),
+                };
+                let mut tasks: Vec<Task> = vec![Task::Check(first)];
+                tasks.extend(self.args[1..].iter().map(Task::ExpectSame));
+                tasks.push(Task::Finish(self));
+                checker.then(tasks)
+            },
+            prim => {
+                let (params, result) = prim.signature().expect("only list primitives have no signature");
+                let mut tasks: Vec<Task> = params.into_iter().zip(&self.args).map(|(param, arg)| Task::Expect(param, arg)).collect();
+                tasks.push(Task::Push(result));
+                checker.then(tasks)
+            },
+        }
+        Ok(())
+    }
+
+    fn finish<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        let first: Type = checker.types.pop().unwrap();
+        match self.prim {
+            Primitive::Cons => {
+                let list: Type = Type::List(Box::new(first));
+                checker.types.push(list.clone());
+                checker.then(vec![Task::Expect(list, &self.args[1])])
+            },
+            Primitive::List => checker.types.push(Type::List(Box::new(first))),
+            _ => {
+                let item: Type = match &first {
+                    Type::List(item) => (**item).clone(),
+                    ty => return Err(mismatch("a list", ty, self.args[0].meta().span)),
+                };
+                checker.types.push(match self.prim {
+                    Primitive::First => item,
+                    Primitive::Rest => first,
+                    _ => Type::Boolean,
+                })
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Primitive {
+    // signature returns the types of the arguments this primitive takes, in
+    // order, and the type of its result, or None for a list primitive, whose
+    // types depend on the type of the elements.
+    pub(crate) fn signature(self) -> Option<(Vec<Type>, Type)> {
+        match self {
+            Primitive::Less | Primitive::Equal | Primitive::Greater => Some((vec![Type::Number, Type::Number], Type::Boolean)),
+            Primitive::StringAppend => Some((vec![Type::String, Type::String], Type::String)),
+            Primitive::StringLength => Some((vec![Type::String], Type::Number)),
+            Primitive::Substring => Some((vec![Type::String, Type::Number, Type::Number], Type::String)),
+            Primitive::NumberToString => Some((vec![Type::Number], Type::String)),
+            Primitive::StringToNumber => Some((vec![Type::String], Type::Number)),
+            Primitive::Cons | Primitive::First | Primitive::Rest | Primitive::IsEmpty | Primitive::List => None,
+        }
+    }
+}
+
+impl CheckableNode for Fun {
+    fn check_node<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        let param: Type = match &self.annotation {
+            Some(annotation) => annotation.ty.clone(),
+            None => {
+                let msg: String = format!("parameter {} needs a type annotation", self.param.val);
+                return Err(located(self.param.meta.span, &msg))
+            },
+        };
+        checker.then(vec![Task::Declare(&self.param.val, param), Task::Check(&self.body), Task::Finish(self)]);
+        Ok(())
+    }
+
+    fn finish<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        let result: Type = checker.types.pop().unwrap();
+        let (_, param): (&str, Type) = checker.scope.pop().unwrap();
+        checker.types.push(Type::Fun(Box::new(param), Box::new(result)));
+        Ok(())
+    }
+}
+
+impl CheckableNode for App {
+    fn check_node<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        checker.then(vec![Task::Check(&self.fun), Task::Finish(self)]);
+        Ok(())
+    }
+
+    fn finish<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        match checker.types.pop().unwrap() {
+            Type::Fun(param, result) => {
+                checker.types.push(*result);
+                checker.then(vec![Task::Expect(*param, &self.arg)]);
+                Ok(())
+            },
+            ty => Err(mismatch("a function", &ty, self.fun.meta().span)),
+        }
+    }
+}
+
+impl CheckableNode for Str {
+    fn check_node<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        checker.types.push(Type::String);
+        Ok(())
+    }
+}
+
+impl CheckableNode for Empty {
+    fn check_node<'a>(&'a self, _: &mut Checker<'a>) -> Result<(), String> {
+        Err(unknown_element(self.meta.span))
+    }
+}
+
+impl CheckableNode for Record {
+    fn check_node<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        let mut tasks: Vec<Task> = self.fields.iter().map(|field| Task::Check(&field.value)).collect();
+        tasks.push(Task::Finish(self));
+        checker.then(tasks);
+        Ok(())
+    }
+
+    fn finish<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        let start: usize = checker.types.len() - self.fields.len();
+        let types: Vec<Type> = checker.types.split_off(start);
+        let fields: Vec<(String, Type)> = self.fields.iter().map(|field| field.name.val.clone()).zip(types).collect();
+        checker.types.push(Type::Record(fields));
+        Ok(())
+    }
+}
+
+impl CheckableNode for Get {
+    fn check_node<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        checker.then(vec![Task::Check(&self.record), Task::Finish(self)]);
+        Ok(())
+    }
+
+    fn finish<'a>(&'a self, checker: &mut Checker<'a>) -> Result<(), String> {
+        let fields: Vec<(String, Type)> = match checker.types.pop().unwrap() {
+            Type::Record(fields) => fields,
+            ty => return Err(mismatch("a record", &ty, self.record.meta().span)),
+        };
+        match fields.into_iter().find(|(name, _)| *name == self.field.val) {
+            Some((_, ty)) => {
+                checker.types.push(ty);
+                Ok(())
+            },
+            None => Err(located(self.field.meta.span, &format!("record has no field: {}", self.field.val))),
+        }
+    }
+}
+
+// mismatch returns the error reported when an expression of the given type is
+// found where a different type is expected.
+fn mismatch(expected: &str, ty: &Type, span: Span) -> String {
+    located(span, &format!("expected {} but found {}", expected, ty))
+}
+
+// is_empty_list returns true if the given expression is the empty list, either
+// written as such or as a list of no elements.
+fn is_empty_list(expr: &Expr) -> bool {
+    match expr {
+        Expr::Empty(_) => true,
+        Expr::Call(call) => call.prim == Primitive::List && call.args.is_empty(),
+        _ => false,
+    }
+}
+
+// unknown_element returns the error reported for an empty list that is not
+// where a list type is expected.
+fn unknown_element(span: Span) -> String {
+    located(span, "the element type of an empty list is not known here; give it a type annotation")
+}
@@ -0,0 +1,630 @@
+use crate::{Expr, Number, Binary, Operator, With, Binding, Id};
+use crate::ast::{Error, Bool, If, Call, Primitive, Fun, App, Type, Annotation, TypeMeta, Str, Empty, Record, Field, Get};
+use crate::reader::{quote, Comment, CommentKind, Trivia};
+use crate::parse::{OPEN_PAREN, CLOSE_PAREN, OPEN_BRACE, CLOSE_BRACE, ADD_OP, SUB_OP, MUL_OP, DIV_OP, WITH_OP};
+use crate::parse::{LESS_OP, EQUAL_OP, GREATER_OP, IF_OP, FUN_OP, TRUE, FALSE, COLON, ARROW, NUMBER_TYPE, BOOLEAN_TYPE};
+use crate::parse::{STRING_APPEND_OP, STRING_LENGTH_OP, SUBSTRING_OP, NUMBER_TO_STRING_OP, STRING_TO_NUMBER_OP, STRING_TYPE};
+use crate::parse::{CONS_OP, FIRST_OP, REST_OP, IS_EMPTY_OP, LIST_OP, EMPTY, LISTOF_TYPE, RECORD_OP, GET_OP, DEFINE_OP};
+use crate::program::Define;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+// to_source returns WAE source text for the given abstract syntax tree, such
+// that parsing the text yields a structurally equal tree. Comments attached to
+// the nodes are re-emitted next to them.
+//
+// The grammar only has natural number literals, so a Number that is zero or
+// negative (for example one built by an optimization pass) is printed as is
+// and will not parse.
+pub fn to_source(ast: &Expr) -> String {
+    let mut source: String = String::new();
+    ast.unparse(&mut source);
+    source
+}
+
+// A type that implements Unparsable can append its concrete syntax to a
+// string.
+pub(crate) trait Unparsable {
+    // unparse appends the source text of the expression rooted at this node,
+    // surrounded by the node's comments.
+    fn unparse(&self, source: &mut String) {
+        let trivia: Option<&Trivia> = self.trivia();
+        if let Some(trivia) = trivia {
+            unparse_leading(trivia, source);
+        }
+        self.unparse_node(source);
+        if let Some(trivia) = trivia {
+            unparse_trailing(trivia, source);
+        }
+    }
+
+    // unparse_node appends the source text of the expression rooted at this
+    // node, without the node's own comments.
+    fn unparse_node(&self, source: &mut String);
+
+    // trivia returns the comments attached to this node, if it can have any.
+    fn trivia(&self) -> Option<&Trivia> {
+        None
+    }
+}
+
+// Part is a piece of the source text of a tree still to be appended.
+enum Part<'a> {
+    // Node appends a node and its comments.
+    Node(&'a Expr),
+    // Inner appends a node without its comments.
+    Inner(&'a Expr),
+    Binding(&'a Binding),
+    Field(&'a Field),
+    // Id appends an identifier that is not a node, such as a field name.
+    Id(&'a Id),
+    Trailing(&'a Trivia),
+    // Comments appends the inner comments of a node, before its closing
+    // delimiter.
+    Comments(&'a Trivia),
+    Separate,
+    Close(char),
+}
+
+impl Unparsable for Expr {
+    // unparse_node walks the tree with an explicit work stack rather than
+    // recursing, so that deep trees can be printed.
+    fn unparse_node(&self, source: &mut String) {
+        let mut parts: Vec<Part> = vec![Part::Inner(self)];
+        while let Some(part) = parts.pop() {
+            match part {
+                Part::Node(expr) => {
+                    if let Some(trivia) = expr.trivia() {
+                        unparse_leading(trivia, source);
+                        parts.push(Part::Trailing(trivia));
+                    }
+                    parts.push(Part::Inner(expr))
+                },
+                Part::Inner(Expr::Binary(expr)) => {
+                    source.push(OPEN_PAREN);
+                    expr.op.unparse(source);
+                    source.push(' ');
+                    parts.push(Part::Close(CLOSE_PAREN));
+                    parts.push(Part::Node(&expr.right));
+                    parts.push(Part::Separate);
+                    parts.push(Part::Node(&expr.left))
+                },
+                Part::Inner(Expr::With(expr)) => {
+                    source.push(OPEN_PAREN);
+                    source.push_str(WITH_OP);
+                    source.push(' ');
+                    parts.push(Part::Close(CLOSE_PAREN));
+                    parts.push(Part::Node(&expr.input));
+                    parts.push(Part::Separate);
+                    parts.push(Part::Binding(&expr.binding))
+                },
+                Part::Inner(Expr::If(expr)) => {
+                    source.push(OPEN_PAREN);
+                    source.push_str(IF_OP);
+                    source.push(' ');
+                    parts.push(Part::Close(CLOSE_PAREN));
+                    push_separated(&mut parts, &[&expr.condition, &expr.then, &expr.otherwise])
+                },
+                Part::Inner(Expr::Call(expr)) => {
+                    source.push(OPEN_PAREN);
+                    expr.prim.unparse(source);
+                    if !expr.args.is_empty() {
+                        source.push(' ');
+                    }
+                    parts.push(Part::Close(CLOSE_PAREN));
+                    parts.push(Part::Comments(&expr.meta.trivia));
+                    push_separated(&mut parts, &expr.args.iter().collect::<Vec<&Expr>>())
+                },
+                Part::Inner(Expr::Fun(expr)) => {
+                    unparse_param(expr, source);
+                    separate(source);
+                    parts.push(Part::Close(CLOSE_PAREN));
+                    parts.push(Part::Node(&expr.body))
+                },
+                Part::Inner(Expr::App(expr)) => {
+                    source.push(OPEN_PAREN);
+                    parts.push(Part::Close(CLOSE_PAREN));
+                    push_separated(&mut parts, &[&expr.fun, &expr.arg])
+                },
+                Part::Inner(Expr::Record(expr)) => {
+                    source.push(OPEN_PAREN);
+                    source.push_str(RECORD_OP);
+                    parts.push(Part::Close(CLOSE_PAREN));
+                    parts.push(Part::Comments(&expr.meta.trivia));
+                    for field in expr.fields.iter().rev() {
+                        parts.push(Part::Field(field));
+                        parts.push(Part::Separate);
+                    }
+                },
+                Part::Inner(Expr::Get(expr)) => {
+                    source.push(OPEN_PAREN);
+                    source.push_str(GET_OP);
+                    source.push(' ');
+                    parts.push(Part::Close(CLOSE_PAREN));
+                    parts.push(Part::Id(&expr.field));
+                    parts.push(Part::Separate);
+                    parts.push(Part::Node(&expr.record))
+                },
+                Part::Inner(expr) => match expr {
+                    Expr::Number(expr) => expr.unparse_node(source),
+                    Expr::Id(expr) => expr.unparse_node(source),
+                    Expr::Error(expr) => expr.unparse_node(source),
+                    Expr::Bool(expr) => expr.unparse_node(source),
+                    Expr::Str(expr) => expr.unparse_node(source),
+                    Expr::Empty(expr) => expr.unparse_node(source),
+                    _ => unreachable!("nodes with inputs are handled above"),
+                },
+                Part::Binding(binding) => {
+                    unparse_leading(&binding.meta.trivia, source);
+                    source.push(OPEN_PAREN);
+                    source.push(OPEN_BRACE);
+                    binding.identifier.unparse(source);
+                    unparse_annotation(&binding.annotation, source);
+                    parts.push(Part::Trailing(&binding.meta.trivia));
+                    parts.push(Part::Close(CLOSE_PAREN));
+                    parts.push(Part::Close(CLOSE_BRACE));
+                    parts.push(Part::Node(&binding.replace));
+                    parts.push(Part::Separate)
+                },
+                Part::Field(field) => {
+                    unparse_leading(&field.meta.trivia, source);
+                    source.push(OPEN_BRACE);
+                    field.name.unparse(source);
+                    parts.push(Part::Trailing(&field.meta.trivia));
+                    parts.push(Part::Close(CLOSE_BRACE));
+                    parts.push(Part::Node(&field.value));
+                    parts.push(Part::Separate)
+                },
+                Part::Id(id) => id.unparse(source),
+                Part::Trailing(trivia) => unparse_trailing(trivia, source),
+                Part::Comments(trivia) => unparse_inner(trivia, source),
+                Part::Separate => separate(source),
+                Part::Close(c) => source.push(c),
+            }
+        }
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        match self {
+            Expr::Number(expr) => expr.trivia(),
+            Expr::Binary(expr) => expr.trivia(),
+            Expr::With(expr) => expr.trivia(),
+            Expr::Id(expr) => expr.trivia(),
+            Expr::Error(expr) => expr.trivia(),
+            Expr::Bool(expr) => expr.trivia(),
+            Expr::If(expr) => expr.trivia(),
+            Expr::Call(expr) => expr.trivia(),
+            Expr::Fun(expr) => expr.trivia(),
+            Expr::App(expr) => expr.trivia(),
+            Expr::Str(expr) => expr.trivia(),
+            Expr::Empty(expr) => expr.trivia(),
+            Expr::Record(expr) => expr.trivia(),
+            Expr::Get(expr) => expr.trivia(),
+        }
+    }
+}
+
+// push_separated pushes the parts that append the given nodes separated by
+// spaces, so that they are appended in order.
+fn push_separated<'a>(parts: &mut Vec<Part<'a>>, nodes: &[&'a Expr]) {
+    for (i, node) in nodes.iter().enumerate().rev() {
+        parts.push(Part::Node(node));
+        if i > 0 {
+            parts.push(Part::Separate);
+        }
+    }
+}
+
+impl Unparsable for Number {
+    fn unparse_node(&self, source: &mut String) {
+        source.push_str(&self.val.to_string())
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Binary {
+    fn unparse_node(&self, source: &mut String) {
+        source.push(OPEN_PAREN);
+        self.op.unparse(source);
+        source.push(' ');
+        self.left.unparse(source);
+        separate(source);
+        self.right.unparse(source);
+        source.push(CLOSE_PAREN)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Operator {
+    fn unparse_node(&self, source: &mut String) {
+        source.push_str(match self {
+            Operator::Add => ADD_OP,
+            Operator::Sub => SUB_OP,
+            Operator::Mul => MUL_OP,
+            Operator::Div => DIV_OP,
+        })
+    }
+}
+
+impl Unparsable for With {
+    fn unparse_node(&self, source: &mut String) {
+        source.push(OPEN_PAREN);
+        source.push_str(WITH_OP);
+        source.push(' ');
+        self.binding.unparse(source);
+        separate(source);
+        self.input.unparse(source);
+        source.push(CLOSE_PAREN)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Binding {
+    fn unparse_node(&self, source: &mut String) {
+        source.push(OPEN_PAREN);
+        source.push(OPEN_BRACE);
+        self.identifier.unparse(source);
+        unparse_annotation(&self.annotation, source);
+        separate(source);
+        self.replace.unparse(source);
+        source.push(CLOSE_BRACE);
+        source.push(CLOSE_PAREN)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Id {
+    fn unparse_node(&self, source: &mut String) {
+        source.push_str(&self.val)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Define {
+    fn unparse_node(&self, source: &mut String) {
+        source.push(OPEN_PAREN);
+        source.push_str(DEFINE_OP);
+        source.push(' ');
+        self.name.unparse(source);
+        source.push(' ');
+        self.value.unparse(source);
+        source.push(CLOSE_PAREN)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Error {
+    // unparse_node appends the text of the datum that could not be parsed, so
+    // that the source of a tree with errors is unchanged.
+    fn unparse_node(&self, source: &mut String) {
+        source.push_str(&self.source)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Bool {
+    fn unparse_node(&self, source: &mut String) {
+        source.push_str(if self.val { TRUE } else { FALSE })
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for If {
+    fn unparse_node(&self, source: &mut String) {
+        source.push(OPEN_PAREN);
+        source.push_str(IF_OP);
+        source.push(' ');
+        self.condition.unparse(source);
+        separate(source);
+        self.then.unparse(source);
+        separate(source);
+        self.otherwise.unparse(source);
+        source.push(CLOSE_PAREN)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Call {
+    fn unparse_node(&self, source: &mut String) {
+        source.push(OPEN_PAREN);
+        self.prim.unparse(source);
+        for arg in &self.args {
+            separate(source);
+            arg.unparse(source);
+        }
+        unparse_inner(&self.meta.trivia, source);
+        source.push(CLOSE_PAREN)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Primitive {
+    fn unparse_node(&self, source: &mut String) {
+        source.push_str(match self {
+            Primitive::Less => LESS_OP,
+            Primitive::Equal => EQUAL_OP,
+            Primitive::Greater => GREATER_OP,
+            Primitive::StringAppend => STRING_APPEND_OP,
+            Primitive::StringLength => STRING_LENGTH_OP,
+            Primitive::Substring => SUBSTRING_OP,
+            Primitive::NumberToString => NUMBER_TO_STRING_OP,
+            Primitive::StringToNumber => STRING_TO_NUMBER_OP,
+            Primitive::Cons => CONS_OP,
+            Primitive::First => FIRST_OP,
+            Primitive::Rest => REST_OP,
+            Primitive::IsEmpty => IS_EMPTY_OP,
+            Primitive::List => LIST_OP,
+        })
+    }
+}
+
+impl Unparsable for Fun {
+    fn unparse_node(&self, source: &mut String) {
+        unparse_param(self, source);
+        separate(source);
+        self.body.unparse(source);
+        source.push(CLOSE_PAREN)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for App {
+    fn unparse_node(&self, source: &mut String) {
+        source.push(OPEN_PAREN);
+        self.fun.unparse(source);
+        separate(source);
+        self.arg.unparse(source);
+        source.push(CLOSE_PAREN)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Str {
+    fn unparse_node(&self, source: &mut String) {
+        source.push_str(&quote(&self.val))
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Empty {
+    fn unparse_node(&self, source: &mut String) {
+        source.push_str(EMPTY)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Record {
+    fn unparse_node(&self, source: &mut String) {
+        source.push(OPEN_PAREN);
+        source.push_str(RECORD_OP);
+        for field in &self.fields {
+            separate(source);
+            field.unparse(source);
+        }
+        unparse_inner(&self.meta.trivia, source);
+        source.push(CLOSE_PAREN)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Field {
+    fn unparse_node(&self, source: &mut String) {
+        source.push(OPEN_BRACE);
+        self.name.unparse(source);
+        separate(source);
+        self.value.unparse(source);
+        source.push(CLOSE_BRACE)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Get {
+    fn unparse_node(&self, source: &mut String) {
+        source.push(OPEN_PAREN);
+        source.push_str(GET_OP);
+        source.push(' ');
+        self.record.unparse(source);
+        separate(source);
+        self.field.unparse(source);
+        source.push(CLOSE_PAREN)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+impl Unparsable for Type {
+    fn unparse_node(&self, source: &mut String) {
+        unparse_type(self, &TypeMeta::default(), source)
+    }
+}
+
+impl Unparsable for Annotation {
+    fn unparse_node(&self, source: &mut String) {
+        unparse_type(&self.ty, &self.meta, source)
+    }
+
+    fn trivia(&self) -> Option<&Trivia> {
+        Some(&self.meta.trivia)
+    }
+}
+
+// unparse_type appends the given type, without its own comments but with the
+// comments that the given metadata attaches to its inputs.
+fn unparse_type(ty: &Type, meta: &TypeMeta, source: &mut String) {
+    match ty {
+        Type::Number => source.push_str(NUMBER_TYPE),
+        Type::Boolean => source.push_str(BOOLEAN_TYPE),
+        Type::String => source.push_str(STRING_TYPE),
+        Type::Fun(from, to) => {
+            source.push(OPEN_PAREN);
+            unparse_type_input(from, meta.input(0), source);
+            separate(source);
+            source.push_str(ARROW);
+            source.push(' ');
+            unparse_type_input(to, meta.input(1), source);
+            source.push(CLOSE_PAREN)
+        },
+        Type::List(item) => {
+            source.push(OPEN_PAREN);
+            source.push_str(LISTOF_TYPE);
+            source.push(' ');
+            unparse_type_input(item, meta.input(0), source);
+            source.push(CLOSE_PAREN)
+        },
+        Type::Record(fields) => {
+            source.push(OPEN_PAREN);
+            source.push_str(RECORD_OP);
+            for (i, (name, ty)) in fields.iter().enumerate() {
+                let field: &TypeMeta = meta.input(i);
+                separate(source);
+                unparse_leading(&field.trivia, source);
+                source.push(OPEN_BRACE);
+                unparse_leading(&field.input(0).trivia, source);
+                source.push_str(name);
+                unparse_trailing(&field.input(0).trivia, source);
+                separate(source);
+                source.push_str(COLON);
+                source.push(' ');
+                unparse_type_input(ty, field.input(1), source);
+                source.push(CLOSE_BRACE);
+                unparse_trailing(&field.trivia, source)
+            }
+            unparse_inner(&meta.trivia, source);
+            source.push(CLOSE_PAREN)
+        },
+    }
+}
+
+// unparse_type_input appends the given input of a type, surrounded by its
+// comments.
+fn unparse_type_input(ty: &Type, meta: &TypeMeta, source: &mut String) {
+    unparse_leading(&meta.trivia, source);
+    unparse_type(ty, meta, source);
+    unparse_trailing(&meta.trivia, source)
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut text: String = String::new();
+        self.unparse(&mut text);
+        write!(f, "{}", text)
+    }
+}
+
+// unparse_param appends the start of the given function, up to and including
+// its parameter.
+pub(crate) fn unparse_param(fun: &Fun, source: &mut String) {
+    source.push(OPEN_PAREN);
+    source.push_str(FUN_OP);
+    source.push(' ');
+    source.push(OPEN_PAREN);
+    if fun.annotation.is_some() {
+        source.push(OPEN_BRACE);
+    }
+    fun.param.unparse(source);
+    unparse_annotation(&fun.annotation, source);
+    if fun.annotation.is_some() {
+        source.push(CLOSE_BRACE);
+    }
+    source.push(CLOSE_PAREN)
+}
+
+// unparse_annotation appends the given type annotation, if there is one.
+fn unparse_annotation(annotation: &Option<Annotation>, source: &mut String) {
+    if let Some(ty) = annotation {
+        separate(source);
+        source.push_str(COLON);
+        source.push(' ');
+        ty.unparse(source);
+    }
+}
+
+// unparse_leading appends the comments that lead a node. A line comment is
+// ended by a newline, and any other comment by a space.
+fn unparse_leading(trivia: &Trivia, source: &mut String) {
+    for comment in &trivia.leading {
+        source.push_str(&comment.text);
+        source.push(if comment.kind == CommentKind::Line { '\n' } else { ' ' });
+    }
+}
+
+// unparse_trailing appends the comments that trail a node.
+fn unparse_trailing(trivia: &Trivia, source: &mut String) {
+    unparse_after(&trivia.trailing, source)
+}
+
+// unparse_inner appends the comments inside a node that has no inputs to
+// attach them to, before its closing delimiter.
+fn unparse_inner(trivia: &Trivia, source: &mut String) {
+    unparse_after(&trivia.inner, source)
+}
+
+// unparse_after appends comments that follow a token. A line comment ends the
+// line, so whatever follows it starts on the next one.
+fn unparse_after(comments: &[Comment], source: &mut String) {
+    for comment in comments {
+        separate(source);
+        source.push_str(&comment.text);
+        if comment.kind == CommentKind::Line {
+            source.push('\n');
+        }
+    }
+}
+
+// separate appends the space between two tokens, unless a line comment has
+// already ended the line.
+fn separate(source: &mut String) {
+    if !source.ends_with('\n') {
+        source.push(' ')
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{Expr, Number, Id, Meta};
+use crate::ast::{hole, Bool, Fun, Str, Empty, Call, Primitive, Record, Field};
+use crate::reader::quote;
+use crate::unparse::to_source;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::rc::Rc;
+
+// Value is the result of evaluating an expression. Under substitution a value
+// is an expression that cannot be reduced any further, so every value can be
+// turned back into the expression it came from.
+//
+// Lists and records are built from shared cells, so copying a value takes
+// constant time, and a value can be nested far deeper than the call stack.
+// Value implements Drop, PartialEq and to_expr with explicit work stacks for
+// the same reason.
+#[derive(Clone)]
+pub enum Value {
+    Number(i32),
+    Bool(bool),
+    Str(String),
+    // Empty is the empty list.
+    Empty,
+    // Cons is a non-empty list: a cell holding its first element and the list
+    // of the rest. Cells are shared rather than copied, so that cons, first
+    // and rest take constant time.
+    Cons(Rc<(Value, Value)>),
+    // Record holds the name and value of each field of a record, in the order
+    // they were written. The fields are shared, like the cells of a list.
+    Record(Rc<Vec<(String, Value)>>),
+    // Fun is a function, whose body has had every identifier bound outside it
+    // substituted.
+    Fun(Box<Fun>),
+}
+
+impl Value {
+    // to_expr returns the expression that this value is substituted as.
+    pub(crate) fn to_expr(&self) -> Expr {
+        enum Task<'v> {
+            Visit(&'v Value),
+            // Build pops the given number of expressions, which are the inputs
+            // of the node in order, and fills them into the node.
+            Build(Expr, usize),
+        }
+        let mut tasks: Vec<Task> = vec![Task::Visit(self)];
+        let mut exprs: Vec<Expr> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(value) => match value {
+                    Value::Number(val) => exprs.push(Number{ val: *val, meta: Meta::default() }.into()),
+                    Value::Bool(val) => exprs.push(Bool{ val: *val, meta: Meta::default() }.into()),
+                    Value::Str(val) => exprs.push(Str{ val: val.clone(), meta: Meta::default() }.into()),
+                    Value::Empty => exprs.push(Empty{ meta: Meta::default() }.into()),
+                    Value::Cons(_) => {
+                        let items: Vec<&Value> = value.items();
+                        let call: Expr = Call{ prim: Primitive::List, args: items.iter().map(|_| hole()).collect(), meta: Meta::default() }.into();
+                        tasks.push(Task::Build(call, items.len()));
+                        tasks.extend(items.into_iter().rev().map(Task::Visit))
+                    },
+                    Value::Record(fields) => {
+                        let record: Expr = Record{ fields: fields.iter().map(|(name, _)| Field{
+                            name: Box::new(Id{ val: name.clone(), meta: Meta::default() }),
+                            value: hole(),
+                            meta: Meta::default(),
+                        }).collect(), meta: Meta::default() }.into();
+                        tasks.push(Task::Build(record, fields.len()));
+                        tasks.extend(fields.iter().rev().map(|(_, value)| Task::Visit(value)))
+                    },
+                    Value::Fun(fun) => exprs.push(Expr::Fun(fun.clone())),
+                },
+                Task::Build(mut expr, count) => {
+                    let start: usize = exprs.len() - count;
+                    expr.fill(exprs.drain(start..));
+                    exprs.push(expr)
+                },
+            }
+        }
+        exprs.pop().unwrap()
+    }
+
+    // items returns the elements of this value, first to last, if it is a
+    // list, and nothing otherwise.
+    pub(crate) fn items(&self) -> Vec<&Value> {
+        let mut items: Vec<&Value> = Vec::new();
+        let mut list: &Value = self;
+        while let Value::Cons(cell) = list {
+            items.push(&cell.0);
+            list = &cell.1;
+        }
+        items
+    }
+
+    // kind describes the type of this value, for error messages.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "a number",
+            Value::Bool(_) => "a boolean",
+            Value::Str(_) => "a string",
+            Value::Empty => "an empty list",
+            Value::Cons(_) => "a list",
+            Value::Record(_) => "a record",
+            Value::Fun(_) => "a function",
+        }
+    }
+}
+
+impl PartialEq for Value {
+    // eq compares the values with a stack of the pairs of parts still to
+    // compare. Shared cells are equal without looking inside them.
+    fn eq(&self, other: &Value) -> bool {
+        let mut pairs: Vec<(&Value, &Value)> = vec![(self, other)];
+        while let Some(pair) = pairs.pop() {
+            match pair {
+                (Value::Number(a), Value::Number(b)) if a == b => {},
+                (Value::Bool(a), Value::Bool(b)) if a == b => {},
+                (Value::Str(a), Value::Str(b)) if a == b => {},
+                (Value::Empty, Value::Empty) => {},
+                (Value::Cons(a), Value::Cons(b)) => {
+                    if !Rc::ptr_eq(a, b) {
+                        pairs.push((&a.1, &b.1));
+                        pairs.push((&a.0, &b.0))
+                    }
+                },
+                (Value::Record(a), Value::Record(b)) if Rc::ptr_eq(a, b) => {},
+                (Value::Record(a), Value::Record(b)) if a.len() == b.len() => {
+                    for ((a_name, a), (b_name, b)) in a.iter().zip(b.iter()).rev() {
+                        if a_name != b_name {
+                            return false
+                        }
+                        pairs.push((a, b))
+                    }
+                },
+                (Value::Fun(a), Value::Fun(b)) if a == b => {},
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Eq for Value {}
+
+impl Drop for Value {
+    // drop detaches the values inside this one that nothing else shares onto
+    // a stack, so that every value is dropped with nothing but leaves inside
+    // it.
+    fn drop(&mut self) {
+        let mut stack: Vec<Value> = Vec::new();
+        detach_parts(self, &mut stack);
+        while let Some(mut value) = stack.pop() {
+            detach_parts(&mut value, &mut stack);
+        }
+    }
+}
+
+// detach_parts moves the parts of the given value that it alone owns onto
+// the given stack, leaving empty lists in their place.
+fn detach_parts(value: &mut Value, stack: &mut Vec<Value>) {
+    match value {
+        Value::Cons(cell) => {
+            if let Some((first, rest)) = Rc::get_mut(cell) {
+                stack.push(std::mem::replace(first, Value::Empty));
+                stack.push(std::mem::replace(rest, Value::Empty))
+            }
+        },
+        Value::Record(fields) => {
+            if let Some(fields) = Rc::get_mut(fields) {
+                for (_, value) in fields.iter_mut() {
+                    stack.push(std::mem::replace(value, Value::Empty))
+                }
+            }
+        },
+        _ => {},
+    }
+}
+
+impl Display for Value {
+    // fmt writes a number, boolean or string as a literal and a list, record
+    // or function as its source text.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(val) => write!(f, "{}", val),
+            Value::Bool(val) => write!(f, "{}", val),
+            Value::Str(val) => write!(f, "{}", quote(val)),
+            Value::Empty | Value::Cons(_) | Value::Record(_) | Value::Fun(_) => write!(f, "{}", to_source(&self.to_expr())),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use crate::Operator;
+use crate::bytecode::{Chunk, Instruction};
+use crate::calc::unbound_identifier;
+
+// run executes the given chunk on a stack virtual machine and returns the
+// value left on top of the operand stack.
+pub fn run(chunk: &Chunk) -> Result<i32, String> {
+    let mut stack: Vec<i32> = Vec::new();
+    let mut locals: Vec<i32> = vec![0; chunk.locals.len()];
+
+    for instruction in &chunk.code {
+        match *instruction {
+            Instruction::Const(val) => stack.push(val),
+            Instruction::Load(slot) => stack.push(locals[slot]),
+            Instruction::Bind(slot) => locals[slot] = pop(&mut stack)?,
+            Instruction::Add => apply(&mut stack, Operator::Add)?,
+            Instruction::Sub => apply(&mut stack, Operator::Sub)?,
+            Instruction::Mul => apply(&mut stack, Operator::Mul)?,
+            Instruction::Div => apply(&mut stack, Operator::Div)?,
+            Instruction::Unbound(name) => return Err(unbound_identifier(&chunk.names[name])),
+            Instruction::Fail(error) => return Err(chunk.errors[error].clone()),
+        }
+    }
+
+    let result: i32 = pop(&mut stack)?;
+    if !stack.is_empty() {
+        return Err("malformed bytecode: values left on the stack".to_string())
+    }
+    Ok(result)
+}
+
+// apply pops two operands and pushes the result of the given operator.
+fn apply(stack: &mut Vec<i32>, op: Operator) -> Result<(), String> {
+    let right: i32 = pop(stack)?;
+    let left: i32 = pop(stack)?;
+    stack.push(op.apply(left, right)?);
+    Ok(())
+}
+
+// pop removes the top of the operand stack.
+fn pop(stack: &mut Vec<i32>) -> Result<i32, String> {
+    stack.pop().ok_or_else(|| "malformed bytecode: operand stack underflow".to_string())
+}